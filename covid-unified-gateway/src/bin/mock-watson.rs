@@ -0,0 +1,25 @@
+//! Serve local mock of Watson Assistant v2 and Watson Language Translator v3.
+//!
+//! Usage: `mock-watson [CONFIG_JSON] [--port PORT]`
+//!
+//! Point both `WA_ENDPOINT` and `WLT_ENDPOINT` to the printed url to run the gateway end to end.
+//! See [MockConfig](../covid_unified_gateway/mock_server/struct.MockConfig.html) for config format.
+
+use covid_unified_gateway::mock_server::{MockConfig, MockServer};
+use std::env;
+
+fn main() {
+    let mut config = MockConfig::default();
+    let mut port = 0u16;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--port" {
+            port = args.next().and_then(|p| p.parse().ok()).expect("--port shall be followed by port number");
+        } else {
+            config = MockConfig::load(&arg).unwrap_or_else(|e| panic!("{}", e));
+        }
+    }
+    let server = MockServer::bind(&format!("127.0.0.1:{}", port), config).expect("Fail to start mock server");
+    println!("Mock Watson is listening on {}", server.url());
+    server.join();
+}
@@ -0,0 +1,734 @@
+//! Gateway configuration.
+//!
+//! [GatewayConfig](struct.GatewayConfig.html) is loaded once by [load](struct.GatewayConfig.html#method.load)
+//! then passed to [wa](../wa/index.html) and [wlt](../wlt/index.html).
+//! Settings come from, in order of precedence,
+//! 1. environment variable
+//! 1. `.env` file
+//! 1. optional TOML file at `GATEWAY_CONFIG`, or `gateway.toml` in current directory if it exists.
+//!
+//! Every setting has environment variable name, e.g. `WA_APIKEY`. In TOML, the part before the first `_`
+//! of `WA_` and `WLT_` settings is the table, and the name is lowercase:
+//! ```toml
+//! turn_timeout = 14000
+//! log_level = "info"
+//!
+//! [wa]
+//! apikey = "..."
+//! endpoint = "https://api.us-south.assistant.watson.cloud.ibm.com"
+//! version = "2020-04-01"
+//! id = "..."
+//! retry = 1
+//!
+//! [wlt]
+//! apikey = "..."
+//! endpoint = "https://api.us-south.language-translator.watson.cloud.ibm.com"
+//! version = "2018-05-01"
+//!
+//! [models]
+//! th_en = "d589d1c7-aaaa-aaaa-aaaa-cec1e4caaaaa"
+//! th_zh = { base = "th-zh", pivot = "en" }
+//!
+//! [models.output]
+//! en_th = { custom = "a4c5e3f1-bbbb-bbbb-bbbb-0d9e2cbbbbbb", base = "en-th" }
+//! ```
+//! `WLT_MODELS`, or `models` of `[wlt]` table, optionally list language pairs that WLT has model for, e.g. `th-en,en-th`.
+//! Pair that isn't listed is translated through pivot language, `en` by default or `WLT_PIVOT`. Without the list,
+//! pairs are listed from WLT when a direct model is missing.
+//!
+//! Translations are cached in memory and, if `WLT_CACHE` is set, in that file between invocations.
+//! `WLT_CACHE_WARM` is optional file to pre-warm the cache with and `WLT_CACHE_SIZE` the number of translations kept in memory.
+//! `WLT_CACHE_FILE_SIZE` is the number of translations kept in file. Only translations of WA response are cached.
+//!
+//! Batch of text is split into chunks that WLT accept, at most `WLT_CHUNK_TEXTS` texts and `WLT_CHUNK_BYTES` bytes of
+//! JSON encoded text per request.
+//!
+//! `WLT_GLOSSARY` optionally list glossary CSV or TMX files, e.g. `../wlt-dataset/th/glossary.csv`.
+//!
+//! Each language pair is routed to [ModelRoute](struct.ModelRoute.html). Route of both directions is named
+//! `{source}_{target}` language codes, e.g. `th_en`, and route of one direction is prefixed with `input_` or `output_`.
+//! In environment, route is prefixed with `WLT_MODEL_` so unrelated variable never become a route, i.e.
+//! `WLT_MODEL_th_en` is the custom model, `WLT_MODEL_th_en_base` the base model and `WLT_MODEL_th_en_pivot` the pivot language.
+//! Only variables prefixed with `WA_` or `WLT_`, and the other settings named here, are read from environment.
+//! Old unprefixed custom model, e.g. `th_en`, is reported as a problem rather than silently ignored.
+//!
+//! URLs, numbers and language codes are validated. Every problem is listed in one
+//! `ErrKind::Config` error instead of stopping at the first one.
+
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use super::error::GatewayErr;
+use super::logger::Level;
+use super::utils::{Authenticator, RetryPolicy, Timeout, Upstream, DEFAULT_IAM_URL};
+
+/// TOML file that is read if `GATEWAY_CONFIG` isn't set.
+pub const DEFAULT_CONFIG_FILE: &str = "gateway.toml";
+
+/// Default time budget of one turn in millisecond.
+/// Cloud Functions action is deployed with 15 seconds timeout so leave some room to print result.
+pub const DEFAULT_TURN_TIMEOUT: u64 = 14000;
+
+/// Upper bound of `WA_RETRY` and `WLT_RETRY`. More retry than this can't fit in a turn anyway.
+const MAX_RETRY: usize = 10;
+
+/// Settings shared by WA and WLT.
+#[derive(Clone, Debug)]
+pub struct ServiceConfig {
+    /// Base url without trailing `/`
+    pub endpoint: String,
+    pub version: String,
+    pub api_key: String,
+    pub timeout: Timeout,
+    pub retry: RetryPolicy
+}
+
+impl ServiceConfig {
+    /// Service with default timeout and retry policy.
+    pub fn new<S: Into<String>>(endpoint: S, version: S, api_key: S) -> ServiceConfig {
+        ServiceConfig {
+            endpoint: endpoint.into().trim_end_matches('/').to_owned(),
+            version: version.into(),
+            api_key: api_key.into(),
+            timeout: Timeout::default(),
+            retry: RetryPolicy::default()
+        }
+    }
+
+    /// Upstream of this service with given authenticator.
+    pub fn upstream(&self, auth: Authenticator) -> Upstream {
+        Upstream::new(auth).timeout(self.timeout).retry(self.retry)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct WaConfig {
+    pub service: ServiceConfig,
+    pub assistant_id: String
+}
+
+/// Direction of translation. Input is user message translated into WA language and
+/// output is WA response translated back into user language.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Input,
+    Output
+}
+
+/// Models used to translate one language pair.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ModelRoute {
+    /// Model that is tried first, if any
+    pub custom: Option<String>,
+    /// Model used when there's no custom model or WLT cannot use it
+    pub base: String,
+    /// Language to translate through when there's no model for the pair
+    pub pivot: Option<String>
+}
+
+impl ModelRoute {
+    /// Route with only base model `{source}-{target}`.
+    pub fn base(source: &str, target: &str) -> ModelRoute {
+        ModelRoute {
+            custom: None,
+            base: format!("{}-{}", source, target),
+            pivot: None
+        }
+    }
+
+    /// Models to try, in order.
+    pub fn models(&self) -> Vec<&str> {
+        let mut models: Vec<&str> = self.custom.iter().map(String::as_str).collect();
+        if !models.contains(&self.base.as_str()) {
+            models.push(&self.base);
+        }
+        models
+    }
+}
+
+/// Pivot language used when a route doesn't set one.
+pub const DEFAULT_PIVOT: &str = "en";
+
+/// Default number of translations kept in memory.
+pub const DEFAULT_CACHE_SIZE: usize = 1000;
+pub const DEFAULT_CACHE_FILE_SIZE: usize = 10000;
+
+/// Default maximum number of texts in one WLT request.
+pub const DEFAULT_CHUNK_TEXTS: usize = 50;
+
+/// Default maximum bytes of JSON encoded texts in one WLT request. WLT reject request body larger than 50 KB.
+pub const DEFAULT_CHUNK_BYTES: usize = 48 * 1024;
+
+/// How large a batch of text WLT accept in one request. Larger batch is split into chunks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChunkConfig {
+    /// Maximum number of texts
+    pub max_texts: usize,
+    /// Maximum bytes of JSON encoded texts
+    pub max_bytes: usize
+}
+
+impl Default for ChunkConfig {
+    fn default() -> ChunkConfig {
+        ChunkConfig {
+            max_texts: DEFAULT_CHUNK_TEXTS,
+            max_bytes: DEFAULT_CHUNK_BYTES
+        }
+    }
+}
+
+/// Where translations are cached. See [TranslationCache](../wlt/cache/struct.TranslationCache.html).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// File that keep translations between invocations. If it is `None`, translations are kept only in memory.
+    pub path: Option<PathBuf>,
+    /// File of translations to pre-warm the cache with
+    pub warm: Option<PathBuf>,
+    /// Number of translations kept in memory
+    pub capacity: usize,
+    /// Number of translations kept in file
+    pub file_capacity: usize
+}
+
+impl Default for CacheConfig {
+    fn default() -> CacheConfig {
+        CacheConfig {
+            path: None,
+            warm: None,
+            capacity: DEFAULT_CACHE_SIZE,
+            file_capacity: DEFAULT_CACHE_FILE_SIZE
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct WltConfig {
+    pub service: ServiceConfig,
+    /// `{source}-{target}` pairs that WLT has model for. If it is `None`, it is listed from WLT when needed.
+    pub pairs: Option<HashSet<String>>,
+    /// Language to translate through when there's no model for the pair
+    pub pivot: String,
+    /// Routes of input direction keyed by `{source}-{target}`
+    pub input_routes: HashMap<String, ModelRoute>,
+    /// Routes of output direction keyed by `{source}-{target}`
+    pub output_routes: HashMap<String, ModelRoute>,
+    pub cache: CacheConfig,
+    pub chunk: ChunkConfig,
+    /// Glossary CSV or TMX files
+    pub glossary: Vec<PathBuf>
+}
+
+impl WltConfig {
+    /// Config without any route so every pair use its base model.
+    pub fn new(service: ServiceConfig) -> WltConfig {
+        WltConfig {
+            service,
+            pairs: None,
+            pivot: DEFAULT_PIVOT.to_owned(),
+            input_routes: HashMap::new(),
+            output_routes: HashMap::new(),
+            cache: CacheConfig::default(),
+            chunk: ChunkConfig::default(),
+            glossary: Vec::new()
+        }
+    }
+
+    /// Route to translate from `source` to `target` in given direction. Pair without configured route
+    /// use base model `{source}-{target}`.
+    pub fn route(&self, direction: Direction, source: &str, target: &str) -> ModelRoute {
+        let routes = match direction {
+            Direction::Input => &self.input_routes,
+            Direction::Output => &self.output_routes
+        };
+        routes.get(&format!("{}-{}", source, target)).cloned().unwrap_or_else(|| ModelRoute::base(source, target))
+    }
+
+    /// Pivot language of the route, or the default one if route doesn't set it.
+    pub fn pivot_of<'a>(&'a self, route: &'a ModelRoute) -> &'a str {
+        route.pivot.as_deref().unwrap_or(&self.pivot)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuthType {
+    /// API key as basic auth
+    Basic,
+    /// Bearer token from IAM
+    Iam
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CassetteMode {
+    Record,
+    Replay
+}
+
+#[derive(Clone, Debug)]
+pub struct GatewayConfig {
+    pub wa: WaConfig,
+    pub wlt: WltConfig,
+    pub auth_type: AuthType,
+    pub iam_url: String,
+    /// Time budget of a turn
+    pub turn_timeout: Duration,
+    pub log_level: Level,
+    /// Record or replay HTTP interactions to/from cassette file
+    pub cassette: Option<(CassetteMode, PathBuf)>
+}
+
+/// Language code such as `th`, `en` or `zh-TW`.
+pub fn is_language_code(code: &str) -> bool {
+    let mut parts = code.splitn(2, '-');
+    let lang = parts.next().unwrap_or_default();
+    let lang_ok = (2..=3).contains(&lang.len()) && lang.chars().all(|c| c.is_ascii_lowercase());
+    let region_ok = parts.next().is_none_or(|r| (2..=4).contains(&r.len()) && r.chars().all(|c| c.is_ascii_alphabetic()));
+    lang_ok && region_ok
+}
+
+/// Split `{source}-{target}` pair. Language code may have region, e.g. `zh-TW-en`.
+pub fn parse_pair(pair: &str) -> Option<(&str, &str)> {
+    pair.match_indices('-')
+        .map(|(i, _)| (&pair[..i], &pair[i + 1..]))
+        .find(|(source, target)| is_language_code(source) && is_language_code(target))
+}
+
+/// Settings by environment variable name. Custom model entries are kept in their `{source}_{target}` form.
+type Settings = HashMap<String, String>;
+
+/// Prefix of route setting in environment, e.g. `WLT_MODEL_th_en`.
+pub const ROUTE_PREFIX: &str = "WLT_MODEL_";
+
+/// Settings other than those prefixed with `WA_` or `WLT_`.
+const SETTING_NAMES: [&str; 6] = ["AUTH_TYPE", "HTTP_CASSETTE", "HTTP_CASSETTE_MODE", "IAM_URL", "LOG_LEVEL", "TURN_TIMEOUT"];
+
+/// Whether environment variable `name` is a gateway setting. Route of old name is kept too so it can be reported.
+fn is_setting(name: &str) -> bool {
+    name.starts_with("WA_") || name.starts_with("WLT_") || SETTING_NAMES.contains(&name) || legacy_route(name).is_some()
+}
+
+/// New name of custom model setting named `{source}_{target}` or `{source}-{target}`, as it was before
+/// routes were prefixed, e.g. `WLT_MODEL_th_en` for `th_en`.
+fn legacy_route(name: &str) -> Option<String> {
+    let pair = |separator: char| name.match_indices(separator)
+                                     .map(|(i, _)| (&name[..i], &name[i + 1..]))
+                                     .find(|(source, target)| is_language_code(source) && is_language_code(target));
+    pair('_').or_else(|| pair('-')).map(|(source, target)| format!("{}{}_{}", ROUTE_PREFIX, source, target))
+}
+
+/// Gateway settings in process environment. Other variables are ignored so they never become a setting by accident.
+/// Value that isn't UTF-8 is converted lossily rather than failing.
+fn env_settings() -> Settings {
+    env::vars_os().filter_map(|(name, value)| {
+        let name = name.into_string().ok().filter(|n| is_setting(n))?;
+        Some((name, value.to_string_lossy().into_owned()))
+    }).collect()
+}
+
+/// Read settings from TOML file. Tables other than `models` are flattened into environment variable names.
+/// Invalid entry of `models` table is added to `problems`.
+fn read_toml(path: &Path, problems: &mut Vec<String>) -> Settings {
+    let mut settings = Settings::new();
+    let table = match std::fs::read_to_string(path) {
+        Ok(content) => match toml::from_str::<toml::Table>(&content) {
+            Ok(table) => table,
+            Err(e) => {
+                problems.push(format!("Cannot parse {}: {}", path.display(), e));
+                return settings;
+            }
+        },
+        Err(e) => {
+            problems.push(format!("Cannot read {}: {}", path.display(), e));
+            return settings;
+        }
+    };
+    for (key, value) in table {
+        match value {
+            toml::Value::Table(t) if key == "models" => read_routes("models", t, &mut settings, problems),
+            toml::Value::Table(t) => {
+                for (name, v) in t {
+                    settings.insert(format!("{}_{}", key, name).to_uppercase(), scalar(&v));
+                }
+            },
+            v => {
+                settings.insert(key.to_uppercase(), scalar(&v));
+            }
+        }
+    }
+    settings
+}
+
+/// Flatten `[models]` table into route settings, e.g. `models.input.th_en.base` into `WLT_MODEL_input_th_en_base`.
+/// `custom` of inline route table is the route itself, i.e. `th_en = { custom = "..." }` is `th_en = "..."`.
+fn read_routes(path: &str, table: toml::Table, settings: &mut Settings, problems: &mut Vec<String>) {
+    for (key, value) in table {
+        let path = format!("{}.{}", path, key);
+        match value {
+            toml::Value::Table(t) => read_routes(&path, t, settings, problems),
+            v => {
+                let name = path.trim_start_matches("models.").replace('.', "_");
+                let name = name.strip_suffix("_custom").map(str::to_owned).unwrap_or(name);
+                if parse_route_setting(&name).is_some() {
+                    settings.insert(format!("{}{}", ROUTE_PREFIX, name), scalar(&v));
+                } else {
+                    problems.push(format!("{} shall be {{source}}_{{target}} language codes", path));
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RouteField {
+    Custom,
+    Base,
+    Pivot
+}
+
+/// Parse route setting name `[input_|output_]{source}_{target}[_base|_pivot]`, e.g. `th_en` or `output_en_th_base`.
+/// It return direction, if the setting is for one direction only, `{source}-{target}` pair and the field being set.
+fn parse_route_setting(name: &str) -> Option<(Option<Direction>, String, RouteField)> {
+    let mut parts: Vec<&str> = name.split('_').collect();
+    let direction = match parts.first() {
+        Some(&"input") => Some(Direction::Input),
+        Some(&"output") => Some(Direction::Output),
+        _ => None
+    };
+    if direction.is_some() {
+        parts.remove(0);
+    }
+    let field = match parts.last() {
+        Some(&"base") => RouteField::Base,
+        Some(&"pivot") => RouteField::Pivot,
+        _ => RouteField::Custom
+    };
+    if field != RouteField::Custom {
+        parts.pop();
+    }
+    match parts.as_slice() {
+        [source, target] if is_language_code(source) && is_language_code(target) => Some((direction, format!("{}-{}", source, target), field)),
+        _ => None
+    }
+}
+
+/// Route fields set for one pair, either for both directions or for one of them.
+#[derive(Clone, Default)]
+struct RouteSettings {
+    custom: Option<String>,
+    base: Option<String>,
+    pivot: Option<String>
+}
+
+fn scalar(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(s) => s.to_owned(),
+        toml::Value::Array(a) => a.iter().map(scalar).collect::<Vec<String>>().join(","),
+        v => v.to_string()
+    }
+}
+
+/// Read each setting and record every problem found.
+struct Validator<'a> {
+    settings: &'a Settings,
+    problems: Vec<String>
+}
+
+impl<'a> Validator<'a> {
+    fn get(&self, name: &str) -> Option<&'a str> {
+        self.settings.get(name).map(|v| v.trim()).filter(|v| !v.is_empty())
+    }
+
+    fn required(&mut self, name: &str) -> String {
+        match self.get(name) {
+            Some(v) => v.to_owned(),
+            None => {
+                self.problems.push(format!("{} is missing", name));
+                String::new()
+            }
+        }
+    }
+
+    fn url(&mut self, name: &str) -> String {
+        let value = self.required(name);
+        if value.is_empty() {
+            return value;
+        }
+        match url::Url::parse(&value) {
+            Ok(u) if (u.scheme() == "http" || u.scheme() == "https") && u.host().is_some() => value.trim_end_matches('/').to_owned(),
+            Ok(_) => {
+                self.problems.push(format!("{} shall be http or https URL, found {}", name, value));
+                value
+            },
+            Err(e) => {
+                self.problems.push(format!("{} is not a valid URL ({}), found {}", name, e, value));
+                value
+            }
+        }
+    }
+
+    fn parse<T: FromStr>(&mut self, name: &str, default: T, what: &str) -> T {
+        match self.get(name) {
+            Some(v) => match v.parse() {
+                Ok(v) => v,
+                Err(_) => {
+                    self.problems.push(format!("{} shall be {}, found {}", name, what, v));
+                    default
+                }
+            },
+            None => default
+        }
+    }
+
+    fn positive(&mut self, name: &str, default: usize) -> usize {
+        let value = self.parse(name, default, "positive number");
+        if value == 0 {
+            self.problems.push(format!("{} shall be greater than 0", name));
+            return default;
+        }
+        value
+    }
+
+    fn millis(&mut self, name: &str, default: Duration) -> Duration {
+        let ms = self.parse(name, default.as_millis() as u64, "positive number of millisecond");
+        if ms == 0 {
+            self.problems.push(format!("{} shall be greater than 0", name));
+            return default;
+        }
+        Duration::from_millis(ms)
+    }
+
+    fn service(&mut self, prefix: &str) -> ServiceConfig {
+        let default_timeout = Timeout::default();
+        let default_retry = RetryPolicy::default();
+        let retry_name = format!("{}_RETRY", prefix);
+        let max_retry = self.parse(&retry_name, default_retry.max_retry, "number of retry");
+        if max_retry > MAX_RETRY {
+            self.problems.push(format!("{} shall be at most {}, found {}", retry_name, MAX_RETRY, max_retry));
+        }
+        ServiceConfig {
+            endpoint: self.url(&format!("{}_ENDPOINT", prefix)),
+            version: self.required(&format!("{}_VERSION", prefix)),
+            api_key: self.required(&format!("{}_APIKEY", prefix)),
+            timeout: Timeout {
+                connect: self.millis(&format!("{}_CONNECT_TIMEOUT", prefix), default_timeout.connect),
+                total: self.millis(&format!("{}_TIMEOUT", prefix), default_timeout.total)
+            },
+            retry: RetryPolicy {
+                max_retry: max_retry.min(MAX_RETRY),
+                base_delay: self.millis(&format!("{}_RETRY_DELAY", prefix), default_retry.base_delay),
+                ..default_retry
+            }
+        }
+    }
+
+    fn language(&mut self, name: &str) -> Option<String> {
+        let code = self.get(name)?;
+        if !is_language_code(code) {
+            self.problems.push(format!("{} shall be language code, found {}", name, code));
+            return None;
+        }
+        Some(code.to_owned())
+    }
+
+    /// Comma separated `{source}-{target}` pairs, e.g. `th-en,en-th`.
+    fn pairs(&mut self, name: &str) -> Option<HashSet<String>> {
+        let value = self.get(name)?;
+        let mut pairs = HashSet::new();
+        for pair in value.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            if parse_pair(pair).is_some() {
+                pairs.insert(pair.to_owned());
+            } else {
+                self.problems.push(format!("{} shall be comma separated {{source}}-{{target}} language codes, found {}", name, pair));
+            }
+        }
+        Some(pairs)
+    }
+
+    /// Comma separated paths of existing files.
+    fn files(&mut self, name: &str) -> Vec<PathBuf> {
+        let value = match self.get(name) {
+            Some(v) => v,
+            None => return Vec::new()
+        };
+        let mut files = Vec::new();
+        for path in value.split(',').map(str::trim).filter(|p| !p.is_empty()).map(PathBuf::from) {
+            if path.is_file() {
+                files.push(path);
+            } else {
+                self.problems.push(format!("{} shall be comma separated files, found {}", name, path.display()));
+            }
+        }
+        files
+    }
+
+    fn cache(&mut self) -> CacheConfig {
+        let warm = self.get("WLT_CACHE_WARM").map(PathBuf::from);
+        if let Some(path) = warm.as_ref().filter(|p| !p.is_file()) {
+            self.problems.push(format!("WLT_CACHE_WARM shall be a file, found {}", path.display()));
+        }
+        CacheConfig {
+            path: self.get("WLT_CACHE").map(PathBuf::from),
+            warm,
+            capacity: self.parse("WLT_CACHE_SIZE", DEFAULT_CACHE_SIZE, "number of translation"),
+            file_capacity: self.parse("WLT_CACHE_FILE_SIZE", DEFAULT_CACHE_FILE_SIZE, "number of translation")
+        }
+    }
+
+    fn chunk(&mut self) -> ChunkConfig {
+        ChunkConfig {
+            max_texts: self.positive("WLT_CHUNK_TEXTS", DEFAULT_CHUNK_TEXTS),
+            max_bytes: self.positive("WLT_CHUNK_BYTES", DEFAULT_CHUNK_BYTES)
+        }
+    }
+
+    /// Input and output routes. Setting without direction apply to both directions unless
+    /// the direction has its own value.
+    fn routes(&mut self) -> (HashMap<String, ModelRoute>, HashMap<String, ModelRoute>) {
+        let mut entries: HashMap<(Option<Direction>, String), RouteSettings> = HashMap::new();
+        for (name, value) in self.settings {
+            let value = value.trim();
+            if let Some(renamed) = legacy_route(name) {
+                // ignoring it would silently drop custom model of existing deployment
+                if !value.is_empty() {
+                    self.problems.push(format!("{} is no longer read, rename it to {}", name, renamed));
+                }
+                continue;
+            }
+            let route = match name.strip_prefix(ROUTE_PREFIX) {
+                Some(route) if !value.is_empty() => route,
+                _ => continue
+            };
+            let (direction, pair, field) = match parse_route_setting(route) {
+                Some(setting) => setting,
+                None => {
+                    self.problems.push(format!("{} shall be {}[input_|output_]{{source}}_{{target}}[_base|_pivot]", name, ROUTE_PREFIX));
+                    continue;
+                }
+            };
+            if field == RouteField::Pivot && !is_language_code(value) {
+                self.problems.push(format!("{} shall be language code, found {}", name, value));
+                continue;
+            }
+            let entry = entries.entry((direction, pair)).or_default();
+            let value = Some(value.to_owned());
+            match field {
+                RouteField::Custom => entry.custom = value,
+                RouteField::Base => entry.base = value,
+                RouteField::Pivot => entry.pivot = value
+            }
+        }
+        let mut routes = (HashMap::new(), HashMap::new());
+        for (direction, pair) in entries.keys() {
+            let directions = match direction {
+                Some(d) => vec![*d],
+                None => vec![Direction::Input, Direction::Output]
+            };
+            for d in directions {
+                let shared = entries.get(&(None, pair.to_owned())).cloned().unwrap_or_default();
+                let own = entries.get(&(Some(d), pair.to_owned())).cloned().unwrap_or_default();
+                let route = ModelRoute {
+                    custom: own.custom.or(shared.custom),
+                    base: own.base.or(shared.base).unwrap_or_else(|| pair.to_owned()),
+                    pivot: own.pivot.or(shared.pivot)
+                };
+                match d {
+                    Direction::Input => routes.0.insert(pair.to_owned(), route),
+                    Direction::Output => routes.1.insert(pair.to_owned(), route)
+                };
+            }
+        }
+        routes
+    }
+}
+
+impl GatewayConfig {
+    /// Load config from environment variable, `.env` and TOML file.
+    pub fn load() -> Result<GatewayConfig, GatewayErr> {
+        dotenv::dotenv().ok();
+        let mut settings = Settings::new();
+        let mut problems = Vec::new();
+        let file = match env::var("GATEWAY_CONFIG") {
+            Ok(path) => Some(PathBuf::from(path)),
+            Err(_) => Some(PathBuf::from(DEFAULT_CONFIG_FILE)).filter(|p| p.exists())
+        };
+        if let Some(path) = file {
+            settings.extend(read_toml(&path, &mut problems));
+        }
+        settings.extend(env_settings());
+        GatewayConfig::validate(&settings, problems)
+    }
+
+    /// Build config from settings keyed by environment variable name.
+    pub fn from_settings(settings: &HashMap<String, String>) -> Result<GatewayConfig, GatewayErr> {
+        GatewayConfig::validate(settings, Vec::new())
+    }
+
+    fn validate(settings: &Settings, problems: Vec<String>) -> Result<GatewayConfig, GatewayErr> {
+        let mut v = Validator {
+            settings,
+            problems
+        };
+        let wa = WaConfig {
+            service: v.service("WA"),
+            assistant_id: v.required("WA_ID")
+        };
+        let service = v.service("WLT");
+        let (input_routes, output_routes) = v.routes();
+        let wlt = WltConfig {
+            service,
+            pairs: v.pairs("WLT_MODELS"),
+            pivot: v.language("WLT_PIVOT").unwrap_or_else(|| DEFAULT_PIVOT.to_owned()),
+            input_routes,
+            output_routes,
+            cache: v.cache(),
+            chunk: v.chunk(),
+            glossary: v.files("WLT_GLOSSARY")
+        };
+        let auth_type = match v.get("AUTH_TYPE").map(|t| t.to_lowercase()) {
+            None => AuthType::Basic,
+            Some(t) if t == "basic" => AuthType::Basic,
+            Some(t) if t == "iam" => AuthType::Iam,
+            Some(t) => {
+                v.problems.push(format!("AUTH_TYPE shall be basic or iam, found {}", t));
+                AuthType::Basic
+            }
+        };
+        let iam_url = if v.get("IAM_URL").is_some() { v.url("IAM_URL") } else { DEFAULT_IAM_URL.to_owned() };
+        let turn_timeout = v.millis("TURN_TIMEOUT", Duration::from_millis(DEFAULT_TURN_TIMEOUT));
+        let log_level = match v.get("LOG_LEVEL").map(Level::from_str) {
+            None => Level::Info,
+            Some(Ok(level)) => level,
+            Some(Err(e)) => {
+                v.problems.push(format!("LOG_LEVEL: {}", e));
+                Level::Info
+            }
+        };
+        let cassette = match (v.get("HTTP_CASSETTE_MODE"), v.get("HTTP_CASSETTE")) {
+            (None, _) => None,
+            (Some("record"), Some(path)) => Some((CassetteMode::Record, PathBuf::from(path))),
+            (Some("replay"), Some(path)) => Some((CassetteMode::Replay, PathBuf::from(path))),
+            (Some(mode), _) => {
+                v.problems.push(format!("HTTP_CASSETTE_MODE shall be record or replay with HTTP_CASSETTE, found {}", mode));
+                None
+            }
+        };
+
+        if !v.problems.is_empty() {
+            v.problems.sort();
+            return Err(GatewayErr::config(format!("Invalid configuration: {}", v.problems.join("; "))));
+        }
+        Ok(GatewayConfig {
+            wa,
+            wlt,
+            auth_type,
+            iam_url,
+            turn_timeout,
+            log_level,
+            cassette
+        })
+    }
+}
+
+#[cfg(test)]
+mod test;
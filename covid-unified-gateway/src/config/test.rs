@@ -0,0 +1,319 @@
+use super::*;
+use crate::error::ErrKind;
+use std::io::Write;
+
+fn settings(pairs: &[(&str, &str)]) -> Settings {
+    pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+}
+
+fn minimal() -> Settings {
+    settings(&[
+        ("WA_APIKEY", "wa-key"),
+        ("WA_ENDPOINT", "https://wa.example/"),
+        ("WA_VERSION", "2020-04-01"),
+        ("WA_ID", "assistant"),
+        ("WLT_APIKEY", "wlt-key"),
+        ("WLT_ENDPOINT", "https://wlt.example"),
+        ("WLT_VERSION", "2018-05-01")
+    ])
+}
+
+fn problems(settings: &Settings) -> String {
+    GatewayConfig::from_settings(settings).unwrap_err().message().to_owned()
+}
+
+#[test]
+fn test_minimal_config() {
+    let config = GatewayConfig::from_settings(&minimal()).unwrap();
+    assert_eq!(config.wa.service.endpoint, "https://wa.example");
+    assert_eq!(config.wa.assistant_id, "assistant");
+    assert_eq!(config.wlt.service.api_key, "wlt-key");
+    assert_eq!(config.auth_type, AuthType::Basic);
+    assert_eq!(config.iam_url, DEFAULT_IAM_URL);
+    assert_eq!(config.turn_timeout, Duration::from_millis(DEFAULT_TURN_TIMEOUT));
+    assert_eq!(config.log_level, Level::Info);
+    assert_eq!(config.wa.service.retry.max_retry, RetryPolicy::default().max_retry);
+    assert!(config.cassette.is_none());
+}
+
+#[test]
+fn test_report_every_problem() {
+    let mut s = minimal();
+    s.remove("WA_ID");
+    s.remove("WLT_APIKEY");
+    s.insert("WA_ENDPOINT".to_owned(), "not a url".to_owned());
+    s.insert("WLT_ENDPOINT".to_owned(), "ftp://wlt.example".to_owned());
+    s.insert("WA_RETRY".to_owned(), "11".to_owned());
+    s.insert("WLT_TIMEOUT".to_owned(), "soon".to_owned());
+    s.insert("TURN_TIMEOUT".to_owned(), "0".to_owned());
+    s.insert("AUTH_TYPE".to_owned(), "token".to_owned());
+    let err = GatewayConfig::from_settings(&s).unwrap_err();
+    assert_eq!(err.kind(), ErrKind::Config);
+    let message = err.message();
+    for expected in ["WA_ID is missing", "WLT_APIKEY is missing", "WA_ENDPOINT is not a valid URL", "WLT_ENDPOINT shall be http or https URL",
+                     "WA_RETRY shall be at most 10", "WLT_TIMEOUT shall be positive number of millisecond", "TURN_TIMEOUT shall be greater than 0",
+                     "AUTH_TYPE shall be basic or iam"] {
+        assert!(message.contains(expected), "{} not found in {}", expected, message);
+    }
+}
+
+#[test]
+fn test_optional_settings() {
+    let mut s = minimal();
+    s.extend(settings(&[
+        ("AUTH_TYPE", "IAM"),
+        ("IAM_URL", "https://iam.example/token"),
+        ("TURN_TIMEOUT", "5000"),
+        ("LOG_LEVEL", "debug"),
+        ("WLT_RETRY", "0"),
+        ("WLT_RETRY_DELAY", "50"),
+        ("WA_CONNECT_TIMEOUT", "1000"),
+        ("HTTP_CASSETTE_MODE", "replay"),
+        ("HTTP_CASSETTE", "tests/cassettes/th_en_turn.json")
+    ]));
+    let config = GatewayConfig::from_settings(&s).unwrap();
+    assert_eq!(config.auth_type, AuthType::Iam);
+    assert_eq!(config.iam_url, "https://iam.example/token");
+    assert_eq!(config.turn_timeout, Duration::from_millis(5000));
+    assert_eq!(config.log_level, Level::Debug);
+    assert_eq!(config.wlt.service.retry.max_retry, 0);
+    assert_eq!(config.wlt.service.retry.base_delay, Duration::from_millis(50));
+    assert_eq!(config.wa.service.timeout.connect, Duration::from_millis(1000));
+    assert_eq!(config.cassette, Some((CassetteMode::Replay, PathBuf::from("tests/cassettes/th_en_turn.json"))));
+}
+
+#[test]
+fn test_invalid_optional_settings() {
+    let mut s = minimal();
+    s.extend(settings(&[("IAM_URL", "iam"), ("LOG_LEVEL", "verbose"), ("HTTP_CASSETTE_MODE", "replay")]));
+    let message = problems(&s);
+    assert!(message.contains("IAM_URL is not a valid URL"), "{}", message);
+    assert!(message.contains("LOG_LEVEL: Unknown log level verbose"), "{}", message);
+    assert!(message.contains("HTTP_CASSETTE_MODE shall be record or replay with HTTP_CASSETTE"), "{}", message);
+}
+
+#[test]
+fn test_custom_models() {
+    let mut s = minimal();
+    s.extend(settings(&[("WLT_MODEL_th_en", " custom-th-en "), ("PATH", "/usr/bin"), ("http_proxy", "http://proxy")]));
+    let config = GatewayConfig::from_settings(&s).unwrap();
+    assert_eq!(config.wlt.input_routes.len(), 1);
+    let route = config.wlt.route(Direction::Input, "th", "en");
+    assert_eq!(route.models(), vec!["custom-th-en", "th-en"]);
+    assert_eq!(config.wlt.route(Direction::Output, "th", "en"), route);
+    assert_eq!(config.wlt.route(Direction::Output, "en", "th"), ModelRoute::base("en", "th"));
+}
+
+#[test]
+fn test_route_per_direction() {
+    let mut s = minimal();
+    s.extend(settings(&[
+        ("WLT_MODEL_th_en", "custom-th-en"),
+        ("WLT_MODEL_th_en_pivot", "ja"),
+        ("WLT_MODEL_output_th_en", "output-th-en"),
+        ("WLT_MODEL_output_th_en_base", "th-en-v2"),
+        ("WLT_MODEL_input_en_th_base", "en-th-v2")
+    ]));
+    let config = GatewayConfig::from_settings(&s).unwrap();
+    assert_eq!(config.wlt.route(Direction::Input, "th", "en"), ModelRoute {
+        custom: Some("custom-th-en".to_owned()),
+        base: "th-en".to_owned(),
+        pivot: Some("ja".to_owned())
+    });
+    assert_eq!(config.wlt.route(Direction::Output, "th", "en"), ModelRoute {
+        custom: Some("output-th-en".to_owned()),
+        base: "th-en-v2".to_owned(),
+        pivot: Some("ja".to_owned())
+    });
+    assert_eq!(config.wlt.route(Direction::Input, "en", "th").models(), vec!["en-th-v2"]);
+    assert_eq!(config.wlt.route(Direction::Output, "en", "th"), ModelRoute::base("en", "th"));
+}
+
+#[test]
+fn test_invalid_pivot() {
+    let mut s = minimal();
+    s.extend(settings(&[("WLT_MODEL_th_zh_pivot", "english"), ("WLT_MODEL_thai", "custom")]));
+    let message = problems(&s);
+    assert!(message.contains("WLT_MODEL_th_zh_pivot shall be language code, found english"), "{}", message);
+    assert!(message.contains("WLT_MODEL_thai shall be WLT_MODEL_[input_|output_]{source}_{target}[_base|_pivot]"), "{}", message);
+}
+
+#[test]
+fn test_env_setting_names() {
+    for name in ["WA_APIKEY", "WLT_MODEL_th_en", "AUTH_TYPE", "TURN_TIMEOUT", "LOG_LEVEL", "th_en"] {
+        assert!(is_setting(name), "{}", name);
+    }
+    for name in ["PATH", "no_proxy", "LC_ALL", "HOME", "th_en_base"] {
+        assert!(!is_setting(name), "{}", name);
+    }
+}
+
+#[test]
+fn test_legacy_route_reported() {
+    let mut s = minimal();
+    s.extend(settings(&[("th_en", "custom-th-en"), ("zh-TW-en", "custom-zh-en"), ("lo_th", "")]));
+    let message = problems(&s);
+    assert!(message.contains("th_en is no longer read, rename it to WLT_MODEL_th_en"), "{}", message);
+    assert!(message.contains("zh-TW-en is no longer read, rename it to WLT_MODEL_zh-TW_en"), "{}", message);
+    // empty one doesn't route anything so it's fine
+    assert!(!message.contains("lo_th"), "{}", message);
+}
+
+#[test]
+fn test_parse_route_setting() {
+    assert_eq!(parse_route_setting("th_en"), Some((None, "th-en".to_owned(), RouteField::Custom)));
+    assert_eq!(parse_route_setting("input_zh-TW_en_base"), Some((Some(Direction::Input), "zh-TW-en".to_owned(), RouteField::Base)));
+    assert_eq!(parse_route_setting("output_en_th_pivot"), Some((Some(Direction::Output), "en-th".to_owned(), RouteField::Pivot)));
+    for name in ["TH_EN", "th", "th_en_model", "input_th", "no_proxy", "LC_ALL"] {
+        assert_eq!(parse_route_setting(name), None, "{}", name);
+    }
+}
+
+#[test]
+fn test_language_code() {
+    for code in ["th", "en", "zh-TW", "fil", "sr-Latn"] {
+        assert!(is_language_code(code), "{}", code);
+    }
+    for code in ["", "t", "TH", "thai", "th-", "th-T", "th_en", "auto1"] {
+        assert!(!is_language_code(code), "{}", code);
+    }
+}
+
+#[test]
+fn test_read_toml() {
+    let path = std::env::temp_dir().join(format!("gateway-config-{}.toml", fastrand::u64(..)));
+    let mut file = std::fs::File::create(&path).unwrap();
+    file.write_all(r#"
+turn_timeout = 9000
+log_level = "warn"
+
+[wa]
+apikey = "wa-key"
+endpoint = "https://wa.example"
+version = "2020-04-01"
+id = "assistant"
+retry = 2
+
+[wlt]
+apikey = "wlt-key"
+endpoint = "https://wlt.example"
+version = "2018-05-01"
+
+[models]
+th_en = "custom-th-en"
+thai_english = "ignored"
+th_zh = { base = "th-zh", pivot = "en" }
+
+[models.output]
+en_th = { custom = "custom-en-th", base = "en-th" }
+"#.as_bytes()).unwrap();
+    let mut problems = Vec::new();
+    let mut s = read_toml(&path, &mut problems);
+    std::fs::remove_file(&path).ok();
+    assert_eq!(problems, vec!["models.thai_english shall be {source}_{target} language codes".to_owned()]);
+    assert_eq!(s.get("TURN_TIMEOUT").map(String::as_str), Some("9000"));
+    assert_eq!(s.get("WA_RETRY").map(String::as_str), Some("2"));
+
+    // environment variable take precedence over the file
+    s.insert("WA_ID".to_owned(), "from-env".to_owned());
+    let config = GatewayConfig::from_settings(&s).unwrap();
+    assert_eq!(config.turn_timeout, Duration::from_millis(9000));
+    assert_eq!(config.log_level, Level::Warn);
+    assert_eq!(config.wa.assistant_id, "from-env");
+    assert_eq!(config.wa.service.retry.max_retry, 2);
+    assert_eq!(config.wlt.route(Direction::Input, "th", "en").models(), vec!["custom-th-en", "th-en"]);
+    assert_eq!(config.wlt.route(Direction::Input, "th", "zh").pivot.as_deref(), Some("en"));
+    assert_eq!(config.wlt.route(Direction::Output, "en", "th").models(), vec!["custom-en-th", "en-th"]);
+    assert_eq!(config.wlt.route(Direction::Input, "en", "th"), ModelRoute::base("en", "th"));
+}
+
+#[test]
+fn test_unreadable_toml() {
+    let mut problems = Vec::new();
+    let s = read_toml(Path::new("does/not/exist.toml"), &mut problems);
+    assert!(s.is_empty());
+    assert_eq!(problems.len(), 1);
+    assert!(problems[0].starts_with("Cannot read does/not/exist.toml"));
+}
+
+#[test]
+fn test_available_pairs_and_pivot() {
+    let config = GatewayConfig::from_settings(&minimal()).unwrap();
+    assert!(config.wlt.pairs.is_none());
+    assert_eq!(config.wlt.pivot, DEFAULT_PIVOT);
+
+    let mut s = minimal();
+    s.extend(settings(&[("WLT_MODELS", "th-en, en-th,zh-TW-en"), ("WLT_PIVOT", "ja"), ("WLT_MODEL_lo_th_pivot", "en")]));
+    let config = GatewayConfig::from_settings(&s).unwrap();
+    let mut pairs: Vec<&str> = config.wlt.pairs.as_ref().unwrap().iter().map(String::as_str).collect();
+    pairs.sort();
+    assert_eq!(pairs, vec!["en-th", "th-en", "zh-TW-en"]);
+    assert_eq!(config.wlt.pivot_of(&config.wlt.route(Direction::Input, "th", "zh")), "ja");
+    assert_eq!(config.wlt.pivot_of(&config.wlt.route(Direction::Input, "lo", "th")), "en");
+
+    let mut s = minimal();
+    s.extend(settings(&[("WLT_MODELS", "th-en,thai"), ("WLT_PIVOT", "English")]));
+    let message = problems(&s);
+    assert!(message.contains("WLT_MODELS shall be comma separated {source}-{target} language codes, found thai"), "{}", message);
+    assert!(message.contains("WLT_PIVOT shall be language code, found English"), "{}", message);
+}
+
+#[test]
+fn test_parse_pair() {
+    assert_eq!(parse_pair("th-en"), Some(("th", "en")));
+    assert_eq!(parse_pair("zh-TW-en"), Some(("zh-TW", "en")));
+    assert_eq!(parse_pair("en-zh-TW"), Some(("en", "zh-TW")));
+    assert_eq!(parse_pair("thai"), None);
+    assert_eq!(parse_pair("custom-model"), None);
+}
+
+#[test]
+fn test_cache_settings() {
+    let config = GatewayConfig::from_settings(&minimal()).unwrap();
+    assert_eq!(config.wlt.cache, CacheConfig::default());
+
+    let mut s = minimal();
+    s.extend(settings(&[("WLT_CACHE", "/tmp/translations.json"), ("WLT_CACHE_SIZE", "50"), ("WLT_CACHE_FILE_SIZE", "500"), ("WLT_CACHE_WARM", "Cargo.toml")]));
+    let config = GatewayConfig::from_settings(&s).unwrap();
+    assert_eq!(config.wlt.cache, CacheConfig {
+        path: Some(PathBuf::from("/tmp/translations.json")),
+        warm: Some(PathBuf::from("Cargo.toml")),
+        capacity: 50,
+        file_capacity: 500
+    });
+
+    let mut s = minimal();
+    s.extend(settings(&[("WLT_CACHE_SIZE", "-1"), ("WLT_CACHE_WARM", "does/not/exist.json")]));
+    let message = problems(&s);
+    assert!(message.contains("WLT_CACHE_SIZE shall be number of translation, found -1"), "{}", message);
+    assert!(message.contains("WLT_CACHE_WARM shall be a file, found does/not/exist.json"), "{}", message);
+}
+
+#[test]
+fn test_chunk_settings() {
+    let config = GatewayConfig::from_settings(&minimal()).unwrap();
+    assert_eq!(config.wlt.chunk, ChunkConfig::default());
+
+    let mut s = minimal();
+    s.extend(settings(&[("WLT_CHUNK_TEXTS", "10"), ("WLT_CHUNK_BYTES", "2048")]));
+    let config = GatewayConfig::from_settings(&s).unwrap();
+    assert_eq!(config.wlt.chunk, ChunkConfig { max_texts: 10, max_bytes: 2048 });
+
+    let mut s = minimal();
+    s.extend(settings(&[("WLT_CHUNK_TEXTS", "0"), ("WLT_CHUNK_BYTES", "big")]));
+    let message = problems(&s);
+    assert!(message.contains("WLT_CHUNK_TEXTS shall be greater than 0"), "{}", message);
+    assert!(message.contains("WLT_CHUNK_BYTES shall be positive number, found big"), "{}", message);
+}
+
+#[test]
+fn test_glossary_files() {
+    let mut s = minimal();
+    s.extend(settings(&[("WLT_GLOSSARY", "../wlt-dataset/th/glossary.csv, ../wlt-dataset/th/glossary.tmx")]));
+    let config = GatewayConfig::from_settings(&s).unwrap();
+    assert_eq!(config.wlt.glossary, vec![PathBuf::from("../wlt-dataset/th/glossary.csv"), PathBuf::from("../wlt-dataset/th/glossary.tmx")]);
+
+    let mut s = minimal();
+    s.extend(settings(&[("WLT_GLOSSARY", "missing.csv")]));
+    assert!(problems(&s).contains("WLT_GLOSSARY shall be comma separated files, found missing.csv"));
+}
@@ -0,0 +1,166 @@
+//! JSON that the gateway print as the result of a turn.
+//!
+//! Success look like
+//! ```json
+//! {"status": 200, "sessionId": "...", "result": {"output": {...}}}
+//! ```
+//! Failure has HTTP like status, stable error code, message and the stage that fail.
+//! `sessionId` is present if session has been established before the failure.
+//! ```json
+//! {"status": 504, "code": "timeout", "message": "...", "stage": "message", "sessionId": "..."}
+//! ```
+//! Failure that the gateway recover from, e.g. untranslated output, is listed in `warnings` of
+//! successful envelope so client can tell the result is degraded.
+//! `detectedLang` is the language identified from user message when client doesn't give `sourceLang`,
+//! so client can switch its locale.
+//! Translation that went through pivot language is listed in `pivots`:
+//! ```json
+//! {"status": 200, "sessionId": "...", "result": {...}, "pivots": [{"stage": "input_translation", "source": "th", "pivot": "en", "target": "zh"}]}
+//! ```
+//! When WA response is translated, `translations` tell how each text of it end up, so client know which one
+//! is still in the language of WA:
+//! ```json
+//! {"status": 200, "sessionId": "...", "result": {...}, "translations": [{"field": "output.generic[0].text", "status": "fallback"}]}
+//! ```
+
+use serde::Serialize;
+use super::error::{GatewayErr, Stage};
+use super::wa::WAResponse;
+
+/// Error or warning as seen by client.
+#[derive(Debug, Serialize)]
+pub struct ErrorBody {
+    pub code: &'static str,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stage: Option<Stage>
+}
+
+impl From<&GatewayErr> for ErrorBody {
+    fn from(e: &GatewayErr) -> Self {
+        ErrorBody {
+            code: e.code(),
+            message: e.report(),
+            stage: e.stage()
+        }
+    }
+}
+
+/// Translation that went through pivot language because WLT has no model for the pair.
+#[derive(Debug, Serialize)]
+pub struct PivotUse {
+    pub stage: Stage,
+    pub source: String,
+    pub pivot: String,
+    pub target: String
+}
+
+/// How text of WA response end up after output translation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TranslationStatus {
+    /// Translated in one batch with other texts
+    Translated,
+    /// Translated on its own after the batch failed
+    Fallback,
+    /// Kept as WA return it, e.g. translation failed or dialog author flag it
+    Original
+}
+
+/// Translation status of one text of WA response.
+#[derive(Debug, Serialize)]
+pub struct FieldTranslation {
+    /// Path of the text in `result`, e.g. `output.generic[0].options[1].label`
+    pub field: String,
+    pub status: TranslationStatus
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Envelope {
+    pub status: u16,
+    #[serde(flatten)]
+    pub error: Option<ErrorBody>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<WAResponse>,
+    /// Language identified from user message
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detected_lang: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<ErrorBody>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub pivots: Vec<PivotUse>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub translations: Vec<FieldTranslation>
+}
+
+impl Envelope {
+    pub fn success(session_id: String, result: WAResponse) -> Envelope {
+        Envelope {
+            status: 200,
+            error: None,
+            session_id: Some(session_id),
+            result: Some(result),
+            detected_lang: None,
+            warnings: Vec::new(),
+            pivots: Vec::new(),
+            translations: Vec::new()
+        }
+    }
+
+    /// Envelope of failed turn. `session_id` is the session established before the failure, if any.
+    pub fn failure(e: &GatewayErr, session_id: Option<String>) -> Envelope {
+        Envelope {
+            status: e.status(),
+            error: Some(e.into()),
+            session_id,
+            result: None,
+            detected_lang: None,
+            warnings: Vec::new(),
+            pivots: Vec::new(),
+            translations: Vec::new()
+        }
+    }
+
+    /// Record failure that the turn has recovered from.
+    pub fn warning(mut self, e: &GatewayErr) -> Envelope {
+        self.warnings.push(e.into());
+        self
+    }
+
+    /// Language identified from user message.
+    pub fn detected_lang<S: Into<String>>(mut self, lang: S) -> Envelope {
+        self.detected_lang = Some(lang.into());
+        self
+    }
+
+    /// Record translation that went through pivot language.
+    pub fn pivot(mut self, pivot: PivotUse) -> Envelope {
+        self.pivots.push(pivot);
+        self
+    }
+
+    /// Record translation status of text of WA response.
+    pub fn translation(mut self, translation: FieldTranslation) -> Envelope {
+        self.translations.push(translation);
+        self
+    }
+
+    /// Serialize into single line JSON. It never fail. If the result cannot be serialized,
+    /// it return `500` envelope instead.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_else(|e| {
+            serde_json::json!({
+                "status": 500,
+                "code": "internal_error",
+                "message": format!("Cannot serialize result: {}", e),
+                "sessionId": self.session_id
+            }).to_string()
+        })
+    }
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,101 @@
+use super::*;
+use crate::error::ErrKind;
+use crate::utils::{CurlErr, HttpErr, WatsonError};
+use serde_json::{json, Value};
+
+fn to_value(envelope: &Envelope) -> Value {
+    serde_json::from_str(&envelope.to_json()).unwrap()
+}
+
+fn http_err(status: u16, error: &str) -> GatewayErr {
+    GatewayErr::from(CurlErr::HttpStatus(HttpErr {
+        status,
+        body: Some(WatsonError {
+            code: Some(status),
+            error: Some(error.to_owned()),
+            trace: None
+        }),
+        transaction_id: None,
+        retry_after: None
+    }))
+}
+
+#[test]
+fn test_success_envelope() {
+    let result: WAResponse = serde_json::from_value(json!({"output": {"generic": []}})).unwrap();
+    let envelope = Envelope::success("s1".to_owned(), result);
+    assert_eq!(to_value(&envelope), json!({"status": 200, "sessionId": "s1", "result": {"output": {"generic": []}}}));
+}
+
+#[test]
+fn test_failure_envelope() {
+    let err = GatewayErr::from(CurlErr::Timeout).context("Fail to send message").at(Stage::Message);
+    let envelope = Envelope::failure(&err, Some("s1".to_owned()));
+    assert_eq!(to_value(&envelope), json!({
+        "status": 504,
+        "code": "timeout",
+        "message": "Fail to send message: Request timed out",
+        "stage": "message",
+        "sessionId": "s1"
+    }));
+}
+
+#[test]
+fn test_warning() {
+    let result: WAResponse = serde_json::from_value(json!({"output": {"generic": []}})).unwrap();
+    let err = GatewayErr::translation_mismatch(2, 1).at(Stage::OutputTranslation);
+    let value = to_value(&Envelope::success("s1".to_owned(), result).warning(&err));
+    assert_eq!(value["status"], 200);
+    assert_eq!(value["warnings"][0]["code"], "translation_mismatch");
+    assert_eq!(value["warnings"][0]["stage"], "output_translation");
+}
+
+#[test]
+fn test_status_and_code() {
+    let cases = vec![
+        (GatewayErr::config("bad").at(Stage::Request), 400, "invalid_request"),
+        (GatewayErr::config("bad").at(Stage::Config), 500, "config_error"),
+        (GatewayErr::from(CurlErr::RequestFail), 502, "upstream_unreachable"),
+        (GatewayErr::new(ErrKind::Decode, "bad"), 502, "upstream_malformed"),
+        (http_err(404, "Invalid Session"), 404, "session_expired"),
+        (http_err(401, "Unauthorized"), 502, "upstream_unauthorized"),
+        (http_err(403, "Forbidden"), 502, "upstream_unauthorized"),
+        (http_err(429, "Too many"), 429, "rate_limited"),
+        (http_err(503, "Down"), 502, "upstream_error"),
+        (http_err(400, "Bad"), 502, "upstream_rejected")
+    ];
+    for (err, status, code) in cases {
+        assert_eq!((err.status(), err.code()), (status, code), "{}", err.report());
+    }
+}
+
+#[test]
+fn test_pivot() {
+    let result: WAResponse = serde_json::from_value(json!({"output": {"generic": []}})).unwrap();
+    let value = to_value(&Envelope::success("s1".to_owned(), result).pivot(PivotUse {
+        stage: Stage::InputTranslation,
+        source: "lo".to_owned(),
+        pivot: "en".to_owned(),
+        target: "th".to_owned()
+    }));
+    assert_eq!(value["pivots"], json!([{"stage": "input_translation", "source": "lo", "pivot": "en", "target": "th"}]));
+}
+
+#[test]
+fn test_detected_lang() {
+    let result: WAResponse = serde_json::from_value(json!({"output": {"generic": []}})).unwrap();
+    let value = to_value(&Envelope::success("s1".to_owned(), result).detected_lang("th"));
+    assert_eq!(value["detectedLang"], "th");
+}
+
+#[test]
+fn test_translation_status() {
+    let result: WAResponse = serde_json::from_value(json!({"output": {"generic": []}})).unwrap();
+    let envelope = Envelope::success("s1".to_owned(), result);
+    assert!(to_value(&envelope).get("translations").is_none());
+    let value = to_value(&envelope.translation(FieldTranslation {
+        field: "output.generic[0].text".to_owned(),
+        status: TranslationStatus::Fallback
+    }));
+    assert_eq!(value["translations"], json!([{"field": "output.generic[0].text", "status": "fallback"}]));
+}
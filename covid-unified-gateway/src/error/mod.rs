@@ -0,0 +1,238 @@
+//! Error type shared by every part of the gateway.
+//!
+//! [GatewayErr](struct.GatewayErr.html) has an [ErrKind](enum.ErrKind.html) that caller can match on,
+//! a message that describe what the gateway was doing, and optional source error.
+//! Low level [CurlErr](../utils/enum.CurlErr.html) is converted into it with `From`.
+//! More context is attached with [context](struct.GatewayErr.html#method.context) which wrap
+//! the original error as the source so nothing is lost.
+//!
+//! ```
+//! use covid_unified_gateway::error::{ErrKind, GatewayErr};
+//! use covid_unified_gateway::utils::CurlErr;
+//!
+//! let err = GatewayErr::from(CurlErr::Timeout).context("Fail to create WA session");
+//! assert_eq!(err.kind(), ErrKind::Timeout);
+//! assert_eq!(err.report(), "Fail to create WA session: Request timed out");
+//! ```
+//!
+//! The gateway flow record [Stage](enum.Stage.html) where it fail with [at](struct.GatewayErr.html#method.at).
+//! [code](struct.GatewayErr.html#method.code) and [status](struct.GatewayErr.html#method.status)
+//! is what client see in [Envelope](../envelope/struct.Envelope.html).
+
+use serde::Serialize;
+use std::error::Error;
+use std::fmt;
+use super::utils::{CurlErr, HttpErr};
+
+/// Kind of failure. It is stable so caller can decide what to do, e.g. retry or renew session.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrKind {
+    /// Request cannot be delivered or its response cannot be read.
+    Transport,
+    /// Request or the whole turn ran out of time.
+    Timeout,
+    /// Non 2xx response without Watson error body.
+    Http,
+    /// Non 2xx response with Watson error body.
+    WatsonApi,
+    /// Request cannot be encoded or response cannot be decoded.
+    Decode,
+    /// Missing or invalid configuration or parameter.
+    Config,
+    /// Number of translations doesn't match number of text sent.
+    TranslationMismatch
+}
+
+/// Step of a turn where the failure happen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    /// Loading gateway configuration
+    Config,
+    /// Parsing request parameters
+    Request,
+    /// Identifying language of user message
+    LanguageIdentification,
+    InputTranslation,
+    /// Creating or renewing WA session
+    Session,
+    Message,
+    OutputTranslation
+}
+
+#[derive(Debug)]
+pub struct GatewayErr {
+    kind: ErrKind,
+    stage: Option<Stage>,
+    message: String,
+    source: Option<Box<dyn Error + Send + Sync + 'static>>
+}
+
+impl GatewayErr {
+    pub fn new<M: Into<String>>(kind: ErrKind, message: M) -> GatewayErr {
+        GatewayErr {
+            kind,
+            stage: None,
+            message: message.into(),
+            source: None
+        }
+    }
+
+    /// Missing or invalid configuration, e.g. environment variable or request parameter.
+    pub fn config<M: Into<String>>(message: M) -> GatewayErr {
+        GatewayErr::new(ErrKind::Config, message)
+    }
+
+    /// WLT return `actual` translations for `expected` text.
+    pub fn translation_mismatch(expected: usize, actual: usize) -> GatewayErr {
+        GatewayErr::new(ErrKind::TranslationMismatch, format!("Expect {} translations but WLT return {}", expected, actual))
+    }
+
+    /// Attach the error that cause this one.
+    pub fn with_source<E: Error + Send + Sync + 'static>(mut self, source: E) -> GatewayErr {
+        self.source = Some(Box::new(source));
+        self
+    }
+
+    /// Wrap this error with message describing what was being done. The kind and stage are kept.
+    pub fn context<M: Into<String>>(self, message: M) -> GatewayErr {
+        GatewayErr {
+            kind: self.kind,
+            stage: self.stage,
+            message: message.into(),
+            source: Some(Box::new(self))
+        }
+    }
+
+    /// Record the stage of the turn where it fail.
+    pub fn at(mut self, stage: Stage) -> GatewayErr {
+        self.stage = Some(stage);
+        self
+    }
+
+    pub fn kind(&self) -> ErrKind {
+        self.kind
+    }
+
+    pub fn stage(&self) -> Option<Stage> {
+        self.stage
+    }
+
+    /// Stable machine readable code of the error. Client shall rely on it instead of message.
+    pub fn code(&self) -> &'static str {
+        match self.kind {
+            ErrKind::Transport => "upstream_unreachable",
+            ErrKind::Timeout => "timeout",
+            ErrKind::Decode => "upstream_malformed",
+            ErrKind::Config if self.stage == Some(Stage::Request) => "invalid_request",
+            ErrKind::Config => "config_error",
+            ErrKind::TranslationMismatch => "translation_mismatch",
+            ErrKind::Http | ErrKind::WatsonApi => match self.http() {
+                Some(e) if e.is_session_expired() => "session_expired",
+                Some(e) if e.is_unauthorized() || e.is_forbidden() => "upstream_unauthorized",
+                Some(e) if e.is_rate_limited() => "rate_limited",
+                Some(e) if e.is_server_error() => "upstream_error",
+                _ => "upstream_rejected"
+            }
+        }
+    }
+
+    /// HTTP like status to report to client.
+    /// Problem of the request is `4xx`, otherwise it is `5xx` since the gateway or upstream fail.
+    pub fn status(&self) -> u16 {
+        match self.code() {
+            "invalid_request" => 400,
+            "session_expired" => 404,
+            "rate_limited" => 429,
+            "config_error" => 500,
+            "timeout" => 504,
+            _ => 502
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Non 2xx response that cause this error, if any.
+    pub fn http(&self) -> Option<&HttpErr> {
+        let mut current: Option<&(dyn Error + 'static)> = Some(self);
+        while let Some(e) = current {
+            if let Some(http) = e.downcast_ref::<HttpErr>() {
+                return Some(http);
+            }
+            current = e.source();
+        }
+        None
+    }
+
+    /// Failure that may succeed if the request is sent again.
+    pub fn is_transient(&self) -> bool {
+        match self.kind {
+            ErrKind::Transport | ErrKind::Timeout => true,
+            ErrKind::Http | ErrKind::WatsonApi => self.http().is_some_and(|e| e.is_rate_limited() || e.is_server_error()),
+            _ => false
+        }
+    }
+
+    /// WA doesn't know the session anymore.
+    pub fn is_session_expired(&self) -> bool {
+        self.http().is_some_and(|e| e.is_session_expired())
+    }
+
+    /// WLT cannot translate with the requested model.
+    pub fn is_model_unavailable(&self) -> bool {
+        self.http().is_some_and(|e| e.is_model_unavailable())
+    }
+
+    /// Message of this error followed by message of every source, separated by `: `.
+    pub fn report(&self) -> String {
+        let mut report = self.to_string();
+        let mut current = self.source();
+        while let Some(e) = current {
+            report.push_str(": ");
+            report.push_str(&e.to_string());
+            current = e.source();
+        }
+        report
+    }
+}
+
+impl fmt::Display for GatewayErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for GatewayErr {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.as_ref().map(|e| e.as_ref() as &(dyn Error + 'static))
+    }
+}
+
+impl From<CurlErr> for GatewayErr {
+    fn from(e: CurlErr) -> Self {
+        let kind = match &e {
+            CurlErr::InvalidUrl => ErrKind::Config,
+            CurlErr::InvalidInputData | CurlErr::IncompatibleResultData => ErrKind::Decode,
+            CurlErr::UnexpectedOutputData | CurlErr::RequestFail | CurlErr::NotRecorded => ErrKind::Transport,
+            CurlErr::Timeout => ErrKind::Timeout,
+            CurlErr::HttpStatus(h) if h.body.is_some() => ErrKind::WatsonApi,
+            CurlErr::HttpStatus(_) => ErrKind::Http
+        };
+        let message = e.to_string();
+        let source: Option<Box<dyn Error + Send + Sync + 'static>> = match e {
+            CurlErr::HttpStatus(h) => Some(Box::new(h)),
+            _ => None
+        };
+        GatewayErr {
+            kind,
+            stage: None,
+            message,
+            source
+        }
+    }
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,54 @@
+use super::*;
+use crate::utils::WatsonError;
+
+fn http_err(status: u16, error: Option<&str>) -> CurlErr {
+    CurlErr::HttpStatus(HttpErr {
+        status,
+        body: error.map(|e| WatsonError {
+            code: Some(status),
+            error: Some(e.to_owned()),
+            trace: None
+        }),
+        transaction_id: Some("tx".to_owned()),
+        retry_after: None
+    })
+}
+
+#[test]
+fn test_kind_from_curl_err() {
+    assert_eq!(GatewayErr::from(CurlErr::InvalidUrl).kind(), ErrKind::Config);
+    assert_eq!(GatewayErr::from(CurlErr::RequestFail).kind(), ErrKind::Transport);
+    assert_eq!(GatewayErr::from(CurlErr::Timeout).kind(), ErrKind::Timeout);
+    assert_eq!(GatewayErr::from(CurlErr::IncompatibleResultData).kind(), ErrKind::Decode);
+    assert_eq!(GatewayErr::from(http_err(502, None)).kind(), ErrKind::Http);
+    assert_eq!(GatewayErr::from(http_err(404, Some("Invalid Session"))).kind(), ErrKind::WatsonApi);
+}
+
+#[test]
+fn test_context_keep_kind_and_source() {
+    let err = GatewayErr::from(http_err(404, Some("Invalid Session"))).context("Fail to send message");
+    assert_eq!(err.kind(), ErrKind::WatsonApi);
+    assert_eq!(err.message(), "Fail to send message");
+    assert!(err.is_session_expired());
+    assert!(!err.is_transient());
+    assert_eq!(err.http().map(|e| e.status), Some(404));
+    assert_eq!(err.report(), "Fail to send message: Server reject the request: HTTP 404 Invalid Session (transaction id tx)");
+
+    let source = err.source().unwrap().downcast_ref::<GatewayErr>().unwrap();
+    assert_eq!(source.message(), "Server reject the request");
+}
+
+#[test]
+fn test_transient() {
+    assert!(GatewayErr::from(CurlErr::Timeout).is_transient());
+    assert!(GatewayErr::from(http_err(503, None)).context("x").is_transient());
+    assert!(!GatewayErr::translation_mismatch(2, 1).is_transient());
+}
+
+#[test]
+fn test_with_source() {
+    let parse = "x".parse::<u64>().unwrap_err();
+    let err = GatewayErr::config("WA_RETRY shall be numeric").with_source(parse);
+    assert_eq!(err.kind(), ErrKind::Config);
+    assert_eq!(err.report(), "WA_RETRY shall be numeric: invalid digit found in string");
+}
@@ -0,0 +1,26 @@
+//! A gateway for covid chatbot.
+//!
+//! The crate is split into 3 modules which shall be split, in the future, to separate crate.
+//! - [utils](utils/index.html) - Utilities function to send REST request
+//! - [wa](wa/index.html) - Watson Assistant related type
+//! - [wlt](wlt/index.html) - Watson Language Translate related type
+//!
+//! [GatewayConfig](config/struct.GatewayConfig.html) is loaded once and passed to `wa` and `wlt`.
+//!
+//! Every failure is reported as [GatewayErr](error/struct.GatewayErr.html) from [error](error/index.html) module.
+//! The result of a turn, success or not, is printed as [Envelope](envelope/struct.Envelope.html).
+//!
+//! [mock_server](mock_server/index.html) is local stand-in of both Watson services for testing.
+//!
+//! The binary in `main.rs` glue them together as a Cloud Functions action.
+//! The `mock-watson` binary serve the mock server.
+
+pub mod config;
+pub mod envelope;
+pub mod error;
+#[macro_use]
+pub mod logger;
+pub mod mock_server;
+pub mod utils;
+pub mod wa;
+pub mod wlt;
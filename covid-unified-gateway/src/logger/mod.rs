@@ -0,0 +1,234 @@
+//! Leveled JSON log written to stderr.
+//!
+//! Stdout is reserved for the result [Envelope](../envelope/struct.Envelope.html) so
+//! every diagnostic goes through this module instead of `println!`.
+//! Each record is single line JSON object:
+//! ```json
+//! {"ts":1594028000123,"level":"info","correlation_id":"5f0c2a9e1b7d4c33","stage":"message","msg":"WA return response","latency_ms":182}
+//! ```
+//! `correlation_id` is set once per turn with [set_correlation_id](fn.set_correlation_id.html)
+//! so every record of the same turn can be grouped. `stage` and `latency_ms` are present only if
+//! the record has them.
+//!
+//! Records below the level set by [init](fn.init.html) are dropped. User text shall be passed
+//! through [redact](fn.redact.html). It is kept only when level is `debug`.
+//!
+//! Use `log_error!`, `log_warn!`, `log_info!` and `log_debug!` for plain message or
+//! [Record](struct.Record.html) to attach stage, latency and other fields.
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use super::error::Stage;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "error" => Ok(Level::Error),
+            "warn" | "warning" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            other => Err(format!("Unknown log level {}, expect one of error, warn, info or debug", other))
+        }
+    }
+}
+
+struct State {
+    level: Level,
+    correlation_id: Option<String>,
+    /// Write to stderr if it is `None`
+    writer: Option<Box<dyn Write + Send>>
+}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    level: Level::Info,
+    correlation_id: None,
+    writer: None
+});
+
+fn state() -> std::sync::MutexGuard<'static, State> {
+    // logging shall keep working even if some thread panic while holding the lock
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Set the most verbose level to be written.
+pub fn init(level: Level) {
+    state().level = level;
+}
+
+pub fn level() -> Level {
+    state().level
+}
+
+/// Whether record of given level will be written.
+pub fn enabled(level: Level) -> bool {
+    level <= state().level
+}
+
+/// Attach `id` to every following record.
+pub fn set_correlation_id<S: Into<String>>(id: S) {
+    state().correlation_id = Some(id.into());
+}
+
+/// Generate random correlation id.
+pub fn new_correlation_id() -> String {
+    format!("{:016x}", fastrand::u64(..))
+}
+
+/// Write records to `writer` instead of stderr. It is meant for testing.
+pub fn set_writer<W: Write + Send + 'static>(writer: W) {
+    state().writer = Some(Box::new(writer));
+}
+
+/// Return `text` as is if debug log is enabled. Otherwise, only its length is kept.
+pub fn redact(text: &str) -> String {
+    if enabled(Level::Debug) {
+        text.to_owned()
+    } else {
+        format!("<redacted {} chars>", text.chars().count())
+    }
+}
+
+/// Log record with optional stage, latency and extra fields.
+#[derive(Debug)]
+pub struct Record {
+    level: Level,
+    message: String,
+    stage: Option<Stage>,
+    latency: Option<Duration>,
+    fields: Map<String, Value>
+}
+
+impl Record {
+    pub fn new<M: Into<String>>(level: Level, message: M) -> Record {
+        Record {
+            level,
+            message: message.into(),
+            stage: None,
+            latency: None,
+            fields: Map::new()
+        }
+    }
+
+    pub fn error<M: Into<String>>(message: M) -> Record {
+        Record::new(Level::Error, message)
+    }
+
+    pub fn warn<M: Into<String>>(message: M) -> Record {
+        Record::new(Level::Warn, message)
+    }
+
+    pub fn info<M: Into<String>>(message: M) -> Record {
+        Record::new(Level::Info, message)
+    }
+
+    pub fn debug<M: Into<String>>(message: M) -> Record {
+        Record::new(Level::Debug, message)
+    }
+
+    pub fn stage(mut self, stage: Stage) -> Record {
+        self.stage = Some(stage);
+        self
+    }
+
+    /// Time taken by the operation being logged.
+    pub fn latency(mut self, latency: Duration) -> Record {
+        self.latency = Some(latency);
+        self
+    }
+
+    /// Attach extra field. Value that cannot be serialized is logged as `null`.
+    pub fn field<V: Serialize>(mut self, name: &str, value: V) -> Record {
+        self.fields.insert(name.to_owned(), serde_json::to_value(value).unwrap_or(Value::Null));
+        self
+    }
+
+    /// Write the record if its level is enabled.
+    pub fn emit(self) {
+        let mut state = state();
+        if self.level > state.level {
+            return;
+        }
+        let mut record = Map::new();
+        let ts = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64);
+        record.insert("ts".to_owned(), ts.into());
+        record.insert("level".to_owned(), serde_json::to_value(self.level).unwrap_or(Value::Null));
+        if let Some(id) = &state.correlation_id {
+            record.insert("correlation_id".to_owned(), id.to_owned().into());
+        }
+        if let Some(stage) = self.stage {
+            record.insert("stage".to_owned(), serde_json::to_value(stage).unwrap_or(Value::Null));
+        }
+        record.insert("msg".to_owned(), self.message.into());
+        if let Some(latency) = self.latency {
+            record.insert("latency_ms".to_owned(), (latency.as_millis() as u64).into());
+        }
+        for (name, value) in self.fields {
+            record.entry(name).or_insert(value);
+        }
+        let line = Value::Object(record).to_string();
+        match state.writer.as_mut() {
+            Some(w) => {
+                writeln!(w, "{}", line).ok();
+            },
+            None => eprintln!("{}", line)
+        }
+    }
+}
+
+/// Log formatted message at error level.
+#[macro_export]
+macro_rules! log_error {
+    ($($arg:tt)*) => {
+        if $crate::logger::enabled($crate::logger::Level::Error) {
+            $crate::logger::Record::error(format!($($arg)*)).emit();
+        }
+    };
+}
+
+/// Log formatted message at warn level.
+#[macro_export]
+macro_rules! log_warn {
+    ($($arg:tt)*) => {
+        if $crate::logger::enabled($crate::logger::Level::Warn) {
+            $crate::logger::Record::warn(format!($($arg)*)).emit();
+        }
+    };
+}
+
+/// Log formatted message at info level.
+#[macro_export]
+macro_rules! log_info {
+    ($($arg:tt)*) => {
+        if $crate::logger::enabled($crate::logger::Level::Info) {
+            $crate::logger::Record::info(format!($($arg)*)).emit();
+        }
+    };
+}
+
+/// Log formatted message at debug level.
+#[macro_export]
+macro_rules! log_debug {
+    ($($arg:tt)*) => {
+        if $crate::logger::enabled($crate::logger::Level::Debug) {
+            $crate::logger::Record::debug(format!($($arg)*)).emit();
+        }
+    };
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,67 @@
+use super::*;
+use std::sync::Arc;
+
+/// Writer that keep everything written in memory.
+#[derive(Clone, Default)]
+struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Buffer {
+    /// Records whose message start with `prefix`, so records of other tests running concurrently are ignored.
+    fn records(&self, prefix: &str) -> Vec<Value> {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+            .lines()
+            .map(|l| serde_json::from_str::<Value>(l).unwrap())
+            .filter(|r| r["msg"].as_str().is_some_and(|m| m.starts_with(prefix)))
+            .collect()
+    }
+}
+
+#[test]
+fn test_parse_level() {
+    assert_eq!("WARNING".parse::<Level>(), Ok(Level::Warn));
+    assert_eq!("debug".parse::<Level>(), Ok(Level::Debug));
+    assert!("verbose".parse::<Level>().is_err());
+}
+
+// every assertion on the global logger is in one test so they don't race each other
+#[test]
+fn test_record() {
+    let buffer = Buffer::default();
+    set_writer(buffer.clone());
+    set_correlation_id("turn-1");
+    init(Level::Info);
+
+    Record::info("logger-test structured").stage(Stage::Message).latency(Duration::from_millis(42)).field("session_id", "s1").emit();
+    log_warn!("logger-test plain {}", 1);
+    log_debug!("logger-test dropped");
+    assert_eq!(redact("สวัสดี"), "<redacted 6 chars>");
+
+    let records = buffer.records("logger-test");
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0]["level"], "info");
+    assert_eq!(records[0]["correlation_id"], "turn-1");
+    assert_eq!(records[0]["stage"], "message");
+    assert_eq!(records[0]["latency_ms"], 42);
+    assert_eq!(records[0]["session_id"], "s1");
+    assert!(records[0]["ts"].is_u64());
+    assert_eq!(records[1]["level"], "warn");
+    assert_eq!(records[1]["msg"], "logger-test plain 1");
+    assert!(records[1].get("stage").is_none());
+
+    init(Level::Debug);
+    log_debug!("logger-test debug");
+    assert_eq!(redact("สวัสดี"), "สวัสดี");
+    assert_eq!(buffer.records("logger-test debug").len(), 1);
+    init(Level::Info);
+}
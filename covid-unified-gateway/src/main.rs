@@ -1,7 +1,24 @@
-use dotenv::dotenv;
+use covid_unified_gateway::{wa, wlt};
+use covid_unified_gateway::wlt::{detect, span};
+use covid_unified_gateway::wlt::cache::TranslationCache;
+use covid_unified_gateway::wlt::glossary::Glossary;
+use covid_unified_gateway::wlt::markup::{self, Markup};
+use covid_unified_gateway::wlt::placeholder::{self, Masked, Restored};
+use covid_unified_gateway::config::{self, AuthType, CassetteMode, Direction, GatewayConfig};
+use covid_unified_gateway::envelope::{Envelope, FieldTranslation, PivotUse, TranslationStatus};
+use covid_unified_gateway::{log_debug, log_info, logger};
+use covid_unified_gateway::logger::Record;
+use covid_unified_gateway::error::{ErrKind, GatewayErr, Stage};
+use covid_unified_gateway::utils::{Authenticator, Deadline, IamAuthenticator, IsahcTransport, Upstream};
+use covid_unified_gateway::utils::cassette::CassetteTransport;
 use std::env;
 use serde::{Deserialize, Serialize};
 use serde_json::{json};
+use std::time::{Duration, Instant};
+
+/// Output translation is skipped if remaining time of the turn is less than this.
+/// It is better to return untranslated response than no response at all.
+const MIN_TRANSLATION_TIME: Duration = Duration::from_secs(1);
 
 #[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -11,144 +28,455 @@ struct Params {
     #[serde(skip_serializing_if="Option::is_none")]
     session_id: Option<String>,
     message: String,
-    source_lang: String,
+    /// Language of `message`. It is identified if it is missing or `auto`.
+    #[serde(skip_serializing_if="Option::is_none")]
+    source_lang: Option<String>,
     target_lang: String
 }
 
-mod wlt;
-mod wa;
-mod utils;
+/// `sourceLang` that ask the gateway to identify language of the message.
+const AUTO_LANGUAGE: &str = "auto";
 
-/// Main flow that is going to be performed when deployed on Cloud Functions.
-fn main() {
-    dotenv().ok();
-    let wlt_api_key = env::var("WLT_APIKEY").expect("Undefined WLT_APIKEY");
-    let wlt_endpoint = env::var("WLT_ENDPOINT").expect("Undefined WLT_ENDPOINT");
-    let wlt_version = env::var("WLT_VERSION").expect("Undefined WLT_VERSION");
-    let wlt_retry = env::var("WLT_RETRY").map_or(1usize, |r| r.parse().expect("WLT_RETRY shall be numeric"));
-
-    let wa_endpoint = std::env::var("WA_ENDPOINT").expect("Fail to find WA_ENDPOINT from environment variable");
-    let wa_id = std::env::var("WA_ID").expect("Fail to find WA_ID from environment variable");
-    let wa_api_key = std::env::var("WA_APIKEY").expect("Fail to find WA_APIKEY from environment variable");
-    let wa_version = std::env::var("WA_VERSION").expect("Fail to find WA_VERSION from environment variable");
-    let wa_retry = std::env::var("WA_RETRY").map_or(1usize, |r| r.parse::<usize>().expect("WA_RETRY shall be numeric"));
-    // println!("{:?}", api_key.unwrap_or(("APIKEY".to_owned(),"Undefined".to_owned())));
+/// User message after language identification and input translation.
+struct Input {
+    /// Language of user message
+    source_lang: String,
+    /// Whether `source_lang` is identified rather than given by client
+    detected: bool,
+    /// Message to be sent to WA
+    message: String,
+    /// Pivot language that input translation went through, if any
+    pivot: Option<String>,
+    /// Failures that the turn has recovered from
+    warnings: Vec<GatewayErr>
+}
 
-    let args = env::args().collect::<Vec<String>>();
+/// Translations of one batch.
+struct Translations {
+    texts: Vec<String>,
+    /// Pivot language the batch is translated through, if any
+    pivot: Option<String>,
+    /// Problems that didn't stop the translation, e.g. dropped placeholder
+    warnings: Vec<GatewayErr>
+}
+
+/// Everything needed to talk to WA and WLT in one turn.
+struct Gateway {
+    config: GatewayConfig,
+    catalog: wlt::Catalog,
+    glossary: Glossary,
+    wlt_upstream: Upstream,
+    wa_upstream: Upstream,
+    deadline: Deadline
+}
+
+impl Gateway {
+    /// Build gateway from validated config.
+    fn new(config: GatewayConfig) -> Result<Gateway, GatewayErr> {
+        // optionally record every interaction with Watson, or replay recorded one without network
+        let cassette = match &config.cassette {
+            Some((CassetteMode::Record, path)) => Some(CassetteTransport::record(IsahcTransport, path)),
+            Some((CassetteMode::Replay, path)) => Some(CassetteTransport::replay(path).map_err(GatewayErr::config)?),
+            None => None
+        };
+
+        let wlt_api_key = config.wlt.service.api_key.to_owned();
+        let wa_api_key = config.wa.service.api_key.to_owned();
+        let (wlt_auth, wa_auth): (Authenticator, Authenticator) = match config.auth_type {
+            AuthType::Iam => {
+                let iam = |api_key: String| match &cassette {
+                    Some(c) => IamAuthenticator::with_url(api_key, config.iam_url.to_owned()).transport(c.clone()),
+                    None => IamAuthenticator::with_url(api_key, config.iam_url.to_owned())
+                };
+                let wlt_iam = iam(wlt_api_key.to_owned());
+                // share the token when both services use the same API key
+                let wa_iam = if wa_api_key == wlt_api_key {
+                    wlt_iam.clone()
+                } else {
+                    iam(wa_api_key)
+                };
+                (wlt_iam.into(), wa_iam.into())
+            },
+            AuthType::Basic => (wlt_api_key.into(), wa_api_key.into())
+        };
+
+        // every request in this turn shall finish within the turn budget
+        let deadline = Deadline::after(config.turn_timeout);
+        let mut wlt_upstream = config.wlt.service.upstream(wlt_auth).deadline(deadline);
+        let mut wa_upstream = config.wa.service.upstream(wa_auth).deadline(deadline);
+        if let Some(c) = cassette {
+            wlt_upstream = wlt_upstream.transport(c.clone());
+            wa_upstream = wa_upstream.transport(c);
+        }
 
-    if args.len() == 2 {
-        let mut params: Params = serde_json::from_str(&args[1]).expect("Missing one or more parameters.");
-        futures::executor::block_on(async move {
-            if params.message.trim().len() > 0 {
-                if params.source_lang != params.target_lang {
-                    for attempt in 0..=wlt_retry {
-                        println!("Attempting {} for WLT from {} to {}", attempt + 1, params.source_lang, params.target_lang);
-                        let input = &[params.message.as_str()];
-                        let request = wlt::WLTTranslationRequest::new(&wlt_endpoint, &wlt_api_key, input, &params.source_lang, &params.target_lang, &wlt_version);
-                        let wlt_result = request.send().await;
-                        if let Ok(result) = wlt_result {
-                            println!("Translate successful in attempt {}, replacing original input message with translated one", attempt + 1);
-                            params.message = result.translations[0].translation.to_owned();
-                            break;
-                        } else {
-                            println!("WLT return error");
+        Ok(Gateway {
+            catalog: wlt::Catalog::new(&config.wlt).with_cache(TranslationCache::from_config(&config.wlt.cache)?),
+            glossary: Glossary::load(&config.wlt.glossary)?,
+            config,
+            wlt_upstream,
+            wa_upstream,
+            deadline
+        })
+    }
+
+    /// Translate `text` from `source` to `target` language following the route of `direction`.
+    /// Glossary terms, numbers, dates and WA variables are swapped for placeholders before WLT translate
+    /// and swapped back after. Placeholder that WLT dropped or duplicated is reported in warnings.
+    /// It fail if WLT doesn't return exactly one translation per text.
+    async fn translate(&self, text: &[&str], direction: Direction, source: &str, target: &str) -> Result<Translations, GatewayErr> {
+        let masked: Vec<Masked> = text.iter().map(|t| {
+            let mut protected = self.glossary.find(t, source, target);
+            protected.extend(placeholder::protected(t));
+            Masked::new(t, protected)
+        }).collect();
+        let masked_text: Vec<&str> = masked.iter().map(|m| m.text.as_str()).collect();
+        let result = wlt::translate_pair(&self.config.wlt, &self.wlt_upstream, &self.catalog, &masked_text, direction, source, target).await?;
+        let translations = result.response.translations;
+        log_debug!("WLT return {} text", translations.len());
+        if translations.len() != text.len() {
+            return Err(GatewayErr::translation_mismatch(text.len(), translations.len()));
+        }
+        let restored: Vec<Restored> = translations.iter().zip(&masked).map(|(t, m)| m.restore(&t.translation)).collect();
+        let total: usize = masked.iter().map(|m| m.values.len()).sum();
+        let dropped: usize = restored.iter().map(|r| r.dropped).sum();
+        let duplicated: usize = restored.iter().map(|r| r.duplicated).sum();
+        let mut warnings = Vec::new();
+        if dropped + duplicated > 0 {
+            Record::warn(format!("WLT dropped {} and duplicated {} of {} placeholders", dropped, duplicated, total)).field("source", source).field("target", target).emit();
+            warnings.push(GatewayErr::new(ErrKind::TranslationMismatch, format!("WLT dropped {} and duplicated {} of {} placeholders", dropped, duplicated, total)));
+        }
+        Ok(Translations {
+            texts: restored.into_iter().map(|r| r.text).collect(),
+            pivot: result.pivot,
+            warnings
+        })
+    }
+
+    /// Identify language of user message. It return the most likely language.
+    async fn identify_language(&self, message: &str) -> Result<String, GatewayErr> {
+        let started = Instant::now();
+        let mut languages = wlt::identify(&self.config.wlt, &self.wlt_upstream, message).await?;
+        Record::info(format!("Identified input language as {}", languages[0].language))
+            .stage(Stage::LanguageIdentification)
+            .latency(started.elapsed())
+            .field("confidence", languages[0].confidence)
+            .emit();
+        // the whole ranking is long, only for debugging
+        Record::debug("Identified language candidates").stage(Stage::LanguageIdentification).field("languages", &languages).emit();
+        Ok(languages.swap_remove(0).language)
+    }
+
+    /// Identify language of user message if client doesn't tell, then translate it into WA language.
+    /// Message whose language cannot be identified is assumed to be in WA language.
+    async fn prepare_input(&self, params: &Params) -> Input {
+        let given = params.source_lang.as_ref().filter(|l| *l != AUTO_LANGUAGE);
+        let mut input = Input {
+            source_lang: given.unwrap_or(&params.target_lang).to_owned(),
+            detected: false,
+            message: params.message.to_owned(),
+            pivot: None,
+            warnings: Vec::new()
+        };
+        // text without any letter, e.g. 555, is the same in every language
+        if given.is_none() && detect::has_letter(&params.message) {
+            match detect::detect(&params.message) {
+                Some(local) if local.confidence >= detect::CONFIDENT => {
+                    Record::info(format!("Detected input language as {} offline", local.language)).stage(Stage::LanguageIdentification).field("confidence", local.confidence).emit();
+                    input.source_lang = local.language;
+                    input.detected = true;
+                },
+                local => match self.identify_language(&params.message).await {
+                    Ok(lang) => {
+                        input.source_lang = lang;
+                        input.detected = true;
+                    },
+                    Err(e) => {
+                        // offline detection is less accurate but better than assuming
+                        if let Some(local) = local {
+                            input.source_lang = local.language;
+                            input.detected = true;
                         }
+                        Record::warn(format!("Fail to identify input language, assume {}: {}", input.source_lang, e.report())).stage(Stage::LanguageIdentification).emit();
+                        input.warnings.push(e.at(Stage::LanguageIdentification));
                     }
-                } else {
-                    println!("Source and target language is the same, forward request to WA");
                 }
-            } else {
-                println!("Receive empty message");
             }
+        }
+        if params.message.trim().is_empty() {
+            log_info!("Receive empty message");
+            return input;
+        }
+        if input.source_lang == params.target_lang {
+            log_info!("Source and target language is the same, forward request to WA");
+            return input;
+        }
+        if detect::can_skip_translation(&params.message, &input.source_lang, &params.target_lang) {
+            log_info!("Input is already in {}, forward request to WA", params.target_lang);
+            return input;
+        }
+        // only the runs in source language script of code-switched message are translated
+        let code_switched = span::CodeSwitched::split(&params.message, &input.source_lang, &params.target_lang);
+        let text = match &code_switched {
+            Some(c) => c.to_translate(),
+            None => vec![params.message.as_str()]
+        };
+        let started = Instant::now();
+        match self.translate(&text, Direction::Input, &input.source_lang, &params.target_lang).await {
+            Ok(mut result) => {
+                let translated = match &code_switched {
+                    Some(c) => c.join(&result.texts),
+                    None => result.texts.swap_remove(0)
+                };
+                Record::info(format!("Translated input from {} to {}", input.source_lang, params.target_lang))
+                    .stage(Stage::InputTranslation)
+                    .latency(started.elapsed())
+                    .field("text", logger::redact(&params.message))
+                    .field("translation", logger::redact(&translated))
+                    .field("pivot", &result.pivot)
+                    .field("spans", text.len())
+                    .emit();
+                input.message = translated;
+                input.pivot = result.pivot;
+                input.warnings.extend(result.warnings.into_iter().map(|w| w.at(Stage::InputTranslation)));
+            },
+            Err(e) => {
+                Record::warn(format!("Fail to translate input: {}", e.report())).stage(Stage::InputTranslation).emit();
+                input.warnings.push(e.at(Stage::InputTranslation));
+            }
+        }
+        input
+    }
 
-            println!("Establishing WA Session");
-            let wa_session = match params.session_id {
-                Some(id) => wa::WASession::re_attach(wa_endpoint, wa_api_key, wa_id, wa_version, id),
-                None => wa::WASession::new(wa_endpoint, wa_api_key, wa_id, wa_version).await.expect("Fail to create new WA session")
-            };
+    /// Report how many translations come from cache and save new ones for next invocation.
+    /// Failure to save only lose the new translations so it is logged but doesn't fail the turn.
+    fn save_cache(&self) {
+        let stats = self.catalog.cache.stats();
+        Record::info("Translation cache").field("hits", stats.hits).field("misses", stats.misses).emit();
+        if let Err(e) = self.catalog.cache.save() {
+            Record::warn(format!("Fail to save translation cache: {}", e.report())).emit();
+        }
+    }
 
-            println!("Mapping user input context to WA context");
-            
-            let mut result = None;
-            let context : wa::UnknownType = params.context.unwrap_or(wa::UnknownType::Value(json!({})));
-
-            for attempt in 0..=wa_retry {
-                println!("Attempting to send WA message for {} try", attempt + 1);
-                if let Ok(r) = wa_session.send_txt_with_context(&params.message, context.clone()).await {
-                    println!("WA successfully return response");
-                    result = Some(r);
-                    break;
-                } else {
-                    println!("Fail {} times", attempt + 1);
-                }
+    /// Re-attach to session `session_id` or create new one.
+    async fn establish_session(&self, session_id: Option<String>) -> Result<wa::WASession, GatewayErr> {
+        match session_id {
+            Some(id) => Ok(wa::WASession::re_attach(&self.config.wa, self.wa_upstream.clone(), id)),
+            None => {
+                let started = Instant::now();
+                let session = wa::WASession::new(&self.config.wa, self.wa_upstream.clone()).await?;
+                Record::info("Created WA session").stage(Stage::Session).latency(started.elapsed()).field("session_id", &session.session_id).emit();
+                Ok(session)
             }
+        }
+    }
 
-            if let Some(mut r) = result {
-                if params.source_lang != params.target_lang {
-                    println!("Extracting result from WA response");
-                    let mut translation_batch: Vec<&mut String> = Vec::with_capacity(r.output.generic.len());
-                    for response in r.output.generic.iter_mut() {
-                        match response.response_type {
-                            wa::ResponseType::Text => {
-                                translation_batch.push(response.text.as_mut().expect("Missing text from response of type text"));
-                            },
-                            wa::ResponseType::Suggestion => {
-                                translation_batch.push(response.title.as_mut().expect("Missing title for suggestions"));
-
-                                if let Some(ref mut suggestions) = response.suggestions {
-                                    for s in suggestions {
-                                        translation_batch.push(&mut s.label);
-                                    }
-                                }
-                            },
-                            wa::ResponseType::Option => {
-                                translation_batch.push(response.title.as_mut().expect("Missing title for suggestions"));
-
-                                if let Some(ref mut options) = response.options {
-                                    for o in options {
-                                        translation_batch.push(&mut o.label);
-                                    }
-                                }
-                            }
-                            _ => {}
-                        }
+    /// Translate `fields` of WA response from `source` to `target` language in place and return how each one end up.
+    /// Only readable text is translated, HTML, URL, markdown and emoji are put back around it.
+    /// Every field is sent in one batch. If the batch fail, e.g. WLT return fewer translations than requested,
+    /// each field is sent on its own so one bad field doesn't leave the others untranslated.
+    async fn translate_output(&self, fields: Vec<&mut String>, source: &str, target: &str, warnings: &mut Vec<GatewayErr>, pivots: &mut Vec<PivotUse>) -> Vec<TranslationStatus> {
+        let mut record_pivot = |pivot: Option<String>| {
+            let known = pivots.iter().any(|p| p.stage == Stage::OutputTranslation && Some(&p.pivot) == pivot.as_ref());
+            if let Some(pivot) = pivot.filter(|_| !known) {
+                pivots.push(PivotUse {
+                    stage: Stage::OutputTranslation,
+                    source: source.to_owned(),
+                    pivot,
+                    target: target.to_owned()
+                });
+            }
+        };
+        let parsed: Vec<Markup> = fields.iter().map(|s| Markup::parse(s)).collect();
+        let segments: Vec<Vec<&str>> = parsed.iter().map(Markup::to_translate).collect();
+        let mut translated: Vec<Option<(Vec<String>, TranslationStatus)>> = vec![None; fields.len()];
+        let batch = segments.concat();
+        if batch.is_empty() {
+            return vec![TranslationStatus::Original; fields.len()];
+        }
+
+        let started = Instant::now();
+        match self.translate(&batch, Direction::Output, source, target).await {
+            Ok(result) => {
+                Record::info(format!("Translated {} text from {} to {}", result.texts.len(), source, target))
+                    .stage(Stage::OutputTranslation)
+                    .latency(started.elapsed())
+                    .field("pivot", &result.pivot)
+                    .emit();
+                warnings.extend(result.warnings.into_iter().map(|w| w.at(Stage::OutputTranslation)));
+                record_pivot(result.pivot);
+                let mut rest = result.texts;
+                for (t, own) in translated.iter_mut().zip(&segments) {
+                    let next = rest.split_off(own.len());
+                    if !own.is_empty() {
+                        *t = Some((rest, TranslationStatus::Translated));
                     }
-                    println!("Total text to be translated: {} text", translation_batch.len());
-                    if translation_batch.len() > 0 {
-                        println!("Sending translation batch to WLT");
-                        // Perform batch translation
-                        let mut wa_translated: Option<wlt::WLTTranslationResponse> = None;
-                        let to_be_translate = translation_batch.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
-                        for attempt in 0..=wlt_retry {
-                            if let Ok(t) = wlt::WLTTranslationRequest::new(
-                                    &wlt_endpoint, 
-                                    &wlt_api_key, 
-                                    to_be_translate.as_slice(), 
-                                    &params.target_lang, 
-                                    &params.source_lang, 
-                                    &wlt_version).send().await {
-                                
-                                println!("WLT return {} text", t.translations.len());
-                                wa_translated = Some(t);
-                            } else {
-                                println!("Failed to translate WA response for {} time(s)", attempt + 1);
-                            }
-                        }
-                        // replace original wa response text with translated text
-                        if let Some(t) = wa_translated {
-                            // consume translation and move the translated value in place of original
-                            t.translations.into_iter().zip(translation_batch.into_iter()).for_each(|(translated, original)| {
-                                *original = translated.translation;
-                            });
-                        }
+                    rest = next;
+                }
+            },
+            Err(e) => {
+                Record::warn(format!("Failed to translate WA response in one batch, translate each text instead: {}", e.report())).stage(Stage::OutputTranslation).emit();
+                warnings.push(e.at(Stage::OutputTranslation));
+                let each = segments.iter().filter(|own| !own.is_empty()).map(|own| self.translate(own, Direction::Output, source, target));
+                let mut results = futures::future::join_all(each).await.into_iter();
+                for (i, t) in translated.iter_mut().enumerate().filter(|(i, _)| !segments[*i].is_empty()) {
+                    match results.next() {
+                        Some(Ok(result)) => {
+                            warnings.extend(result.warnings.into_iter().map(|w| w.at(Stage::OutputTranslation)));
+                            record_pivot(result.pivot);
+                            *t = Some((result.texts, TranslationStatus::Fallback));
+                        },
+                        Some(Err(e)) => Record::warn(format!("Failed to translate text {} of WA response: {}", i, e.report())).stage(Stage::OutputTranslation).emit(),
+                        None => ()
                     }
                 }
-                println!("{{\"status\": 200, \"sessionId\": \"{}\", \"result\": {}}}", wa_session.session_id, serde_json::to_string(&r).expect("Fail to convert result object to JSON"));
-            } else {
-                println!("{}", "{\"status\": 400}");
+            }
+        }
+
+        // replace original wa response text with translated text
+        let rebuilt: Vec<Option<String>> = parsed.iter().zip(&translated).map(|(p, t)| t.as_ref().map(|(texts, _)| p.join(texts))).collect();
+        rebuilt.into_iter().zip(fields).for_each(|(text, original)| {
+            if let Some(text) = text {
+                *original = text;
             }
         });
+        translated.into_iter().map(|t| t.map_or(TranslationStatus::Original, |(_, status)| status)).collect()
+    }
+
+    /// Forward one user message to WA, translating input and output if the languages differ.
+    /// Failure of translation isn't fatal. Untranslated text is used instead and the failure
+    /// is reported as warning.
+    async fn turn(&self, params: Params) -> Envelope {
+        // Input translation and session establishment don't depend on each other
+        // so both requests are sent concurrently.
+        let (input, wa_session) = futures::join!(self.prepare_input(&params), self.establish_session(params.session_id.clone()));
+        let mut wa_session = match wa_session {
+            Ok(s) => s,
+            Err(e) => return Envelope::failure(&e.at(Stage::Session), None)
+        };
+        let source_lang = input.source_lang;
+        let message = input.message;
+        let mut warnings = input.warnings;
+        let mut pivots = Vec::new();
+        if let Some(pivot) = input.pivot {
+            pivots.push(PivotUse {
+                stage: Stage::InputTranslation,
+                source: source_lang.to_owned(),
+                pivot,
+                target: params.target_lang.to_owned()
+            });
+        }
+
+        let context : wa::UnknownType = params.context.unwrap_or(wa::UnknownType::Value(json!({})));
+
+        let started = Instant::now();
+        let mut result = wa_session.send_txt_with_context(&message, context.clone()).await;
+        if let Err(e) = &result {
+            if e.is_session_expired() {
+                Record::warn(format!("WA session {} has expired, renewing session: {}", wa_session.session_id, e.report())).stage(Stage::Session).emit();
+                if let Err(e) = wa_session.renew().await {
+                    return Envelope::failure(&e.at(Stage::Session), Some(wa_session.session_id));
+                }
+                result = wa_session.send_txt_with_context(&message, context).await;
+            }
+        }
+        let mut r = match result {
+            Ok(r) => r,
+            Err(e) => return Envelope::failure(&e.at(Stage::Message), Some(wa_session.session_id))
+        };
+        Record::info("WA return response")
+            .stage(Stage::Message)
+            .latency(started.elapsed())
+            .field("session_id", &wa_session.session_id)
+            .field("text", logger::redact(&message))
+            .emit();
+
+        let mut translations = Vec::new();
+        if source_lang != params.target_lang {
+            // path of each text and whether it shall be translated, response flagged by dialog author is kept as is
+            let mut fields = Vec::new();
+            let mut translation_batch: Vec<&mut String> = Vec::new();
+            for (i, response) in r.output.generic.iter_mut().enumerate() {
+                let flagged = response.no_translate();
+                for (name, text) in response.fields_mut() {
+                    fields.push((format!("output.generic[{}].{}", i, name), !flagged));
+                    if !flagged {
+                        translation_batch.push(text);
+                    }
+                }
+            }
+            let statuses = if self.deadline.remaining() < MIN_TRANSLATION_TIME {
+                Record::warn(format!("Only {:?} left in this turn, skip translating WA response", self.deadline.remaining())).stage(Stage::OutputTranslation).emit();
+                warnings.push(GatewayErr::new(ErrKind::Timeout, "Not enough time left to translate WA response").at(Stage::OutputTranslation));
+                Vec::new()
+            } else {
+                self.translate_output(translation_batch, &params.target_lang, &source_lang, &mut warnings, &mut pivots).await
+            };
+            let mut statuses = statuses.into_iter();
+            translations = fields.into_iter().map(|(field, translatable)| FieldTranslation {
+                field,
+                status: if translatable { statuses.next() } else { None }.unwrap_or(TranslationStatus::Original)
+            }).collect();
+        }
+        // content between no-translate markers is kept as is by Markup, only the markers are removed
+        for (_, text) in r.output.generic.iter_mut().flat_map(wa::ResponseGeneric::fields_mut) {
+            *text = markup::strip_no_translate(text);
+        }
+        let mut envelope = warnings.iter().fold(Envelope::success(wa_session.session_id, r), |envelope, w| envelope.warning(w));
+        if input.detected {
+            envelope = envelope.detected_lang(source_lang);
+        }
+        envelope = pivots.into_iter().fold(envelope, Envelope::pivot);
+        translations.into_iter().fold(envelope, Envelope::translation)
+    }
+}
+
+/// Parse parameters of a turn. Language codes are checked here so bad one never reach WLT.
+fn parse_params(arg: &str) -> Result<Params, GatewayErr> {
+    let params = serde_json::from_str::<Params>(arg).map_err(|e| GatewayErr::config("Missing one or more parameters.").with_source(e))?;
+    let source_lang = params.source_lang.as_ref().filter(|l| *l != AUTO_LANGUAGE);
+    for (name, code) in [("sourceLang", source_lang), ("targetLang", Some(&params.target_lang))] {
+        if let Some(code) = code.filter(|c| !config::is_language_code(c)) {
+            return Err(GatewayErr::config(format!("{} shall be language code such as th or en, found {}", name, code)));
+        }
+    }
+    Ok(params)
+}
+
+/// Main flow that is going to be performed when deployed on Cloud Functions.
+/// It always print one JSON envelope as the last line, even on failure.
+fn main() {
+    logger::set_correlation_id(logger::new_correlation_id());
+    let started = Instant::now();
+    let args = env::args().collect::<Vec<String>>();
+
+    let envelope = if args.len() == 2 {
+        match GatewayConfig::load().and_then(|config| {
+            logger::init(config.log_level);
+            Gateway::new(config)
+        }) {
+            Ok(gateway) => match parse_params(&args[1]) {
+                Ok(params) => {
+                    let envelope = futures::executor::block_on(gateway.turn(params));
+                    gateway.save_cache();
+                    envelope
+                },
+                Err(e) => Envelope::failure(&e.at(Stage::Request), None)
+            },
+            Err(e) => Envelope::failure(&e.at(Stage::Config), None)
+        }
     } else {
-        println!("{}", "{}");
+        Envelope::failure(&GatewayErr::config(format!("Expect exactly one JSON parameter but found {}", args.len().saturating_sub(1))).at(Stage::Request), None)
+    };
+    match &envelope.error {
+        Some(e) => {
+            let record = Record::error(format!("Turn fail with {}: {}", e.code, e.message)).latency(started.elapsed()).field("status", envelope.status);
+            match e.stage {
+                Some(stage) => record.stage(stage).emit(),
+                None => record.emit()
+            }
+        },
+        None => Record::info("Turn complete").latency(started.elapsed()).field("status", envelope.status).field("warnings", envelope.warnings.len()).emit()
     }
+    // stdout carry only the result
+    println!("{}", envelope.to_json());
 }
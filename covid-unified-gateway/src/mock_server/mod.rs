@@ -0,0 +1,472 @@
+//! Local stand-in for Watson Assistant v2 and Watson Language Translator v3.
+//!
+//! [MockServer](struct.MockServer.html) listen on local port and implement only the endpoints
+//! this crate call:
+//! - `POST /v2/assistants/{id}/sessions`
+//! - `DELETE /v2/assistants/{id}/sessions/{session_id}`
+//! - `POST /v2/assistants/{id}/sessions/{session_id}/message`
+//! - `POST /v3/translate`
+//! - `GET /v3/models`
+//! - `POST /v3/identify`
+//!
+//! Reply of message endpoint come from dialog table in [MockConfig](struct.MockConfig.html).
+//! Translation come from translation table, keyed by model id then source text. Table named
+//! `{source}-{target}` is also listed as available model.
+//! [Fault](enum.Fault.html) can be injected per endpoint, either in config or at run time with
+//! [inject](struct.MockServer.html#method.inject).
+//!
+//! Unlike [MockTransport](../utils/mock/struct.MockTransport.html), it speak real HTTP so
+//! the gateway binary can be run end to end by pointing `WA_ENDPOINT` and `WLT_ENDPOINT` at it.
+//! The `mock-watson` binary serve it with config read from JSON file.
+//!
+//! ```
+//! use covid_unified_gateway::mock_server::{Endpoint, Fault, MockConfig, MockServer};
+//!
+//! let config = MockConfig::default()
+//!                 .reply("hello", "Hi, how can I help?")
+//!                 .translation("th-en", "สวัสดี", "hello");
+//! let server = MockServer::start(config).unwrap();
+//! server.inject(Endpoint::Translate, Fault::PartialTranslation { missing: 1 }, Some(1));
+//! println!("WA_ENDPOINT={}", server.url());
+//! ```
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Endpoint of Watson API that is served by [MockServer](struct.MockServer.html).
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Endpoint {
+    CreateSession,
+    DeleteSession,
+    Message,
+    Translate,
+    ListModels,
+    Identify
+}
+
+/// Failure to be injected into reply.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum Fault {
+    /// Reply with given status and Watson style error body.
+    Status { status: u16 },
+    /// Drop every session as if they have expired. The message reply `404 Invalid Session`.
+    SessionExpired,
+    /// Reply with `missing` less translations than number of text requested.
+    PartialTranslation { missing: usize },
+    /// Reply `200` with body that is not JSON.
+    Malformed,
+    /// Delay the reply by `ms` millisecond then reply normally.
+    Latency { ms: u64 }
+}
+
+/// Fault that apply to `endpoint`. It apply to the next `times` requests or every request if `times` is `None`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct FaultRule {
+    pub endpoint: Endpoint,
+    #[serde(flatten)]
+    pub fault: Fault,
+    #[serde(default)]
+    pub times: Option<usize>
+}
+
+/// Entry of dialog table.
+#[derive(Clone, Debug, Deserialize)]
+pub struct DialogRule {
+    /// The rule match if user input contains this text, case insensitive.
+    pub contains: String,
+    /// `output.generic` of the reply
+    pub generic: Vec<Value>
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct MockConfig {
+    /// Dialog table. The first matched rule win.
+    pub dialog: Vec<DialogRule>,
+    /// `output.generic` of the reply when no rule match
+    pub anything_else: Vec<Value>,
+    /// Translation table. Model id -> source text -> translated text.
+    /// Text that isn't in the table of known model is returned as is.
+    /// Unknown model reply `404 Model not found`.
+    pub translations: HashMap<String, HashMap<String, String>>,
+    /// Language identified for each text. Text that isn't in the table is identified as `en`
+    /// with low confidence.
+    pub languages: HashMap<String, String>,
+    /// Fault injected from the start
+    pub faults: Vec<FaultRule>
+}
+
+impl MockConfig {
+    /// Load config from JSON file.
+    pub fn load(path: &str) -> Result<MockConfig, String> {
+        let content = std::fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path, e))?;
+        serde_json::from_str(&content).map_err(|e| format!("Cannot parse {}: {}", path, e))
+    }
+
+    /// Add dialog rule that reply single text response to input containing `contains`.
+    pub fn reply(self, contains: &str, text: &str) -> MockConfig {
+        self.dialog(contains, vec![json!({"response_type": "text", "text": text})])
+    }
+
+    /// Add dialog rule that reply given `output.generic` to input containing `contains`.
+    pub fn dialog(mut self, contains: &str, generic: Vec<Value>) -> MockConfig {
+        self.dialog.push(DialogRule {
+            contains: contains.to_owned(),
+            generic
+        });
+        self
+    }
+
+    /// Add translation of `source` text by `model_id`.
+    pub fn translation(mut self, model_id: &str, source: &str, target: &str) -> MockConfig {
+        self.translations.entry(model_id.to_owned()).or_default().insert(source.to_owned(), target.to_owned());
+        self
+    }
+
+    /// Identify `text` as `language`.
+    pub fn language(mut self, text: &str, language: &str) -> MockConfig {
+        self.languages.insert(text.to_owned(), language.to_owned());
+        self
+    }
+
+    /// Add model that return every text as is.
+    pub fn model(mut self, model_id: &str) -> MockConfig {
+        self.translations.entry(model_id.to_owned()).or_default();
+        self
+    }
+
+    /// Add fault to the rule.
+    pub fn fault(mut self, endpoint: Endpoint, fault: Fault, times: Option<usize>) -> MockConfig {
+        self.faults.push(FaultRule {
+            endpoint,
+            fault,
+            times
+        });
+        self
+    }
+}
+
+/// Request received by [MockServer](struct.MockServer.html).
+#[derive(Clone, Debug)]
+pub struct ReceivedRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String
+}
+
+#[derive(Debug, Default)]
+struct State {
+    config: MockConfig,
+    sessions: Vec<String>,
+    session_count: usize,
+    requests: Vec<(Endpoint, ReceivedRequest)>
+}
+
+impl State {
+    /// Take next fault of given endpoint, if any.
+    fn take_fault(&mut self, endpoint: Endpoint) -> Option<Fault> {
+        let rule = self.config.faults.iter_mut().find(|r| r.endpoint == endpoint && r.times != Some(0))?;
+        if let Some(ref mut times) = rule.times {
+            *times -= 1;
+        }
+        Some(rule.fault.clone())
+    }
+}
+
+/// HTTP server that mimic Watson Assistant and Watson Language Translator.
+/// It stop when dropped.
+#[derive(Debug)]
+pub struct MockServer {
+    addr: SocketAddr,
+    url: String,
+    state: Arc<Mutex<State>>,
+    shutdown: Arc<AtomicBool>,
+    handle: Option<thread::JoinHandle<()>>
+}
+
+impl MockServer {
+    /// Start server on random local port.
+    pub fn start(config: MockConfig) -> io::Result<MockServer> {
+        MockServer::bind("127.0.0.1:0", config)
+    }
+
+    /// Start server on given address.
+    pub fn bind(addr: &str, config: MockConfig) -> io::Result<MockServer> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(State {
+            config,
+            ..State::default()
+        }));
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let server_state = state.clone();
+        let server_shutdown = shutdown.clone();
+        let handle = thread::spawn(move || {
+            for stream in listener.incoming() {
+                if server_shutdown.load(Ordering::SeqCst) {
+                    break;
+                }
+                if let Ok(stream) = stream {
+                    let state = server_state.clone();
+                    // each connection has its own thread so latency of one doesn't block the others
+                    thread::spawn(move || {
+                        if let Err(e) = handle_connection(stream, &state) {
+                            log_warn!("Mock server fail to handle connection: {}", e);
+                        }
+                    });
+                }
+            }
+        });
+        Ok(MockServer {
+            addr,
+            url: format!("http://{}", addr),
+            state,
+            shutdown,
+            handle: Some(handle)
+        })
+    }
+
+    /// Base url of the server. It is both `WA_ENDPOINT` and `WLT_ENDPOINT`.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Inject fault to the next `times` requests of `endpoint`, or every request if `times` is `None`.
+    /// Fault injected earlier take precedence.
+    pub fn inject(&self, endpoint: Endpoint, fault: Fault, times: Option<usize>) {
+        self.state.lock().unwrap().config.faults.push(FaultRule {
+            endpoint,
+            fault,
+            times
+        });
+    }
+
+    /// Drop every session so the next message to them reply `404 Invalid Session`.
+    pub fn expire_sessions(&self) {
+        self.state.lock().unwrap().sessions.clear();
+    }
+
+    /// Session that are currently active.
+    pub fn sessions(&self) -> Vec<String> {
+        self.state.lock().unwrap().sessions.clone()
+    }
+
+    /// Every request received by given endpoint, in order.
+    pub fn requests(&self, endpoint: Endpoint) -> Vec<ReceivedRequest> {
+        self.state.lock().unwrap().requests.iter().filter(|(e, _)| *e == endpoint).map(|(_, r)| r.clone()).collect()
+    }
+
+    /// Block current thread until the server stop.
+    pub fn join(mut self) {
+        if let Some(handle) = self.handle.take() {
+            handle.join().ok();
+        }
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        // wake up the accept loop so it see the shutdown flag
+        TcpStream::connect(self.addr).ok();
+        if let Some(handle) = self.handle.take() {
+            handle.join().ok();
+        }
+    }
+}
+
+fn handle_connection(stream: TcpStream, state: &Mutex<State>) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line)? == 0 {
+        // connection opened only to wake up the server
+        return Ok(());
+    }
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_owned();
+    let target = parts.next().unwrap_or_default().to_owned();
+
+    let mut headers = Vec::new();
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line == "\r\n" {
+            break;
+        }
+        if let Some((name, value)) = line.trim_end().split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+            headers.push((name.to_owned(), value.trim().to_owned()));
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    let request = ReceivedRequest {
+        method,
+        path: target,
+        headers,
+        body: String::from_utf8_lossy(&body).into_owned()
+    };
+    let (status, body) = respond(request, state);
+    let mut stream = stream;
+    write!(stream, "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nX-Global-Transaction-Id: mock-{}\r\nConnection: close\r\n\r\n", status, body.len(), status)?;
+    stream.write_all(body.as_bytes())?;
+    stream.flush()
+}
+
+fn error_body(status: u16, message: &str) -> (u16, String) {
+    (status, json!({"code": status, "error": message}).to_string())
+}
+
+/// Route request and build status and body of the reply.
+fn respond(request: ReceivedRequest, state: &Mutex<State>) -> (u16, String) {
+    let (path, query) = request.path.split_once('?').unwrap_or((request.path.as_str(), ""));
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let endpoint = match (request.method.as_str(), segments.as_slice()) {
+        ("POST", ["v2", "assistants", _, "sessions"]) => Endpoint::CreateSession,
+        ("DELETE", ["v2", "assistants", _, "sessions", _]) => Endpoint::DeleteSession,
+        ("POST", ["v2", "assistants", _, "sessions", _, "message"]) => Endpoint::Message,
+        ("POST", ["v3", "translate"]) => Endpoint::Translate,
+        ("GET", ["v3", "models"]) => Endpoint::ListModels,
+        ("POST", ["v3", "identify"]) => Endpoint::Identify,
+        _ => return error_body(404, "Resource not found")
+    };
+    let session_id = segments.get(4).map(|s| s.to_string());
+    let mut guard = state.lock().unwrap();
+    guard.requests.push((endpoint, request.clone()));
+
+    if !query.split('&').any(|p| p.starts_with("version=")) {
+        return error_body(400, "Missing required query parameter `version`");
+    }
+    if !request.headers.iter().any(|(n, _)| n.eq_ignore_ascii_case("authorization")) {
+        return error_body(401, "Unauthorized");
+    }
+
+    match guard.take_fault(endpoint) {
+        Some(Fault::Status { status }) => return error_body(status, &format!("Injected error {}", status)),
+        Some(Fault::Malformed) => return (200, "{\"output\": <malformed".to_owned()),
+        Some(Fault::SessionExpired) => guard.sessions.clear(),
+        Some(Fault::Latency { ms }) => {
+            // don't hold the lock while sleeping
+            drop(guard);
+            thread::sleep(Duration::from_millis(ms));
+            guard = state.lock().unwrap();
+        },
+        Some(Fault::PartialTranslation { missing }) => return translate(&guard.config, &request.body, missing),
+        None => {}
+    }
+
+    match endpoint {
+        Endpoint::CreateSession => {
+            guard.session_count += 1;
+            let id = format!("mock-session-{}", guard.session_count);
+            guard.sessions.push(id.to_owned());
+            (201, json!({"session_id": id}).to_string())
+        },
+        Endpoint::DeleteSession => {
+            let id = session_id.unwrap_or_default();
+            match guard.sessions.iter().position(|s| *s == id) {
+                Some(i) => {
+                    guard.sessions.remove(i);
+                    (200, "{}".to_owned())
+                },
+                None => error_body(404, "Invalid Session")
+            }
+        },
+        Endpoint::Message => {
+            let id = session_id.unwrap_or_default();
+            if !guard.sessions.contains(&id) {
+                return error_body(404, "Invalid Session");
+            }
+            message(&guard.config, &request.body)
+        },
+        Endpoint::Translate => translate(&guard.config, &request.body, 0),
+        Endpoint::ListModels => list_models(&guard.config),
+        Endpoint::Identify => identify(&guard.config, &request.body)
+    }
+}
+
+/// Ranked languages of `text`. Known text is identified with high confidence, followed by `en`.
+fn identify(config: &MockConfig, text: &str) -> (u16, String) {
+    let languages = match config.languages.get(text.trim()) {
+        Some(language) if language != "en" => json!([{"language": language, "confidence": 0.95}, {"language": "en", "confidence": 0.03}]),
+        Some(_) => json!([{"language": "en", "confidence": 0.95}]),
+        None => json!([{"language": "en", "confidence": 0.4}])
+    };
+    (200, json!({"languages": languages}).to_string())
+}
+
+/// Every translation table named `{source}-{target}` is listed as available model.
+fn list_models(config: &MockConfig) -> (u16, String) {
+    let mut models: Vec<Value> = config.translations
+                                       .keys()
+                                       .filter_map(|id| id.split_once('-').map(|(source, target)| json!({
+                                           "model_id": id,
+                                           "source": source,
+                                           "target": target,
+                                           "status": "available"
+                                       })))
+                                       .collect();
+    models.sort_by_key(|m| m["model_id"].to_string());
+    (200, json!({"models": models}).to_string())
+}
+
+fn message(config: &MockConfig, body: &str) -> (u16, String) {
+    let input: Value = match serde_json::from_str(body) {
+        Ok(v) => v,
+        Err(_) => return error_body(400, "Invalid JSON input")
+    };
+    let text = input["input"]["text"].as_str().unwrap_or_default().to_lowercase();
+    let generic = config.dialog
+                        .iter()
+                        .find(|r| text.contains(&r.contains.to_lowercase()))
+                        .map_or(&config.anything_else, |r| &r.generic);
+    let mut reply = json!({
+        "output": {
+            "generic": generic,
+            "intents": [],
+            "entities": []
+        }
+    });
+    if !input["context"].is_null() {
+        reply["context"] = input["context"].clone();
+    }
+    (200, reply.to_string())
+}
+
+fn translate(config: &MockConfig, body: &str, missing: usize) -> (u16, String) {
+    let input: Value = match serde_json::from_str(body) {
+        Ok(v) => v,
+        Err(_) => return error_body(400, "Invalid JSON input")
+    };
+    let model_id = input["model_id"].as_str().unwrap_or_default();
+    let table = match config.translations.get(model_id) {
+        Some(t) => t,
+        None => return error_body(404, "Model not found.")
+    };
+    let text: Vec<&str> = input["text"].as_array().map_or_else(Vec::new, |t| t.iter().filter_map(|s| s.as_str()).collect());
+    let keep = text.len().saturating_sub(missing);
+    let translations: Vec<Value> = text.iter().take(keep).map(|t| json!({"translation": table.get(*t).map_or(*t, |s| s.as_str())})).collect();
+    let word_count: usize = text.iter().map(|t| t.split_whitespace().count()).sum();
+    let character_count: usize = text.iter().map(|t| t.chars().count()).sum();
+    (200, json!({
+        "word_count": word_count,
+        "character_count": character_count,
+        "translations": translations
+    }).to_string())
+}
+
+#[cfg(test)]
+mod test;
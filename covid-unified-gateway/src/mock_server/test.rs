@@ -0,0 +1,158 @@
+use super::*;
+use crate::config::{ChunkConfig, ServiceConfig, WaConfig, WltConfig};
+use crate::error::{ErrKind, GatewayErr};
+use crate::utils::{RetryPolicy, Upstream};
+use crate::wa::WASession;
+use crate::wlt::WLTTranslationRequest;
+
+const VERSION: &str = "2020-04-01";
+
+fn upstream() -> Upstream {
+    Upstream::new("key".into()).retry(RetryPolicy::no_retry())
+}
+
+fn new_session(server: &MockServer) -> Result<WASession, GatewayErr> {
+    futures::executor::block_on(WASession::new(&WaConfig {
+        service: ServiceConfig::new(server.url(), VERSION, "key"),
+        assistant_id: "assistant".to_owned()
+    }, upstream()))
+}
+
+fn translate(server: &MockServer, text: &[&str], source: &str, target: &str) -> Result<crate::wlt::WLTTranslationResponse, GatewayErr> {
+    futures::executor::block_on(WLTTranslationRequest::new(&WltConfig::new(ServiceConfig::new(server.url(), VERSION, "key")), upstream(), text, &format!("{}-{}", source, target)).send())
+}
+
+#[test]
+fn test_session_lifecycle() {
+    let server = MockServer::start(MockConfig::default().reply("hello", "Hi there")).unwrap();
+    let session = new_session(&server).unwrap();
+    assert_eq!(server.sessions(), vec![session.session_id.to_owned()]);
+
+    let response = futures::executor::block_on(session.send_txt("Hello bot")).unwrap();
+    assert_eq!(response.output.generic[0].text.as_deref(), Some("Hi there"));
+
+    futures::executor::block_on(session.close()).unwrap();
+    assert!(server.sessions().is_empty());
+    assert_eq!(server.requests(Endpoint::Message).len(), 1);
+}
+
+#[test]
+fn test_anything_else() {
+    let config = MockConfig {
+        anything_else: vec![json!({"response_type": "text", "text": "I don't understand"})],
+        ..MockConfig::default()
+    };
+    let server = MockServer::start(config).unwrap();
+    let session = new_session(&server).unwrap();
+    let response = futures::executor::block_on(session.send_txt("???")).unwrap();
+    assert_eq!(response.output.generic[0].text.as_deref(), Some("I don't understand"));
+}
+
+#[test]
+fn test_session_expired() {
+    let server = MockServer::start(MockConfig::default().reply("", "ok")).unwrap();
+    let mut session = new_session(&server).unwrap();
+    server.inject(Endpoint::Message, Fault::SessionExpired, Some(1));
+
+    match futures::executor::block_on(session.send_txt("hi")) {
+        Err(e) => assert!(e.is_session_expired()),
+        Ok(r) => panic!("Expect expired session but got {:?}", r)
+    }
+    futures::executor::block_on(session.renew()).unwrap();
+    assert!(futures::executor::block_on(session.send_txt("hi")).is_ok());
+}
+
+#[test]
+fn test_translate_table() {
+    let server = MockServer::start(MockConfig::default().translation("th-en", "สวัสดี", "hello")).unwrap();
+    let result = translate(&server, &["สวัสดี", "unknown"], "th", "en").unwrap();
+    let translated: Vec<&str> = result.translations.iter().map(|t| t.translation.as_str()).collect();
+    assert_eq!(translated, vec!["hello", "unknown"]);
+    assert_eq!(result.character_count, 13);
+}
+
+#[test]
+fn test_translate_in_chunks() {
+    let server = MockServer::start(MockConfig::default().translation("th-en", "สวัสดี", "hello").translation("th-en", "ลาก่อน", "goodbye")).unwrap();
+    let mut config = WltConfig::new(ServiceConfig::new(server.url(), VERSION, "key"));
+    config.chunk = ChunkConfig { max_texts: 2, max_bytes: 1024 };
+    let text = ["สวัสดี", "one two", "ลาก่อน", "three", "สวัสดี"];
+    let result = futures::executor::block_on(WLTTranslationRequest::new(&config, upstream(), &text, "th-en").send()).unwrap();
+    let translated: Vec<&str> = result.translations.iter().map(|t| t.translation.as_str()).collect();
+    assert_eq!(translated, vec!["hello", "one two", "goodbye", "three", "hello"]);
+    assert_eq!(result.word_count, 6);
+    assert_eq!(result.character_count, 30);
+    assert_eq!(server.requests(Endpoint::Translate).len(), 3);
+}
+
+#[test]
+fn test_unknown_model() {
+    let server = MockServer::start(MockConfig::default()).unwrap();
+    let err = translate(&server, &["hi"], "xx", "yy").unwrap_err();
+    assert_eq!(err.http().map(|e| e.status), Some(404));
+}
+
+#[test]
+fn test_partial_translation() {
+    let server = MockServer::start(MockConfig::default().model("en-th")).unwrap();
+    server.inject(Endpoint::Translate, Fault::PartialTranslation { missing: 1 }, Some(1));
+    assert_eq!(translate(&server, &["a", "b", "c"], "en", "th").unwrap().translations.len(), 2);
+    // fault only apply once
+    assert_eq!(translate(&server, &["a", "b", "c"], "en", "th").unwrap().translations.len(), 3);
+}
+
+#[test]
+fn test_malformed_and_status_fault() {
+    let config = MockConfig::default().model("en-th").fault(Endpoint::Translate, Fault::Malformed, Some(1)).fault(Endpoint::CreateSession, Fault::Status { status: 500 }, None);
+    let server = MockServer::start(config).unwrap();
+    assert_eq!(translate(&server, &["a"], "en", "th").unwrap_err().kind(), ErrKind::Decode);
+    assert_eq!(new_session(&server).unwrap_err().http().map(|e| e.status), Some(500));
+}
+
+#[test]
+fn test_identify_and_list_models() {
+    let server = MockServer::start(MockConfig::default().language("สวัสดี", "th").model("th-en").model("custom")).unwrap();
+    let config = WltConfig::new(ServiceConfig::new(server.url(), VERSION, "key"));
+    let languages = futures::executor::block_on(crate::wlt::identify(&config, &upstream(), "สวัสดี")).unwrap();
+    assert_eq!(languages[0].language, "th");
+    let languages = futures::executor::block_on(crate::wlt::identify(&config, &upstream(), "unknown")).unwrap();
+    assert_eq!(languages[0].language, "en");
+
+    let models = futures::executor::block_on(crate::wlt::list_models(&config, &upstream())).unwrap();
+    let ids: Vec<&str> = models.iter().map(|m| m.model_id.as_str()).collect();
+    assert_eq!(ids, vec!["th-en"]);
+    assert_eq!(server.requests(Endpoint::Identify).len(), 2);
+}
+
+#[test]
+fn test_require_version_and_auth() {
+    let server = MockServer::start(MockConfig::default()).unwrap();
+    let (status, _) = respond(ReceivedRequest {
+        method: "POST".to_owned(),
+        path: "/v2/assistants/a/sessions".to_owned(),
+        headers: vec![("Authorization".to_owned(), "Basic x".to_owned())],
+        body: String::new()
+    }, &server.state);
+    assert_eq!(status, 400);
+    let (status, _) = respond(ReceivedRequest {
+        method: "POST".to_owned(),
+        path: "/v2/assistants/a/sessions?version=1".to_owned(),
+        headers: Vec::new(),
+        body: String::new()
+    }, &server.state);
+    assert_eq!(status, 401);
+}
+
+#[test]
+fn test_load_config() {
+    let config: MockConfig = serde_json::from_value(json!({
+        "dialog": [{"contains": "hello", "generic": [{"response_type": "text", "text": "Hi"}]}],
+        "translations": {"th-en": {"สวัสดี": "hello"}},
+        "languages": {"สวัสดี": "th"},
+        "faults": [{"endpoint": "translate", "type": "partial_translation", "missing": 1, "times": 2}]
+    })).unwrap();
+    assert_eq!(config.dialog[0].contains, "hello");
+    assert_eq!(config.languages["สวัสดี"], "th");
+    assert_eq!(config.faults[0].fault, Fault::PartialTranslation { missing: 1 });
+    assert_eq!(config.faults[0].times, Some(2));
+}
@@ -0,0 +1,191 @@
+//! Authorization for Watson services.
+//!
+//! [Authenticator::Basic](enum.Authenticator.html#variant.Basic) send API key as basic auth
+//! with user name `apikey` on every request.
+//! [Authenticator::Iam](enum.Authenticator.html#variant.Iam) exchange API key for bearer token
+//! at IAM token endpoint. The token is cached until shortly before it expire.
+//! Cloning [IamAuthenticator](struct.IamAuthenticator.html) share the same token cache.
+//! Token is requested within the timeout, deadline and retry policy of the [Upstream](../struct.Upstream.html)
+//! that need it. Request rejected with `401` discard the cached token and is sent once more with new token.
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use futures::lock::Mutex;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use super::transport::{HttpRequest, IsahcTransport, Method, Transport};
+use super::{execute, CurlErr, Timeout, Upstream};
+
+/// Public IBM Cloud IAM token endpoint.
+pub const DEFAULT_IAM_URL: &str = "https://iam.cloud.ibm.com/identity/token";
+
+/// Token is considered expired this long before IAM say it is.
+/// It prevent token from expiring while the request is in flight.
+const EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Debug)]
+pub enum Authenticator {
+    /// Send API key as basic auth.
+    Basic(String),
+    /// Send bearer token obtained from IAM.
+    Iam(IamAuthenticator)
+}
+
+impl From<String> for Authenticator {
+    fn from(api_key: String) -> Self {
+        Authenticator::Basic(api_key)
+    }
+}
+
+impl From<&str> for Authenticator {
+    fn from(api_key: &str) -> Self {
+        Authenticator::Basic(api_key.to_owned())
+    }
+}
+
+impl From<IamAuthenticator> for Authenticator {
+    fn from(iam: IamAuthenticator) -> Self {
+        Authenticator::Iam(iam)
+    }
+}
+
+impl Authenticator {
+    /// Attach `Authorization` header to given request.
+    /// For IAM, it may request new token within `upstream` timeout, deadline and retry policy
+    /// if there's no valid cached token.
+    pub(super) async fn authorize(&self, request: HttpRequest, upstream: &Upstream) -> Result<HttpRequest, CurlErr> {
+        match self {
+            Authenticator::Basic(api_key) => {
+                let credentials = BASE64.encode(format!("apikey:{}", api_key));
+                Ok(request.header("Authorization", &format!("Basic {}", credentials)))
+            },
+            Authenticator::Iam(iam) => {
+                let token = iam.token_for(upstream).await?;
+                Ok(request.header("Authorization", &format!("Bearer {}", token)))
+            }
+        }
+    }
+
+    /// Discard credential that server has rejected. It return whether new credential can be obtained,
+    /// which is only the case for IAM.
+    pub(super) async fn invalidate(&self) -> bool {
+        match self {
+            Authenticator::Basic(_) => false,
+            Authenticator::Iam(iam) => {
+                iam.invalidate().await;
+                true
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64
+}
+
+#[derive(Debug)]
+struct CachedToken {
+    access_token: String,
+    valid_until: Instant
+}
+
+/// Exchange API key for IAM bearer token and cache it.
+#[derive(Clone, Debug)]
+pub struct IamAuthenticator {
+    api_key: String,
+    iam_url: String,
+    transport: Arc<dyn Transport>,
+    cache: Arc<Mutex<Option<CachedToken>>>
+}
+
+impl IamAuthenticator {
+    /// Construct authenticator that use public IBM Cloud IAM endpoint.
+    pub fn new(api_key: String) -> IamAuthenticator {
+        IamAuthenticator::with_url(api_key, DEFAULT_IAM_URL.to_owned())
+    }
+
+    /// Construct authenticator that use given IAM token endpoint.
+    /// It is for private or dedicated deployment, or for testing against a stub server.
+    pub fn with_url(api_key: String, iam_url: String) -> IamAuthenticator {
+        IamAuthenticator {
+            api_key,
+            iam_url,
+            transport: Arc::new(IsahcTransport),
+            cache: Arc::new(Mutex::new(None))
+        }
+    }
+
+    /// Request token through given transport instead of network.
+    pub fn transport<T: Transport + 'static>(mut self, transport: T) -> IamAuthenticator {
+        self.transport = Arc::new(transport);
+        self
+    }
+
+    /// Return cached bearer token or request new one with default timeout and retry policy
+    /// if it is missing or about to expire.
+    pub async fn token(&self) -> Result<String, CurlErr> {
+        self.token_for(&Upstream::new(self.clone().into())).await
+    }
+
+    /// Return cached bearer token or request new one within `upstream` timeout, deadline and retry policy.
+    ///
+    /// The cache is locked while requesting new token so concurrent callers wait for
+    /// the same token instead of each sending their own request to IAM.
+    pub async fn token_for(&self, upstream: &Upstream) -> Result<String, CurlErr> {
+        let mut cache = self.cache.lock().await;
+        if let Some(cached) = cache.as_ref() {
+            if Instant::now() < cached.valid_until {
+                return Ok(cached.access_token.to_owned());
+            }
+        }
+
+        log_debug!("Requesting new IAM token");
+        let fetched_at = Instant::now();
+        let token = upstream.retry.run(upstream.deadline, || self.request_token(upstream)).await?;
+        let lifetime = Duration::from_secs(token.expires_in);
+        // short lived token shall still be reused for half of its life time
+        let margin = EXPIRY_MARGIN.min(lifetime / 2);
+        *cache = Some(CachedToken {
+            access_token: token.access_token.to_owned(),
+            valid_until: fetched_at + lifetime - margin
+        });
+        Ok(token.access_token)
+    }
+
+    /// Discard cached token so the next call request new one.
+    pub async fn invalidate(&self) {
+        *self.cache.lock().await = None;
+    }
+
+    async fn request_token(&self, upstream: &Upstream) -> Result<TokenResponse, CurlErr> {
+        let body = format!("grant_type={}&apikey={}", form_encode("urn:ibm:params:oauth:grant-type:apikey"), form_encode(&self.api_key));
+        let timeout = Timeout {
+            connect: upstream.timeout.connect,
+            total: upstream.total_timeout()?
+        };
+        let request = HttpRequest::new(Method::Post, &self.iam_url, timeout)
+                                  .header("Content-Type", "application/x-www-form-urlencoded")
+                                  .header("Accept", "application/json")
+                                  .body(body.into_bytes());
+        let buf = execute(self.transport.as_ref(), request).await?;
+        serde_json::from_slice(&buf).map_err(|e| {
+            log_error!("Fail to deserialize IAM token: {}", e);
+            CurlErr::IncompatibleResultData
+        })
+    }
+}
+
+/// Percent encode value for `application/x-www-form-urlencoded` body.
+fn form_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for b in value.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(b as char),
+            _ => encoded.push_str(&format!("%{:02X}", b))
+        }
+    }
+    encoded
+}
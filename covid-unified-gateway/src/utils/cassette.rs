@@ -0,0 +1,234 @@
+//! Record and replay HTTP interactions.
+//!
+//! [CassetteTransport::record](struct.CassetteTransport.html#method.record) wrap another
+//! [Transport](../transport/trait.Transport.html). Every request and its response is appended
+//! to a cassette file. Credentials, i.e. `Authorization` header, API key in IAM request and
+//! tokens in IAM response, are redacted before it is written.
+//!
+//! [CassetteTransport::replay](struct.CassetteTransport.html#method.replay) serve recorded
+//! responses without touching network. A request is matched with the first unused
+//! interaction of the same method, url and body, so concurrent requests may be replayed in different
+//! order than they were recorded. Body is compared after redaction, and JSON body is compared by value
+//! so key order and spacing don't matter. Request with unexpected payload therefore fail to replay, at once
+//! with `CurlErr::NotRecorded` that is never retried.
+
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use crate::logger::{redact, Record};
+use super::transport::{HttpRequest, HttpResponse, Method, Transport};
+use super::CurlErr;
+
+/// Value that replace every credential in cassette.
+pub const REDACTED: &str = "REDACTED";
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RecordedResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Interaction {
+    pub request: RecordedRequest,
+    pub response: RecordedResponse
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Cassette {
+    pub interactions: Vec<Interaction>
+}
+
+impl Cassette {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Cassette, String> {
+        let content = fs::read_to_string(path.as_ref()).map_err(|e| format!("Cannot read cassette {:?}: {}", path.as_ref(), e))?;
+        serde_json::from_str(&content).map_err(|e| format!("Cannot parse cassette {:?}: {}", path.as_ref(), e))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let content = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(path.as_ref(), content).map_err(|e| format!("Cannot write cassette {:?}: {}", path.as_ref(), e))
+    }
+}
+
+#[derive(Debug)]
+enum Mode {
+    Record {
+        inner: Arc<dyn Transport>,
+        path: PathBuf
+    },
+    /// Serve recorded interactions
+    Replay
+}
+
+#[derive(Debug)]
+struct State {
+    cassette: Cassette,
+    used: Vec<bool>
+}
+
+/// Transport that either record interactions of inner transport or replay recorded one.
+/// Clone share the same cassette so WA and WLT can be recorded into one file.
+#[derive(Clone, Debug)]
+pub struct CassetteTransport {
+    mode: Arc<Mode>,
+    state: Arc<Mutex<State>>
+}
+
+impl CassetteTransport {
+    /// Send request through `inner` and append each interaction to cassette at `path`.
+    /// Existing cassette is overwritten.
+    pub fn record<T: Transport + 'static, P: Into<PathBuf>>(inner: T, path: P) -> CassetteTransport {
+        CassetteTransport {
+            mode: Arc::new(Mode::Record {
+                inner: Arc::new(inner),
+                path: path.into()
+            }),
+            state: Arc::new(Mutex::new(State {
+                cassette: Cassette::default(),
+                used: Vec::new()
+            }))
+        }
+    }
+
+    /// Serve responses recorded in cassette at `path`.
+    pub fn replay<P: AsRef<Path>>(path: P) -> Result<CassetteTransport, String> {
+        let cassette = Cassette::load(path)?;
+        let used = vec![false; cassette.interactions.len()];
+        Ok(CassetteTransport {
+            mode: Arc::new(Mode::Replay),
+            state: Arc::new(Mutex::new(State {
+                cassette,
+                used
+            }))
+        })
+    }
+
+    /// Number of recorded interactions that haven't been replayed.
+    pub fn remaining(&self) -> usize {
+        self.state.lock().unwrap().used.iter().filter(|u| !**u).count()
+    }
+
+    fn replay_response(&self, request: &HttpRequest) -> Result<HttpResponse, CurlErr> {
+        let mut state = self.state.lock().unwrap();
+        let State { cassette, used } = &mut *state;
+        let body = redact_request(request).body;
+        let found = cassette.interactions.iter().enumerate().find(|(i, interaction)| {
+            !used[*i] && interaction.request.method == request.method.as_str() && interaction.request.url == request.url
+                      && same_body(&interaction.request.body, &body)
+        });
+        match found {
+            Some((i, interaction)) => {
+                used[i] = true;
+                Ok(HttpResponse {
+                    status: interaction.response.status,
+                    headers: interaction.response.headers.clone(),
+                    body: interaction.response.body.as_bytes().to_vec()
+                })
+            },
+            None => {
+                // body has user message and WA response, so it's kept only at debug level
+                Record::warn(format!("No recorded interaction for {} {}", request.method.as_str(), request.url))
+                    .field("body", redact(&body))
+                    .emit();
+                Err(CurlErr::NotRecorded)
+            }
+        }
+    }
+
+    fn append(&self, path: &Path, interaction: Interaction) {
+        let mut state = self.state.lock().unwrap();
+        state.cassette.interactions.push(interaction);
+        state.used.push(true);
+        // save after each interaction so partial recording survive a crash
+        if let Err(e) = state.cassette.save(path) {
+            log_error!("{}", e);
+        }
+    }
+}
+
+impl Transport for CassetteTransport {
+    fn send(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse, CurlErr>> {
+        Box::pin(async move {
+            match self.mode.as_ref() {
+                Mode::Replay => self.replay_response(&request),
+                Mode::Record { inner, path } => {
+                    let recorded_request = redact_request(&request);
+                    let response = inner.send(request).await?;
+                    self.append(path, Interaction {
+                        request: recorded_request,
+                        response: redact_response(&response)
+                    });
+                    Ok(response)
+                }
+            }
+        })
+    }
+}
+
+/// Whether recorded body and body of request are the same. JSON is compared by value.
+fn same_body(recorded: &str, body: &str) -> bool {
+    match (serde_json::from_str::<serde_json::Value>(recorded), serde_json::from_str::<serde_json::Value>(body)) {
+        (Ok(recorded), Ok(body)) => recorded == body,
+        _ => recorded == body
+    }
+}
+
+fn redact_request(request: &HttpRequest) -> RecordedRequest {
+    let headers = request.headers.iter().map(|(name, value)| {
+        if name.eq_ignore_ascii_case("Authorization") {
+            (name.to_owned(), REDACTED.to_owned())
+        } else {
+            (name.to_owned(), value.to_owned())
+        }
+    }).collect();
+    let mut body = String::from_utf8_lossy(&request.body).into_owned();
+    if request.method == Method::Post && request.get_header("Content-Type") == Some("application/x-www-form-urlencoded") {
+        // IAM token request carry API key in body
+        body = body.split('&').map(|pair| {
+            if pair.starts_with("apikey=") {
+                format!("apikey={}", REDACTED)
+            } else {
+                pair.to_owned()
+            }
+        }).collect::<Vec<String>>().join("&");
+    }
+    RecordedRequest {
+        method: request.method.as_str().to_owned(),
+        url: request.url.to_owned(),
+        headers,
+        body
+    }
+}
+
+fn redact_response(response: &HttpResponse) -> RecordedResponse {
+    let mut body = String::from_utf8_lossy(&response.body).into_owned();
+    if let Ok(serde_json::Value::Object(mut obj)) = serde_json::from_str::<serde_json::Value>(&body) {
+        let mut redacted = false;
+        for key in ["access_token", "refresh_token"].iter() {
+            if let Some(v) = obj.get_mut(*key) {
+                *v = serde_json::Value::String(REDACTED.to_owned());
+                redacted = true;
+            }
+        }
+        if redacted {
+            body = serde_json::Value::Object(obj).to_string();
+        }
+    }
+    RecordedResponse {
+        status: response.status,
+        headers: response.headers.clone(),
+        body
+    }
+}
@@ -0,0 +1,189 @@
+//! In-memory [Transport](../transport/trait.Transport.html) for offline testing.
+//!
+//! Script reply for each route with [on](struct.MockTransport.html#method.on).
+//! A route is HTTP method and a fragment of url. Replies of the same route are
+//! served in order that they are scripted. The last reply is repeated once the others are used.
+//!
+//! ```
+//! use covid_unified_gateway::utils::Upstream;
+//! use covid_unified_gateway::utils::mock::{MockReply, MockTransport};
+//! use serde_json::json;
+//! use std::time::Duration;
+//!
+//! let mock = MockTransport::new()
+//!             .on("POST", "/sessions?", MockReply::json(200, json!({"session_id": "s1"})))
+//!             .on("POST", "/message", MockReply::status(503))
+//!             .on("POST", "/message", MockReply::json(200, json!({"output": {"generic": []}})).latency(Duration::from_millis(50)));
+//! let upstream = Upstream::new("apikey".into()).transport(mock.clone());
+//! ```
+//!
+//! Every request received is recorded and can be inspected with
+//! [requests](struct.MockTransport.html#method.requests).
+
+use futures::future::BoxFuture;
+use futures_timer::Delay;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use super::transport::{HttpRequest, HttpResponse, Transport};
+use super::CurlErr;
+
+#[derive(Clone, Debug)]
+enum Outcome {
+    Respond(HttpResponse),
+    RequestFail,
+    Timeout
+}
+
+/// Scripted reply of [MockTransport](struct.MockTransport.html).
+#[derive(Clone, Debug)]
+pub struct MockReply {
+    outcome: Outcome,
+    latency: Duration
+}
+
+impl MockReply {
+    /// Reply with given status and JSON body.
+    pub fn json<T: Serialize>(status: u16, body: T) -> MockReply {
+        MockReply::raw(status, serde_json::to_vec(&body).expect("Mock body shall be serializable"))
+    }
+
+    /// Reply with given status and raw body. Useful for malformed response.
+    pub fn raw(status: u16, body: Vec<u8>) -> MockReply {
+        MockReply {
+            outcome: Outcome::Respond(HttpResponse {
+                status,
+                headers: vec![("Content-Type".to_owned(), "application/json".to_owned())],
+                body
+            }),
+            latency: Duration::from_secs(0)
+        }
+    }
+
+    /// Reply with given status and Watson style error body.
+    pub fn status(status: u16) -> MockReply {
+        MockReply::json(status, serde_json::json!({"code": status, "error": format!("Mock error {}", status)}))
+    }
+
+    /// Fail as if server cannot be reached.
+    pub fn request_fail() -> MockReply {
+        MockReply {
+            outcome: Outcome::RequestFail,
+            latency: Duration::from_secs(0)
+        }
+    }
+
+    /// Fail as if request has timed out.
+    pub fn timeout() -> MockReply {
+        MockReply {
+            outcome: Outcome::Timeout,
+            latency: Duration::from_secs(0)
+        }
+    }
+
+    /// Add response header.
+    pub fn header(mut self, name: &str, value: &str) -> MockReply {
+        if let Outcome::Respond(ref mut r) = self.outcome {
+            r.headers.push((name.to_owned(), value.to_owned()));
+        }
+        self
+    }
+
+    /// Delay the reply. If latency exceed total timeout of the request,
+    /// it fail with `CurlErr::Timeout` after the timeout.
+    pub fn latency(mut self, latency: Duration) -> MockReply {
+        self.latency = latency;
+        self
+    }
+}
+
+#[derive(Debug)]
+struct Route {
+    method: String,
+    url_fragment: String,
+    replies: VecDeque<MockReply>
+}
+
+#[derive(Debug, Default)]
+struct State {
+    routes: Vec<Route>,
+    requests: Vec<HttpRequest>
+}
+
+/// Transport that serve scripted reply instead of sending request over network.
+/// Clone share the same script and request log.
+#[derive(Clone, Debug, Default)]
+pub struct MockTransport {
+    state: Arc<Mutex<State>>
+}
+
+impl MockTransport {
+    pub fn new() -> MockTransport {
+        MockTransport::default()
+    }
+
+    /// Script `reply` for request of `method` whose url contains `url_fragment`.
+    pub fn on(self, method: &str, url_fragment: &str, reply: MockReply) -> MockTransport {
+        {
+            let mut state = self.state.lock().unwrap();
+            match state.routes.iter_mut().find(|r| r.method.eq_ignore_ascii_case(method) && r.url_fragment == url_fragment) {
+                Some(route) => route.replies.push_back(reply),
+                None => state.routes.push(Route {
+                    method: method.to_owned(),
+                    url_fragment: url_fragment.to_owned(),
+                    replies: vec![reply].into()
+                })
+            }
+        }
+        self
+    }
+
+    /// Every request received so far, in order.
+    pub fn requests(&self) -> Vec<HttpRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// Number of received request whose url contains `url_fragment`.
+    pub fn count(&self, url_fragment: &str) -> usize {
+        self.state.lock().unwrap().requests.iter().filter(|r| r.url.contains(url_fragment)).count()
+    }
+
+    fn next_reply(&self, request: &HttpRequest) -> Option<MockReply> {
+        let mut state = self.state.lock().unwrap();
+        state.requests.push(request.clone());
+        let route = state.routes.iter_mut().find(|r| r.method.eq_ignore_ascii_case(request.method.as_str()) && request.url.contains(&r.url_fragment))?;
+        if route.replies.len() > 1 {
+            route.replies.pop_front()
+        } else {
+            route.replies.front().cloned()
+        }
+    }
+}
+
+impl Transport for MockTransport {
+    fn send(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse, CurlErr>> {
+        let reply = self.next_reply(&request);
+        Box::pin(async move {
+            let reply = match reply {
+                Some(reply) => reply,
+                None => {
+                    log_warn!("No mock reply for {} {}", request.method.as_str(), request.url);
+                    MockReply::status(404)
+                }
+            };
+            if reply.latency > request.timeout.total {
+                Delay::new(request.timeout.total).await;
+                return Err(CurlErr::Timeout);
+            }
+            if reply.latency > Duration::from_secs(0) {
+                Delay::new(reply.latency).await;
+            }
+            match reply.outcome {
+                Outcome::Respond(response) => Ok(response),
+                Outcome::RequestFail => Err(CurlErr::RequestFail),
+                Outcome::Timeout => Err(CurlErr::Timeout)
+            }
+        })
+    }
+}
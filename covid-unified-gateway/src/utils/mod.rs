@@ -1,22 +1,61 @@
 //! Helper function to help send REST API
-//! 
-//! It has two functions.
-//! The most common one is [post_json](fn.post_json.html) where 
+//!
+//! It has four functions.
+//! The most common one is [post_json](fn.post_json.html) where
 //! it send HTTP POST request to given url.
-//! It take url, apikey, and optional post body.
-//! It return [RawResponse](struct.RawResponse.html).
-//! The struct implement `Deref` and `Deref_mut` to inner
-//! parsed object so it can be directly treat like it's inner object.
-//! It also allow to unpack it using [into_inner](struct.RawResponse.html#method.into_inner)
-//! to consume it.
-//! Another function is [delete](fn.delete.html).
-//! It take url and apikey as parameters.
-//! 
+//! It take url, [Upstream](struct.Upstream.html), and optional post body.
+//! It return parsed JSON object of requested type.
+//! [post_text](fn.post_text.html) is the same but post plain text body, and
+//! [get_json](fn.get_json.html) send HTTP GET request without body.
+//! The last one is [delete](fn.delete.html).
+//! It take url and [Upstream](struct.Upstream.html) as parameters.
+//!
+//! [Upstream](struct.Upstream.html) bundle how to talk to one service.
+//! It has [Authenticator](enum.Authenticator.html), [Timeout](struct.Timeout.html) and
+//! optional [Deadline](struct.Deadline.html) shared by every request of a turn.
+//! Transient failure is retried according to its [RetryPolicy](struct.RetryPolicy.html).
+//!
+//! The [Authenticator](enum.Authenticator.html) either send API key as basic auth
+//! or exchange it with IBM Cloud IAM for bearer token.
+//! [IamAuthenticator](struct.IamAuthenticator.html) cache the token until shortly before
+//! it expire. It can be cloned to share the same token between WA and WLT.
+//!
+//! Both functions are `async`. The request is delivered by the [Transport](transport/trait.Transport.html)
+//! of the [Upstream](struct.Upstream.html). By default, it is [IsahcTransport](transport/struct.IsahcTransport.html)
+//! which is backed by libcurl multi interface through `isahc` so awaiting several requests at once,
+//! e.g. with `futures::join!`, overlap their network time instead of running one after another.
+//! Tests inject [MockTransport](mock/struct.MockTransport.html) to run without network.
+//! [CassetteTransport](cassette/struct.CassetteTransport.html) record real interactions to a file
+//! and replay them later.
+//!
 //! Both function may also return [CurlErr](enum.CurlErr.html) to designate
 //! there's something wrong with the operation.
-use curl::easy::Easy;
+//! When Watson reply with non 2xx status, the error is
+//! [CurlErr::HttpStatus](enum.CurlErr.html#variant.HttpStatus) which carry
+//! [HttpErr](struct.HttpErr.html). It has status code, parsed Watson error body and
+//! `X-Global-Transaction-Id` that IBM support can use to trace the request.
+//! Both implement `std::error::Error`. The [wa](../wa/index.html) and [wlt](../wlt/index.html)
+//! modules convert them into [GatewayErr](../error/struct.GatewayErr.html) with context attached.
 use serde::{Deserialize, Serialize};
-use std::io::Read;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use super::logger::Record;
+
+mod auth;
+pub mod cassette;
+pub mod mock;
+mod retry;
+pub mod transport;
+
+pub use auth::{Authenticator, IamAuthenticator, DEFAULT_IAM_URL};
+pub use retry::RetryPolicy;
+pub use transport::{HttpRequest, HttpResponse, IsahcTransport, Method, Transport};
+
+/// Name of response header that Watson use to identify each request.
+pub const TRANSACTION_ID_HEADER: &str = "X-Global-Transaction-Id";
 
 #[derive(Debug)]
 pub enum CurlErr {
@@ -24,87 +63,410 @@ pub enum CurlErr {
     InvalidInputData,
     UnexpectedOutputData,
     RequestFail,
-    IncompatibleResultData
-}
-
-/// Send HTTP Post to given URL using `api_key` as authorization and optional JSON `data` 
-/// to be sent as body. It return `R` that is parsed JSON object or it return
-/// `CurlErr`
-pub fn post_json<I, R>(url: &str, api_key: &str, data: Option<&I>) -> Result<R, CurlErr> where I : Serialize, R: for<'r> Deserialize<'r> + Serialize {
-    let mut client = Easy::new();
-    if client.url(url).is_err() {
-        return  Err(CurlErr::InvalidUrl);
-    }
-
-    client.username("apikey").unwrap();
-    client.password(api_key).unwrap();
-    client.post(true).unwrap();
-    let mut buf = Vec::new();
-    let mut headers = curl::easy::List::new();
-    headers.append("Content-Type: application/json").expect("Cannot set Content-Type: application/json");
-    client.http_headers(headers).expect("Fail to add header");
-    let input;
-    {
-        // code block to force transfer lifetime to end before we extract result.
-        // otherwise, `buf` will be burrowed by closure on write_function below while
-        // we try to deserialize `buf`
-        // with this code block, we force transfer to drop before that line.
-        let mut transfer = client.transfer();
-
-        if let Err(e) = transfer.write_function(|d| {
-            buf.extend_from_slice(d);
-            Ok(d.len())
-        }) {
-            println!("Cannot read data from response with following error:{:?}", e);
-            return Err(CurlErr::UnexpectedOutputData);
+    IncompatibleResultData,
+    HttpStatus(HttpErr),
+    Timeout,
+    /// Cassette being replayed has no interaction for the request. Sending it again doesn't help.
+    NotRecorded
+}
+
+impl CurlErr {
+    /// Failure that may succeed if the request is sent again.
+    /// It is connection failure, timeout, `429` and `5xx`.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            CurlErr::RequestFail | CurlErr::Timeout => true,
+            CurlErr::HttpStatus(e) => e.is_rate_limited() || e.is_server_error(),
+            _ => false
         }
+    }
 
-        // only add read_function if data is not None
-        if let Some(d) = data {
-            input = serde_json::to_vec(d).expect("Fail to convert JSON to string");
-            let mut sending_data = input.as_slice();
-            if let Err(e) = transfer.read_function(move |into| {
-                Ok(sending_data.read(into).unwrap())
-            }) {
-                println!("Cannot fill the request buffer with input data. Following error returned:{:?}", e);
-                return Err(CurlErr::InvalidInputData);
-            }
+    /// Delay requested by server through `Retry-After` header.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            CurlErr::HttpStatus(e) => e.retry_after,
+            _ => None
         }
-        
-        if let Err(e) = transfer.perform() {
-            println!("Fail to send request with following error:{:?}", e);
-            return Err(CurlErr::RequestFail);
+    }
+}
+
+impl fmt::Display for CurlErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CurlErr::InvalidUrl => write!(f, "Invalid url"),
+            CurlErr::InvalidInputData => write!(f, "Cannot encode request body"),
+            CurlErr::UnexpectedOutputData => write!(f, "Cannot read response body"),
+            CurlErr::RequestFail => write!(f, "Cannot deliver request to server"),
+            CurlErr::IncompatibleResultData => write!(f, "Cannot decode response body"),
+            CurlErr::HttpStatus(_) => write!(f, "Server reject the request"),
+            CurlErr::Timeout => write!(f, "Request timed out"),
+            CurlErr::NotRecorded => write!(f, "No recorded interaction for the request")
         }
     }
+}
 
-    match serde_json::from_reader(buf.as_slice()) {
-        Ok(result) => Ok(result),
-        Err(e) => {
-            // run into deserialize issue. Print some info to let user know on
-            // what cause the error
-            println!("Fail to deserialize data into object: {:?}", e);
-            Err(CurlErr::IncompatibleResultData)
+impl Error for CurlErr {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CurlErr::HttpStatus(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+/// Connect and total timeout of each request.
+#[derive(Clone, Copy, Debug)]
+pub struct Timeout {
+    /// Maximum time to establish connection
+    pub connect: Duration,
+    /// Maximum time for whole request, including connection and response transfer
+    pub total: Duration
+}
+
+impl Default for Timeout {
+    fn default() -> Self {
+        Timeout {
+            connect: Duration::from_secs(3),
+            total: Duration::from_secs(10)
         }
     }
 }
 
-pub fn delete(url: &str, api_key: &str) -> Result<(), CurlErr> {
-    let mut client = Easy::new();
-    if client.url(url).is_err() {
-        return  Err(CurlErr::InvalidUrl);
+/// Point in time that every request shall be completed.
+#[derive(Clone, Copy, Debug)]
+pub struct Deadline(Instant);
+
+impl Deadline {
+    /// Deadline that is `budget` from now.
+    pub fn after(budget: Duration) -> Deadline {
+        Deadline(Instant::now() + budget)
+    }
+
+    /// Time left until deadline. It is zero if deadline has passed.
+    pub fn remaining(&self) -> Duration {
+        self.0.saturating_duration_since(Instant::now())
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.remaining() == Duration::from_secs(0)
+    }
+}
+
+/// Everything needed to talk to one upstream service, e.g. WA or WLT.
+///
+/// It can be built from plain API key, [Authenticator](enum.Authenticator.html), or
+/// [IamAuthenticator](struct.IamAuthenticator.html) then customized by chaining
+/// [timeout](struct.Upstream.html#method.timeout), [retry](struct.Upstream.html#method.retry)
+/// [deadline](struct.Upstream.html#method.deadline) and [transport](struct.Upstream.html#method.transport).
+#[derive(Clone, Debug)]
+pub struct Upstream {
+    pub auth: Authenticator,
+    pub timeout: Timeout,
+    pub retry: RetryPolicy,
+    pub deadline: Option<Deadline>,
+    pub transport: Arc<dyn Transport>
+}
+
+impl Upstream {
+    pub fn new(auth: Authenticator) -> Upstream {
+        Upstream {
+            auth,
+            timeout: Timeout::default(),
+            retry: RetryPolicy::default(),
+            deadline: None,
+            transport: Arc::new(IsahcTransport)
+        }
+    }
+
+    pub fn timeout(mut self, timeout: Timeout) -> Upstream {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Retry policy of every request to this upstream.
+    pub fn retry(mut self, retry: RetryPolicy) -> Upstream {
+        self.retry = retry;
+        self
     }
 
-    client.username("apikey").unwrap();
-    client.password(api_key).unwrap();
-    client.custom_request("DELETE").unwrap();
+    /// Every request to this upstream shall finish before given deadline.
+    /// Total timeout of each request is shorten to fit remaining time.
+    pub fn deadline(mut self, deadline: Deadline) -> Upstream {
+        self.deadline = Some(deadline);
+        self
+    }
 
-    {
-        let mut transfer = client.transfer();
+    /// Deliver every request to this upstream through given transport.
+    pub fn transport<T: Transport + 'static>(mut self, transport: T) -> Upstream {
+        self.transport = Arc::new(transport);
+        self
+    }
 
-        if transfer.perform().is_err() {
-            Err(CurlErr::RequestFail)
-        } else {
-            Ok(())
+    /// Total timeout of the next request, taking deadline into account.
+    /// It return `CurlErr::Timeout` if deadline has already passed.
+    fn total_timeout(&self) -> Result<Duration, CurlErr> {
+        match self.deadline {
+            Some(d) if d.is_expired() => {
+                log_warn!("Deadline has passed, skip sending request");
+                Err(CurlErr::Timeout)
+            },
+            Some(d) => Ok(self.timeout.total.min(d.remaining())),
+            None => Ok(self.timeout.total)
         }
     }
-}
\ No newline at end of file
+}
+
+impl From<Authenticator> for Upstream {
+    fn from(auth: Authenticator) -> Self {
+        Upstream::new(auth)
+    }
+}
+
+impl From<IamAuthenticator> for Upstream {
+    fn from(iam: IamAuthenticator) -> Self {
+        Upstream::new(iam.into())
+    }
+}
+
+impl From<String> for Upstream {
+    fn from(api_key: String) -> Self {
+        Upstream::new(api_key.into())
+    }
+}
+
+impl From<&str> for Upstream {
+    fn from(api_key: &str) -> Self {
+        Upstream::new(api_key.into())
+    }
+}
+
+/// Error body that Watson services return along with non 2xx status.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct WatsonError {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trace: Option<String>
+}
+
+/// Non 2xx response from Watson.
+#[derive(Debug)]
+pub struct HttpErr {
+    /// HTTP status code
+    pub status: u16,
+    /// Parsed Watson error body. It is `None` if body is empty or isn't Watson error JSON.
+    pub body: Option<WatsonError>,
+    /// Value of `X-Global-Transaction-Id` response header, if any.
+    pub transaction_id: Option<String>,
+    /// Value of `Retry-After` response header, if any. Date in the past is zero delay.
+    pub retry_after: Option<Duration>
+}
+
+impl HttpErr {
+    /// Server reject the credential, e.g. wrong or revoked API key or expired token.
+    pub fn is_unauthorized(&self) -> bool {
+        self.status == 401
+    }
+
+    /// Credential is valid but it isn't allowed to do the request. New token doesn't help.
+    pub fn is_forbidden(&self) -> bool {
+        self.status == 403
+    }
+
+    /// Server reject the request because client send too many request.
+    pub fn is_rate_limited(&self) -> bool {
+        self.status == 429
+    }
+
+    /// WA doesn't know the session anymore. It is usually because session has timed out.
+    pub fn is_session_expired(&self) -> bool {
+        self.status == 404 && self.body.as_ref()
+                                    .and_then(|b| b.error.as_ref())
+                                    .is_some_and(|e| e.to_lowercase().contains("session"))
+    }
+
+    /// WLT cannot use the requested model, e.g. it doesn't exist, isn't available or is still training.
+    pub fn is_model_unavailable(&self) -> bool {
+        matches!(self.status, 400 | 404 | 409) && self.message().is_some_and(|m| m.to_lowercase().contains("model"))
+    }
+
+    /// Server side failure, e.g. 500 or 503.
+    pub fn is_server_error(&self) -> bool {
+        self.status >= 500
+    }
+
+    /// Error message from Watson error body, if any.
+    pub fn message(&self) -> Option<&str> {
+        self.body.as_ref().and_then(|b| b.error.as_deref())
+    }
+}
+
+impl fmt::Display for HttpErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HTTP {}", self.status)?;
+        if let Some(message) = self.message() {
+            write!(f, " {}", message)?;
+        }
+        if let Some(id) = &self.transaction_id {
+            write!(f, " (transaction id {})", id)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for HttpErr {}
+
+/// Parse `Retry-After` header, either seconds, e.g. `120`, or HTTP date, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+    match http_date(value) {
+        Some(date) => Some(date.duration_since(SystemTime::now()).unwrap_or_default()),
+        None => {
+            log_warn!("Ignore malformed Retry-After {}", value);
+            None
+        }
+    }
+}
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// Parse HTTP date in IMF-fixdate format, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+/// The obsolete RFC 850 and asctime formats aren't supported.
+fn http_date(value: &str) -> Option<SystemTime> {
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    if parts.len() != 6 || !parts[0].ends_with(',') || parts[5] != "GMT" {
+        return None;
+    }
+    let day: i64 = parts[1].parse().ok().filter(|d| (1..=31).contains(d))?;
+    let month = MONTHS.iter().position(|m| *m == parts[2])? as i64 + 1;
+    let year: i64 = parts[3].parse().ok()?;
+    let time: Vec<i64> = parts[4].split(':').map(|p| p.parse().ok()).collect::<Option<_>>()?;
+    if time.len() != 3 || time[0] > 23 || time[1] > 59 || time[2] > 60 {
+        return None;
+    }
+    // days since 1970-01-01 of proleptic Gregorian calendar, year start from March so leap day is the last day
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    let seconds = days * 86400 + time[0] * 3600 + time[1] * 60 + time[2];
+    (seconds >= 0).then(|| UNIX_EPOCH + Duration::from_secs(seconds as u64))
+}
+
+/// Convert non 2xx response into [HttpErr](struct.HttpErr.html).
+/// It return `None` if the response status is successful.
+fn check_status(response: &HttpResponse) -> Option<HttpErr> {
+    if (200..300).contains(&response.status) {
+        return None;
+    }
+
+    let err = HttpErr {
+        status: response.status,
+        body: serde_json::from_slice(&response.body).ok(),
+        transaction_id: response.get_header(TRANSACTION_ID_HEADER).map(|v| v.to_owned()),
+        retry_after: response.get_header("Retry-After").and_then(parse_retry_after)
+    };
+    Record::warn(format!("Server return status {}", err.status))
+        .field("status", err.status)
+        .field("transaction_id", &err.transaction_id)
+        .field("error", err.message())
+        .emit();
+    Some(err)
+}
+
+/// Deliver given request through `transport` and return the raw response body.
+/// Non 2xx response is converted into [CurlErr::HttpStatus](enum.CurlErr.html#variant.HttpStatus).
+async fn execute(transport: &dyn Transport, request: HttpRequest) -> Result<Vec<u8>, CurlErr> {
+    let response = transport.send(request).await?;
+    match check_status(&response) {
+        Some(e) => Err(CurlErr::HttpStatus(e)),
+        None => Ok(response.body)
+    }
+}
+
+/// Authorize request with `upstream` authenticator, apply its timeout then send it once,
+/// or twice if IAM token is rejected.
+/// `body` is sent with `content_type` if it is given.
+async fn send(method: Method, url: &str, upstream: &Upstream, body: Vec<u8>, content_type: Option<&str>) -> Result<Vec<u8>, CurlErr> {
+    let timeout = Timeout {
+        connect: upstream.timeout.connect,
+        total: upstream.total_timeout()?
+    };
+    let mut request = HttpRequest::new(method, url, timeout).body(body);
+    if let Some(content_type) = content_type {
+        request = request.header("Content-Type", content_type);
+    }
+    let started = Instant::now();
+    let mut result = execute(upstream.transport.as_ref(), upstream.auth.authorize(request.clone(), upstream).await?).await;
+    // token may be revoked before it expire, so it is renewed and the request is sent once more
+    if let Err(CurlErr::HttpStatus(e)) = &result {
+        if e.is_unauthorized() && upstream.auth.invalidate().await {
+            log_warn!("Token is rejected, retry {} {} with new token", method.as_str(), url);
+            result = execute(upstream.transport.as_ref(), upstream.auth.authorize(request, upstream).await?).await;
+        }
+    }
+    Record::debug(format!("{} {}", method.as_str(), url))
+        .latency(started.elapsed())
+        .field("ok", result.is_ok())
+        .emit();
+    result
+}
+
+/// Send HTTP Post to given URL using `upstream` authorization, timeout and retry policy
+/// and optional JSON `data` to be sent as body. It return `R` that is parsed JSON object
+/// or it return `CurlErr`
+pub async fn post_json<I, R>(url: &str, upstream: &Upstream, data: Option<&I>) -> Result<R, CurlErr> where I : Serialize, R: for<'r> Deserialize<'r> + Serialize {
+    let input = match data {
+        Some(d) => match serde_json::to_vec(d) {
+            Ok(input) => input,
+            Err(e) => {
+                log_error!("Cannot fill the request buffer with input data. Following error returned: {}", e);
+                return Err(CurlErr::InvalidInputData);
+            }
+        },
+        None => Vec::new()
+    };
+
+    let buf = upstream.retry.run(upstream.deadline, || send(Method::Post, url, upstream, input.clone(), Some("application/json"))).await?;
+    parse_json(&buf)
+}
+
+/// Send HTTP Post to given URL with plain `text` as body, using `upstream` authorization, timeout
+/// and retry policy. It return `R` that is parsed JSON object or it return `CurlErr`
+pub async fn post_text<R>(url: &str, upstream: &Upstream, text: &str) -> Result<R, CurlErr> where R: for<'r> Deserialize<'r> {
+    let buf = upstream.retry.run(upstream.deadline, || send(Method::Post, url, upstream, text.as_bytes().to_vec(), Some("text/plain; charset=utf-8"))).await?;
+    parse_json(&buf)
+}
+
+/// Send HTTP Get to given URL using `upstream` authorization, timeout and retry policy.
+/// It return `R` that is parsed JSON object or it return `CurlErr`
+pub async fn get_json<R>(url: &str, upstream: &Upstream) -> Result<R, CurlErr> where R: for<'r> Deserialize<'r> {
+    let buf = upstream.retry.run(upstream.deadline, || send(Method::Get, url, upstream, Vec::new(), None)).await?;
+    parse_json(&buf)
+}
+
+fn parse_json<R>(buf: &[u8]) -> Result<R, CurlErr> where R: for<'r> Deserialize<'r> {
+    match serde_json::from_slice(buf) {
+        Ok(result) => Ok(result),
+        Err(e) => {
+            // run into deserialize issue. Print some info to let user know on
+            // what cause the error
+            log_error!("Fail to deserialize data into object: {}", e);
+            Err(CurlErr::IncompatibleResultData)
+        }
+    }
+}
+
+/// Send HTTP Delete to given URL using `upstream` authorization, timeout and retry policy.
+pub async fn delete(url: &str, upstream: &Upstream) -> Result<(), CurlErr> {
+    upstream.retry.run(upstream.deadline, || send(Method::Delete, url, upstream, Vec::new(), None)).await.map(|_| ())
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,83 @@
+//! Retry transient failure with exponential backoff.
+//!
+//! Only failure that may succeed on next attempt is retried. It is connection failure,
+//! timeout, `429 Too Many Requests` and `5xx`. Other failure, e.g. invalid url or
+//! `400 Bad Request`, is returned immediately.
+//!
+//! Delay between attempt grow exponentially from `base_delay` up to `max_delay`.
+//! Half of the delay is randomized so concurrent clients don't retry at the same time.
+//! If server reply with `Retry-After` header, the delay is what server ask for. Without deadline,
+//! request isn't retried if server ask for longer than `max_delay`, so a turn never hang for hours.
+
+use futures_timer::Delay;
+use std::future::Future;
+use std::time::Duration;
+use super::{CurlErr, Deadline};
+
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// Maximum number of retry after the first attempt.
+    pub max_retry: usize,
+    /// Delay before first retry
+    pub base_delay: Duration,
+    /// Upper bound of computed delay. Delay requested by `Retry-After` can be longer only if there's deadline.
+    pub max_delay: Duration
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retry: 1,
+            base_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(2)
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Policy that never retry.
+    pub fn no_retry() -> RetryPolicy {
+        RetryPolicy {
+            max_retry: 0,
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// Delay before retry number `attempt`, starting from 0.
+    /// `retry_after` is the delay requested by server, if any.
+    pub fn delay(&self, attempt: usize, retry_after: Option<Duration>) -> Duration {
+        if let Some(d) = retry_after {
+            return d;
+        }
+        let factor = 2u32.saturating_pow(attempt.min(u32::MAX as usize) as u32);
+        let backoff = self.base_delay.saturating_mul(factor).min(self.max_delay);
+        // equal jitter: half fixed, half random
+        let half = backoff / 2;
+        half + half.mul_f64(fastrand::f64())
+    }
+
+    /// Run `op` until it succeed, fail with non transient error, run out of retry, or
+    /// the next delay would go past `deadline`.
+    pub async fn run<T, F, Fut>(&self, deadline: Option<Deadline>, mut op: F) -> Result<T, CurlErr> where F: FnMut() -> Fut, Fut: Future<Output = Result<T, CurlErr>> {
+        let mut attempt = 0;
+        loop {
+            match op().await {
+                Err(e) if e.is_transient() && attempt < self.max_retry => {
+                    let delay = self.delay(attempt, e.retry_after());
+                    if deadline.is_some_and(|d| d.remaining() <= delay) {
+                        log_warn!("Not enough time left to retry after {:?}", delay);
+                        return Err(e);
+                    }
+                    if deadline.is_none() && delay > self.max_delay {
+                        log_warn!("Server ask to retry after {:?}, longer than {:?}", delay, self.max_delay);
+                        return Err(e);
+                    }
+                    log_warn!("Attempt {} fail with transient error: {}, retry in {:?}", attempt + 1, e, delay);
+                    Delay::new(delay).await;
+                    attempt += 1;
+                },
+                result => return result
+            }
+        }
+    }
+}
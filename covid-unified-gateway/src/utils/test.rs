@@ -0,0 +1,481 @@
+use super::*;
+
+#[test]
+fn test_parse_watson_error() {
+    let body: WatsonError = serde_json::from_str(r#"{"error": "Invalid Session", "code": 404, "trace": "b6d9c7e1"}"#).unwrap();
+    let err = HttpErr {
+        status: 404,
+        body: Some(body),
+        transaction_id: Some("abcd-1234".to_owned()),
+        retry_after: None
+    };
+    assert!(err.is_session_expired());
+    assert!(!err.is_unauthorized());
+    assert_eq!(err.message(), Some("Invalid Session"));
+}
+
+#[test]
+fn test_classify_http_err() {
+    let unauthorized = HttpErr { status: 401, body: None, transaction_id: None, retry_after: None };
+    assert!(unauthorized.is_unauthorized());
+    assert!(!unauthorized.is_forbidden());
+    assert!(!unauthorized.is_session_expired());
+
+    let forbidden = HttpErr { status: 403, body: None, transaction_id: None, retry_after: None };
+    assert!(forbidden.is_forbidden());
+    assert!(!forbidden.is_unauthorized());
+
+    let rate_limited = HttpErr { status: 429, body: None, transaction_id: None, retry_after: None };
+    assert!(rate_limited.is_rate_limited());
+
+    let not_found = HttpErr { status: 404, body: None, transaction_id: None, retry_after: None };
+    assert!(!not_found.is_session_expired());
+    assert!(!not_found.is_model_unavailable());
+}
+
+#[test]
+fn test_model_unavailable() {
+    let err = |status: u16, message: &str| HttpErr {
+        status,
+        body: serde_json::from_value(serde_json::json!({"error": message, "code": status})).ok(),
+        transaction_id: None,
+        retry_after: None
+    };
+    assert!(err(404, "Model not found.").is_model_unavailable());
+    assert!(err(400, "Model 3f2a-aaaa is not available, status is training").is_model_unavailable());
+    assert!(!err(400, "Invalid JSON input").is_model_unavailable());
+    assert!(!err(500, "Model server crashed").is_model_unavailable());
+}
+
+/// Start HTTP server on random local port that answer every request with `handler`.
+/// It return base url of the server and the list of raw requests it has received.
+fn stub_server<F>(handler: F) -> (String, std::sync::Arc<std::sync::Mutex<Vec<String>>>) where F: Fn(&str) -> (u16, String) + Send + 'static {
+    stub_server_with_headers("", handler)
+}
+
+/// Same as `stub_server` but every response also carry `headers`, each terminated with `\r\n`.
+fn stub_server_with_headers<F>(headers: &'static str, handler: F) -> (String, std::sync::Arc<std::sync::Mutex<Vec<String>>>) where F: Fn(&str) -> (u16, String) + Send + 'static {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let log = received.clone();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request = String::new();
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(v) = line.to_lowercase().strip_prefix("content-length:") {
+                    content_length = v.trim().parse().unwrap();
+                }
+                request.push_str(&line);
+                if line == "\r\n" || line.is_empty() {
+                    break;
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            request.push_str(&String::from_utf8_lossy(&body));
+            let (status, response) = handler(&request);
+            log.lock().unwrap().push(request);
+            write!(stream, "HTTP/1.1 {} Stub\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n{}\r\n{}", status, response.len(), headers, response).unwrap();
+        }
+    });
+    (url, received)
+}
+
+#[test]
+fn test_iam_token_is_cached() {
+    let (url, received) = stub_server(|_| (200, r#"{"access_token": "token-1", "token_type": "Bearer", "expires_in": 3600}"#.to_owned()));
+    let iam = IamAuthenticator::with_url("my key".to_owned(), format!("{}/identity/token", url));
+    let shared = iam.clone();
+
+    futures::executor::block_on(async {
+        let (first, second) = futures::join!(iam.token(), shared.token());
+        assert_eq!(first.unwrap(), "token-1");
+        assert_eq!(second.unwrap(), "token-1");
+    });
+
+    let received = received.lock().unwrap();
+    assert_eq!(received.len(), 1);
+    assert!(received[0].starts_with("POST /identity/token"));
+    assert!(received[0].contains("grant_type=urn%3Aibm%3Aparams%3Aoauth%3Agrant-type%3Aapikey&apikey=my%20key"));
+}
+
+#[test]
+fn test_iam_token_refresh_after_invalidate() {
+    let (url, received) = stub_server(|_| (200, r#"{"access_token": "token", "expires_in": 3600}"#.to_owned()));
+    let iam = IamAuthenticator::with_url("key".to_owned(), url);
+
+    futures::executor::block_on(async {
+        iam.token().await.unwrap();
+        iam.invalidate().await;
+        iam.token().await.unwrap();
+    });
+
+    assert_eq!(received.lock().unwrap().len(), 2);
+}
+
+#[test]
+fn test_iam_token_rejected() {
+    let (url, _) = stub_server(|_| (400, r#"{"errorCode": "BXNIM0415E", "errorMessage": "Provided API key could not be found"}"#.to_owned()));
+    let iam = IamAuthenticator::with_url("bad key".to_owned(), url);
+
+    match futures::executor::block_on(iam.token()) {
+        Err(CurlErr::HttpStatus(e)) => assert_eq!(e.status, 400),
+        other => panic!("Expect HTTP 400 but got {:?}", other)
+    }
+}
+
+#[test]
+fn test_post_json_send_bearer_token() {
+    let (url, received) = stub_server(|request| {
+        if request.starts_with("POST /identity/token") {
+            (200, r#"{"access_token": "abc", "expires_in": 3600}"#.to_owned())
+        } else {
+            (200, r#"{"session_id": "s1"}"#.to_owned())
+        }
+    });
+    let upstream: Upstream = IamAuthenticator::with_url("key".to_owned(), format!("{}/identity/token", url)).into();
+
+    let result: std::collections::HashMap<String, String> = futures::executor::block_on(post_json::<(), _>(&format!("{}/v2/assistants/a/sessions", url), &upstream, None)).unwrap();
+    assert_eq!(result["session_id"], "s1");
+
+    let received = received.lock().unwrap();
+    assert_eq!(received.len(), 2);
+    assert!(received[1].to_lowercase().contains("authorization: bearer abc"));
+}
+
+#[test]
+fn test_revoked_token_is_renewed() {
+    let issued = std::sync::atomic::AtomicUsize::new(0);
+    let (url, received) = stub_server(move |request| {
+        if request.starts_with("POST /identity/token") {
+            let n = issued.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+            (200, format!(r#"{{"access_token": "token-{}", "expires_in": 3600}}"#, n))
+        } else if request.to_lowercase().contains("authorization: bearer token-1") {
+            (401, r#"{"error": "Unauthorized", "code": 401}"#.to_owned())
+        } else {
+            (200, r#"{"session_id": "s1"}"#.to_owned())
+        }
+    });
+    let upstream: Upstream = IamAuthenticator::with_url("key".to_owned(), format!("{}/identity/token", url)).into();
+
+    let result: std::collections::HashMap<String, String> = futures::executor::block_on(post_json::<(), _>(&format!("{}/v2/assistants/a/sessions", url), &upstream, None)).unwrap();
+    assert_eq!(result["session_id"], "s1");
+    let received = received.lock().unwrap();
+    assert_eq!(received.len(), 4);
+    assert!(received[3].to_lowercase().contains("authorization: bearer token-2"));
+}
+
+#[test]
+fn test_forbidden_keep_token() {
+    let (url, received) = stub_server(|request| {
+        if request.starts_with("POST /identity/token") {
+            (200, r#"{"access_token": "token-1", "expires_in": 3600}"#.to_owned())
+        } else {
+            (403, r#"{"error": "Forbidden", "code": 403}"#.to_owned())
+        }
+    });
+    let upstream: Upstream = IamAuthenticator::with_url("key".to_owned(), format!("{}/identity/token", url)).into();
+    let session = format!("{}/v2/assistants/a/sessions", url);
+
+    for _ in 0..2 {
+        let result: Result<serde_json::Value, CurlErr> = futures::executor::block_on(post_json::<(), _>(&session, &upstream, None));
+        assert!(matches!(result, Err(CurlErr::HttpStatus(e)) if e.is_forbidden()));
+    }
+    // token is fetched once and each forbidden request is sent once
+    let received = received.lock().unwrap();
+    assert_eq!(received.iter().filter(|r| r.starts_with("POST /identity/token")).count(), 1);
+    assert_eq!(received.len(), 3);
+}
+
+#[test]
+fn test_rejected_basic_auth_is_not_retried() {
+    let (url, received) = stub_server(|_| (401, r#"{"error": "Unauthorized", "code": 401}"#.to_owned()));
+    let upstream = Upstream::new("key".into());
+
+    let result: Result<serde_json::Value, CurlErr> = futures::executor::block_on(post_json::<(), _>(&url, &upstream, None));
+    assert!(matches!(result, Err(CurlErr::HttpStatus(HttpErr { status: 401, .. }))));
+    assert_eq!(received.lock().unwrap().len(), 1);
+}
+
+#[test]
+fn test_iam_token_follow_upstream() {
+    // transient failure of IAM is retried with the policy of upstream
+    let count = std::sync::atomic::AtomicUsize::new(0);
+    let (url, received) = stub_server(move |_| match count.fetch_add(1, std::sync::atomic::Ordering::SeqCst) {
+        0 => (503, r#"{"error": "failure", "code": 503}"#.to_owned()),
+        _ => (200, r#"{"access_token": "token", "expires_in": 3600}"#.to_owned())
+    });
+    let iam = IamAuthenticator::with_url("key".to_owned(), url);
+    let upstream = Upstream::new(iam.clone().into()).retry(fast_retry(1));
+    assert_eq!(futures::executor::block_on(iam.token_for(&upstream)).unwrap(), "token");
+    assert_eq!(received.lock().unwrap().len(), 2);
+
+    // IAM isn't asked once deadline has passed
+    let (url, received) = stub_server(|_| (200, r#"{"access_token": "token", "expires_in": 3600}"#.to_owned()));
+    let iam = IamAuthenticator::with_url("key".to_owned(), url);
+    let upstream = Upstream::new(iam.clone().into()).deadline(Deadline::after(Duration::from_secs(0)));
+    assert!(matches!(futures::executor::block_on(iam.token_for(&upstream)), Err(CurlErr::Timeout)));
+    assert!(received.lock().unwrap().is_empty());
+}
+
+#[test]
+fn test_request_timeout() {
+    let (url, _) = stub_server(|_| {
+        std::thread::sleep(Duration::from_millis(1000));
+        (200, "{}".to_owned())
+    });
+    let upstream = Upstream::new("key".into()).timeout(Timeout {
+        connect: Duration::from_millis(500),
+        total: Duration::from_millis(200)
+    });
+
+    let result: Result<serde_json::Value, CurlErr> = futures::executor::block_on(post_json::<(), _>(&url, &upstream, None));
+    assert!(matches!(result, Err(CurlErr::Timeout)));
+}
+
+#[test]
+fn test_concurrent_requests_overlap() {
+    // one server per service, like WA and WLT, each answer slowly
+    let slow = |_: &str| {
+        std::thread::sleep(Duration::from_millis(500));
+        (200, r#"{"ok": true}"#.to_owned())
+    };
+    let (wa, _) = stub_server(slow);
+    let (wlt, _) = stub_server(slow);
+    let upstream = Upstream::new("key".into());
+
+    let started = std::time::Instant::now();
+    let (first, second): (Result<serde_json::Value, CurlErr>, Result<serde_json::Value, CurlErr>) = futures::executor::block_on(async {
+        futures::join!(post_json::<(), _>(&wa, &upstream, None), post_json::<(), _>(&wlt, &upstream, None))
+    });
+    let elapsed = started.elapsed();
+    assert_eq!(first.unwrap()["ok"], true);
+    assert_eq!(second.unwrap()["ok"], true);
+    // sequential requests would take at least 1 second
+    assert!(elapsed < Duration::from_millis(800), "Took {:?}", elapsed);
+}
+
+#[test]
+fn test_expired_deadline_skip_request() {
+    let (url, received) = stub_server(|_| (200, "{}".to_owned()));
+    let upstream = Upstream::new("key".into()).deadline(Deadline::after(Duration::from_secs(0)));
+
+    let result: Result<serde_json::Value, CurlErr> = futures::executor::block_on(post_json::<(), _>(&url, &upstream, None));
+    assert!(matches!(result, Err(CurlErr::Timeout)));
+    assert!(received.lock().unwrap().is_empty());
+}
+
+#[test]
+fn test_deadline_shorten_timeout() {
+    let upstream = Upstream::new("key".into()).deadline(Deadline::after(Duration::from_millis(100)));
+    assert!(upstream.total_timeout().unwrap() <= Duration::from_millis(100));
+}
+
+/// Handler that fail with `status` for the first `failures` requests then succeed.
+fn fail_first(failures: usize, status: u16) -> impl Fn(&str) -> (u16, String) + Send + 'static {
+    let count = std::sync::atomic::AtomicUsize::new(0);
+    move |_| {
+        if count.fetch_add(1, std::sync::atomic::Ordering::SeqCst) < failures {
+            (status, r#"{"error": "failure", "code": 0}"#.to_owned())
+        } else {
+            (200, r#"{"session_id": "s1"}"#.to_owned())
+        }
+    }
+}
+
+fn fast_retry(max_retry: usize) -> RetryPolicy {
+    RetryPolicy {
+        max_retry,
+        base_delay: Duration::from_millis(10),
+        max_delay: Duration::from_millis(20)
+    }
+}
+
+#[test]
+fn test_retry_transient_failure() {
+    let (url, received) = stub_server(fail_first(2, 503));
+    let upstream = Upstream::new("key".into()).retry(fast_retry(2));
+
+    let result: Result<serde_json::Value, CurlErr> = futures::executor::block_on(post_json::<(), _>(&url, &upstream, None));
+    assert!(result.is_ok());
+    assert_eq!(received.lock().unwrap().len(), 3);
+}
+
+#[test]
+fn test_no_retry_on_client_error() {
+    let (url, received) = stub_server(fail_first(1, 400));
+    let upstream = Upstream::new("key".into()).retry(fast_retry(3));
+
+    let result: Result<serde_json::Value, CurlErr> = futures::executor::block_on(post_json::<(), _>(&url, &upstream, None));
+    assert!(matches!(result, Err(CurlErr::HttpStatus(HttpErr { status: 400, .. }))));
+    assert_eq!(received.lock().unwrap().len(), 1);
+}
+
+#[test]
+fn test_give_up_after_max_retry() {
+    let (url, received) = stub_server(fail_first(10, 429));
+    let upstream = Upstream::new("key".into()).retry(fast_retry(1));
+
+    let result: Result<serde_json::Value, CurlErr> = futures::executor::block_on(post_json::<(), _>(&url, &upstream, None));
+    assert!(matches!(result, Err(CurlErr::HttpStatus(HttpErr { status: 429, .. }))));
+    assert_eq!(received.lock().unwrap().len(), 2);
+}
+
+#[test]
+fn test_retry_after_beyond_deadline() {
+    let (url, received) = stub_server_with_headers("Retry-After: 30\r\n", fail_first(1, 429));
+    let upstream = Upstream::new("key".into()).retry(fast_retry(3)).deadline(Deadline::after(Duration::from_secs(5)));
+
+    let result: Result<serde_json::Value, CurlErr> = futures::executor::block_on(post_json::<(), _>(&url, &upstream, None));
+    match result {
+        Err(CurlErr::HttpStatus(e)) => assert_eq!(e.retry_after, Some(Duration::from_secs(30))),
+        other => panic!("Expect HTTP 429 but got {:?}", other)
+    }
+    assert_eq!(received.lock().unwrap().len(), 1);
+}
+
+#[test]
+fn test_long_retry_after_without_deadline() {
+    let (url, received) = stub_server_with_headers("Retry-After: 3600\r\n", fail_first(1, 429));
+    let upstream = Upstream::new("key".into()).retry(fast_retry(3));
+
+    let started = std::time::Instant::now();
+    let result: Result<serde_json::Value, CurlErr> = futures::executor::block_on(post_json::<(), _>(&url, &upstream, None));
+    assert!(matches!(result, Err(CurlErr::HttpStatus(HttpErr { status: 429, .. }))));
+    assert_eq!(received.lock().unwrap().len(), 1);
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn test_retry_after_http_date() {
+    // date in the past mean retry now
+    let (url, received) = stub_server_with_headers("Retry-After: Sun, 06 Nov 1994 08:49:37 GMT\r\n", fail_first(1, 503));
+    let upstream = Upstream::new("key".into()).retry(fast_retry(1));
+    let result: Result<serde_json::Value, CurlErr> = futures::executor::block_on(post_json::<(), _>(&url, &upstream, None));
+    assert!(result.is_ok());
+    assert_eq!(received.lock().unwrap().len(), 2);
+
+    let (url, _) = stub_server_with_headers("Retry-After: Fri, 01 Jan 2100 00:00:00 GMT\r\n", fail_first(1, 503));
+    let result: Result<serde_json::Value, CurlErr> = futures::executor::block_on(post_json::<(), _>(&url, &upstream, None));
+    match result {
+        Err(CurlErr::HttpStatus(e)) => assert!(e.retry_after.unwrap() > Duration::from_secs(86400 * 365)),
+        other => panic!("Expect HTTP 503 but got {:?}", other)
+    }
+
+    assert_eq!(http_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(UNIX_EPOCH + Duration::from_secs(784111777)));
+    assert_eq!(http_date("Thu, 29 Feb 2024 23:59:59 GMT"), Some(UNIX_EPOCH + Duration::from_secs(1709251199)));
+    assert_eq!(http_date("Sunday, 06-Nov-94 08:49:37 GMT"), None);
+    assert_eq!(http_date("Sun, 06 Nov 1994 08:49:37 PST"), None);
+    assert_eq!(parse_retry_after(" 120 "), Some(Duration::from_secs(120)));
+    assert_eq!(parse_retry_after("soon"), None);
+}
+
+#[test]
+fn test_retry_delay() {
+    let policy = RetryPolicy {
+        max_retry: 5,
+        base_delay: Duration::from_millis(100),
+        max_delay: Duration::from_millis(1000)
+    };
+    for attempt in 0..5 {
+        let upper = Duration::from_millis(100 * 2u64.pow(attempt as u32)).min(Duration::from_millis(1000));
+        let delay = policy.delay(attempt, None);
+        assert!(delay >= upper / 2 && delay <= upper, "attempt {} has delay {:?}", attempt, delay);
+    }
+    assert_eq!(policy.delay(0, Some(Duration::from_secs(7))), Duration::from_secs(7));
+}
+
+#[test]
+fn test_basic_auth_header() {
+    use mock::{MockReply, MockTransport};
+
+    let mock = MockTransport::new().on("POST", "/", MockReply::json(200, serde_json::json!({})));
+    let upstream = Upstream::new("secret".into()).transport(mock.clone());
+    let _: serde_json::Value = futures::executor::block_on(post_json("https://mock/", &upstream, Some(&serde_json::json!({"a": 1})))).unwrap();
+
+    let request = &mock.requests()[0];
+    // "apikey:secret" in base64
+    assert_eq!(request.get_header("authorization"), Some("Basic YXBpa2V5OnNlY3JldA=="));
+    assert_eq!(request.get_header("Content-Type"), Some("application/json"));
+}
+
+#[test]
+fn test_get_json() {
+    let (url, received) = stub_server(|_| (200, r#"{"models": []}"#.to_owned()));
+    let upstream = Upstream::new("key".into());
+    let result: serde_json::Value = futures::executor::block_on(get_json(&format!("{}/v3/models?version=2018-05-01", url), &upstream)).unwrap();
+    assert_eq!(result, serde_json::json!({"models": []}));
+
+    let request = received.lock().unwrap()[0].to_owned();
+    assert!(request.starts_with("GET /v3/models?version=2018-05-01 "), "{}", request);
+    assert!(!request.contains("Content-Type: application/json"));
+}
+
+#[test]
+fn test_post_text() {
+    use mock::{MockReply, MockTransport};
+
+    let mock = MockTransport::new().on("POST", "/v3/identify", MockReply::json(200, serde_json::json!({"languages": []})));
+    let upstream = Upstream::new("secret".into()).transport(mock.clone());
+    let _: serde_json::Value = futures::executor::block_on(post_text("https://mock/v3/identify", &upstream, "สวัสดี")).unwrap();
+
+    let request = &mock.requests()[0];
+    assert_eq!(request.get_header("Content-Type"), Some("text/plain; charset=utf-8"));
+    assert_eq!(request.body, "สวัสดี".as_bytes());
+}
+
+#[test]
+fn test_cassette_redact_and_replay() {
+    use cassette::{CassetteTransport, REDACTED};
+    use mock::{MockReply, MockTransport};
+
+    let path = std::env::temp_dir().join(format!("cassette-{}.json", std::process::id()));
+    let mock = MockTransport::new()
+                .on("POST", "/identity/token", MockReply::json(200, serde_json::json!({"access_token": "tok-1", "refresh_token": "ref-1", "expires_in": 3600})))
+                .on("POST", "/message", MockReply::json(200, serde_json::json!({"answer": 42})));
+    let recorder = CassetteTransport::record(mock, &path);
+    let iam = IamAuthenticator::with_url("secret-key".to_owned(), "https://iam.mock/identity/token".to_owned()).transport(recorder.clone());
+    let upstream = Upstream::new(iam.into()).transport(recorder);
+    let recorded: serde_json::Value = futures::executor::block_on(post_json("https://wa.mock/message", &upstream, Some(&serde_json::json!({"q": 1})))).unwrap();
+
+    let content = std::fs::read_to_string(&path).unwrap();
+    assert!(!content.contains("secret-key"));
+    assert!(!content.contains("tok-1"));
+    assert!(!content.contains("ref-1"));
+    assert!(content.contains(REDACTED));
+
+    // replay need neither network nor valid credential
+    let replay = CassetteTransport::replay(&path).unwrap();
+    let upstream = Upstream::new("other-key".into()).transport(replay.clone());
+    let replayed: serde_json::Value = futures::executor::block_on(post_json("https://wa.mock/message", &upstream, Some(&serde_json::json!({"q": 1})))).unwrap();
+    assert_eq!(recorded, replayed);
+    assert_eq!(replay.remaining(), 1);
+
+    // request with different payload isn't replayed, and it isn't retried since it can never match
+    let replay = CassetteTransport::replay(&path).unwrap();
+    let upstream = Upstream::new("other-key".into()).transport(replay.clone()).retry(RetryPolicy {
+        max_retry: 3,
+        base_delay: Duration::from_secs(1),
+        max_delay: Duration::from_secs(1)
+    });
+    let started = std::time::Instant::now();
+    let result: Result<serde_json::Value, CurlErr> = futures::executor::block_on(post_json("https://wa.mock/message", &upstream, Some(&serde_json::json!({"q": 2}))));
+    assert!(matches!(result, Err(CurlErr::NotRecorded)));
+    assert!(started.elapsed() < Duration::from_millis(500));
+    assert!(!CurlErr::NotRecorded.is_transient());
+
+    // every matching interaction has been used
+    let result: Result<serde_json::Value, CurlErr> = futures::executor::block_on(post_json::<(), _>("https://wa.mock/message", &upstream, None));
+    assert!(matches!(result, Err(CurlErr::NotRecorded)));
+    std::fs::remove_file(&path).ok();
+}
@@ -0,0 +1,148 @@
+//! HTTP transport that actually deliver request.
+//!
+//! [post_json](../fn.post_json.html), [post_text](../fn.post_text.html), [get_json](../fn.get_json.html) and [delete](../fn.delete.html) build
+//! [HttpRequest](struct.HttpRequest.html) then hand it to the
+//! [Transport](trait.Transport.html) of the [Upstream](../struct.Upstream.html).
+//! The default one is [IsahcTransport](struct.IsahcTransport.html) which send it over network.
+//! Tests can inject [MockTransport](../mock/struct.MockTransport.html) instead.
+
+use futures::future::BoxFuture;
+use isahc::{AsyncReadResponseExt, Request};
+use isahc::config::Configurable;
+use isahc::error::ErrorKind;
+use std::fmt::Debug;
+use super::{CurlErr, Timeout};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Post,
+    Delete
+}
+
+impl Method {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Post => "POST",
+            Method::Delete => "DELETE"
+        }
+    }
+}
+
+/// Fully built request, including authorization header.
+#[derive(Clone, Debug)]
+pub struct HttpRequest {
+    pub method: Method,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// Connect timeout and total timeout, already shorten to fit deadline.
+    pub timeout: Timeout
+}
+
+impl HttpRequest {
+    pub fn new(method: Method, url: &str, timeout: Timeout) -> HttpRequest {
+        HttpRequest {
+            method,
+            url: url.to_owned(),
+            headers: Vec::new(),
+            body: Vec::new(),
+            timeout
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> HttpRequest {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    pub fn body(mut self, body: Vec<u8>) -> HttpRequest {
+        self.body = body;
+        self
+    }
+
+    /// Value of first header with given name. Header name is case insensitive.
+    pub fn get_header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+}
+
+/// Response as received from server, regardless of status code.
+#[derive(Clone, Debug)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>
+}
+
+impl HttpResponse {
+    /// Value of first header with given name. Header name is case insensitive.
+    pub fn get_header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+}
+
+fn find_header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+}
+
+/// Deliver [HttpRequest](struct.HttpRequest.html) and return [HttpResponse](struct.HttpResponse.html).
+///
+/// Implementation shall return `CurlErr::Timeout` if request exceed its timeout and
+/// `CurlErr::RequestFail` if it cannot reach the server at all.
+/// Non 2xx response is not an error at this level.
+pub trait Transport: Debug + Send + Sync {
+    fn send(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse, CurlErr>>;
+}
+
+/// Send request over network using libcurl multi interface through `isahc`.
+#[derive(Debug, Default)]
+pub struct IsahcTransport;
+
+impl Transport for IsahcTransport {
+    fn send(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse, CurlErr>> {
+        Box::pin(async move {
+            let mut builder = Request::builder()
+                                      .method(request.method.as_str())
+                                      .uri(&request.url)
+                                      .connect_timeout(request.timeout.connect)
+                                      .timeout(request.timeout.total);
+            for (name, value) in request.headers.iter() {
+                builder = builder.header(name.as_str(), value.as_str());
+            }
+            let http_request = match builder.body(request.body) {
+                Ok(r) => r,
+                Err(_) => return Err(CurlErr::InvalidUrl)
+            };
+
+            let mut response = match isahc::send_async(http_request).await {
+                Ok(response) => response,
+                Err(e) if e.kind() == ErrorKind::Timeout => {
+                    log_warn!("Request timed out: {}", e);
+                    return Err(CurlErr::Timeout);
+                },
+                Err(e) => {
+                    log_warn!("Fail to send request with following error: {}", e);
+                    return Err(CurlErr::RequestFail);
+                }
+            };
+
+            let headers = response.headers()
+                                  .iter()
+                                  .filter_map(|(n, v)| v.to_str().ok().map(|v| (n.as_str().to_owned(), v.to_owned())))
+                                  .collect();
+            match response.bytes().await {
+                Ok(body) => Ok(HttpResponse {
+                    status: response.status().as_u16(),
+                    headers,
+                    body
+                }),
+                Err(e) => {
+                    log_warn!("Cannot read data from response with following error: {}", e);
+                    Err(CurlErr::UnexpectedOutputData)
+                }
+            }
+        })
+    }
+}
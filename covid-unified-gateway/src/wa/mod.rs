@@ -16,8 +16,14 @@
 //! UserInput object to send.
 //! To Obtain [UserInput](struct.UserInput.html) object, call 
 //! [build](struct.UserInputBuilder.html#method.build) method
-
-use super::utils::{delete, post_json, CurlErr};
+//!
+//! Every method return [GatewayErr](../error/struct.GatewayErr.html) on failure.
+//! Use [is_session_expired](../error/struct.GatewayErr.html#method.is_session_expired)
+//! to decide whether to [renew](struct.WASession.html#method.renew) the session.
+
+use super::config::WaConfig;
+use super::error::{ErrKind, GatewayErr};
+use super::utils::{delete, post_json, Upstream};
 use serde::{Deserialize, Serialize};
 use serde_json::value::{RawValue, Value};
 use std::boxed::Box;
@@ -25,76 +31,93 @@ use std::collections::HashMap;
 
 #[derive(Debug)]
 pub struct WASession {
-    api_key: String,
+    upstream: Upstream,
     pub session_id: String,
+    assistant_url: String,
+    version: String,
     session_url: String,
     delete_url: String,
     send_url: String
 }
 
 impl WASession {
-    /// Construct a new session.
+    /// Construct a new session of the assistant in `config`.
     /// It will immediately establish a session with WA.
-    pub async fn new(endpoint_url: String, api_key: String, assistant_id: String, version: String) -> Result<WASession, CurlErr> {
-        let session_url = format!("{}/v2/assistants/{}/sessions?version={}", endpoint_url, assistant_id, version);
-        let result = post_json::<(), HashMap<String, String>>(&session_url, &api_key, None)?;
-        let session_id = result["session_id"].to_owned();
-        let delete_url = format!("{}/v2/assistants/{}/sessions/{}version={}", &endpoint_url, &assistant_id, &session_id, &version);
-        let send_url = format!("{}/v2/assistants/{}/sessions/{}/message?version={}", &endpoint_url, &assistant_id, &session_id, &version);
-
-        Ok(WASession {
-            api_key,
-            session_id,
-            session_url,
-            send_url,
-            delete_url
-        })
+    /// `upstream` can be plain API key or [Upstream](../utils/struct.Upstream.html).
+    pub async fn new<U: Into<Upstream>>(config: &WaConfig, upstream: U) -> Result<WASession, GatewayErr> {
+        let upstream = upstream.into();
+        let session_url = format!("{}/v2/assistants/{}/sessions?version={}", config.service.endpoint, config.assistant_id, config.service.version);
+        let session_id = WASession::create_session(&session_url, &upstream).await?;
+
+        Ok(WASession::re_attach(config, upstream, session_id))
     }
 
     /// Construct WASession reusing established session.
     /// It take all parameters required to create new session along with session_id which is string
     /// that can be found in `WASession.session_id`.
     /// It doesn't check whether the `session_id` is valid, nor usable.
-    pub fn re_attach(endpoint_url: String, api_key: String, assistant_id: String, version: String, session_id: String) -> WASession {
-        let session_url = format!("{}/v2/assistants/{}/sessions?version={}", endpoint_url, assistant_id, version);
-        let session_id = session_id;
-        let delete_url = format!("{}/v2/assistants/{}/sessions/{}version={}", &endpoint_url, &assistant_id, &session_id, &version);
-        let send_url = format!("{}/v2/assistants/{}/sessions/{}/message?version={}", &endpoint_url, &assistant_id, &session_id, &version);
+    pub fn re_attach<U: Into<Upstream>>(config: &WaConfig, upstream: U, session_id: String) -> WASession {
+        let version = config.service.version.to_owned();
+        let assistant_url = format!("{}/v2/assistants/{}", config.service.endpoint, config.assistant_id);
+        let session_url = format!("{}/sessions?version={}", assistant_url, version);
+        let (delete_url, send_url) = WASession::session_urls(&assistant_url, &session_id, &version);
 
         WASession {
-            api_key,
+            upstream: upstream.into(),
             session_id,
+            assistant_url,
+            version,
             session_url,
             send_url,
             delete_url
         }
     }
 
+    /// Request new session id
+    async fn create_session(session_url: &str, upstream: &Upstream) -> Result<String, GatewayErr> {
+        let mut result = post_json::<(), HashMap<String, String>>(session_url, upstream, None).await
+                                    .map_err(|e| GatewayErr::from(e).context("Fail to create WA session"))?;
+        result.remove("session_id").ok_or_else(|| GatewayErr::new(ErrKind::Decode, "WA return no session_id"))
+    }
+
+    /// Build url to delete session and url to send message to given session
+    fn session_urls(assistant_url: &str, session_id: &str, version: &str) -> (String, String) {
+        (
+            format!("{}/sessions/{}?version={}", assistant_url, session_id, version),
+            format!("{}/sessions/{}/message?version={}", assistant_url, session_id, version)
+        )
+    }
+
     /// Create new session and replace old session with new session.
-    pub async fn renew(&mut self) -> Result<(), CurlErr> {
-        let result = post_json::<(), HashMap<String, String>>(&self.session_url, &self.api_key, None)?;
-        self.session_id = result["session_id"].to_owned();
+    /// It is useful when WA report that the session has expired.
+    pub async fn renew(&mut self) -> Result<(), GatewayErr> {
+        self.session_id = WASession::create_session(&self.session_url, &self.upstream).await?;
+        let (delete_url, send_url) = WASession::session_urls(&self.assistant_url, &self.session_id, &self.version);
+        self.delete_url = delete_url;
+        self.send_url = send_url;
         Ok(())
     }
 
     /// Primitive function to send user input.
-    pub async fn send<'a>(&self, message: &UserInput<'a>) -> Result<WAResponse, CurlErr> {
-        post_json(&self.send_url, &self.api_key, Some(message))
+    pub async fn send<'a>(&self, message: &UserInput<'a>) -> Result<WAResponse, GatewayErr> {
+        post_json(&self.send_url, &self.upstream, Some(message)).await
+            .map_err(|e| GatewayErr::from(e).context(format!("Fail to send message to WA session {}", self.session_id)))
     }
 
     /// User friendly function to let user send simple text message to WA
-    pub async fn send_txt(&self, input: &str) -> Result<WAResponse, CurlErr> {
-        post_json(&self.send_url, &self.api_key, Some(&UserInputBuilder::builder().text(input).options(InputOptions::default()).build()))
+    pub async fn send_txt(&self, input: &str) -> Result<WAResponse, GatewayErr> {
+        self.send(&UserInputBuilder::builder().text(input).options(InputOptions::default()).build()).await
     }
 
     /// User friendly function to let user simple text message along with message context to WA
-    pub async fn send_txt_with_context(&self, input: &str, context: UnknownType) -> Result<WAResponse, CurlErr> {
-        post_json(&self.send_url, &self.api_key, Some(&UserInputBuilder::builder().text(input).options(InputOptions::default()).context(ContextBuilder::builder().user_defined(context).build()).build()))
+    pub async fn send_txt_with_context(&self, input: &str, context: UnknownType) -> Result<WAResponse, GatewayErr> {
+        self.send(&UserInputBuilder::builder().text(input).options(InputOptions::default()).context(ContextBuilder::builder().user_defined(context).build()).build()).await
     }
 
     /// Terminate the session.
-    pub async fn close(self) -> Result<(), CurlErr> {
-        delete(&self.delete_url, &self.api_key)
+    pub async fn close(self) -> Result<(), GatewayErr> {
+        delete(&self.delete_url, &self.upstream).await
+            .map_err(|e| GatewayErr::from(e).context(format!("Fail to close WA session {}", self.session_id)))
     }
 }
 
@@ -379,13 +402,13 @@ pub struct EntityBuilder {
     entity: Entity
 }
 
-impl<'a> EntityBuilder {
+impl EntityBuilder {
     pub fn builder(entity: String, location: [usize;2], value: String) -> EntityBuilder {
         EntityBuilder {
             entity: Entity {
-                entity: entity,
-                location: location,
-                value: value,
+                entity,
+                location,
+                value,
                 confidence: None,
                 metadata: None,
                 groups: None,
@@ -637,6 +660,24 @@ pub struct SearchResult {
     pub highlight: Option<HashMap<String, Vec<String>>>
 }
 
+impl SearchResult {
+    /// Every text of this result that user read with its name, e.g. `title` or `highlight.body[0]`.
+    /// Highlights are ordered by field name so the order is the same every time.
+    pub fn fields_mut(&mut self) -> Vec<(String, &mut String)> {
+        let mut fields: Vec<(String, &mut String)> = Vec::new();
+        fields.extend(self.title.as_mut().map(|t| ("title".to_owned(), t)));
+        fields.extend(self.body.as_mut().map(|b| ("body".to_owned(), b)));
+        if let Some(ref mut highlight) = self.highlight {
+            let mut highlight: Vec<(&String, &mut Vec<String>)> = highlight.iter_mut().collect();
+            highlight.sort_by_key(|(name, _)| name.as_str());
+            for (name, passages) in highlight {
+                fields.extend(passages.iter_mut().enumerate().map(|(i, p)| (format!("highlight.{}[{}]", name, i), p)));
+            }
+        }
+        fields
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ResponseGeneric {
     pub response_type: ResponseType,
@@ -665,7 +706,54 @@ pub struct ResponseGeneric {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub header: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub results: Option<Vec<SearchResult>>
+    pub results: Option<Vec<SearchResult>>,
+    /// Custom data set by dialog author. `{"notranslate": true}` keep the whole response untranslated.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_defined: Option<Value>
+}
+
+impl ResponseGeneric {
+    /// Whether dialog author flag this response with `"user_defined": {"notranslate": true}`.
+    pub fn no_translate(&self) -> bool {
+        self.user_defined.as_ref().and_then(|u| u.get("notranslate")).and_then(Value::as_bool).unwrap_or(false)
+    }
+
+    /// Every text of this response that user read, in the order they are shown, with its name,
+    /// e.g. `title` or `options[0].label`.
+    pub fn fields_mut(&mut self) -> Vec<(String, &mut String)> {
+        let mut fields: Vec<(String, &mut String)> = Vec::new();
+        match self.response_type {
+            ResponseType::Text => fields.extend(self.text.as_mut().map(|t| ("text".to_owned(), t))),
+            ResponseType::Image => {
+                fields.extend(self.title.as_mut().map(|t| ("title".to_owned(), t)));
+                fields.extend(self.description.as_mut().map(|d| ("description".to_owned(), d)));
+            },
+            ResponseType::Option => {
+                fields.extend(self.title.as_mut().map(|t| ("title".to_owned(), t)));
+                fields.extend(self.description.as_mut().map(|d| ("description".to_owned(), d)));
+                if let Some(ref mut options) = self.options {
+                    fields.extend(options.iter_mut().enumerate().map(|(i, o)| (format!("options[{}].label", i), &mut o.label)));
+                }
+            },
+            ResponseType::ConnectToAgent => fields.extend(self.message_to_human_agent.as_mut().map(|m| ("message_to_human_agent".to_owned(), m))),
+            ResponseType::Suggestion => {
+                fields.extend(self.title.as_mut().map(|t| ("title".to_owned(), t)));
+                if let Some(ref mut suggestions) = self.suggestions {
+                    fields.extend(suggestions.iter_mut().enumerate().map(|(i, s)| (format!("suggestions[{}].label", i), &mut s.label)));
+                }
+            },
+            ResponseType::Search => {
+                fields.extend(self.header.as_mut().map(|h| ("header".to_owned(), h)));
+                if let Some(ref mut results) = self.results {
+                    for (i, result) in results.iter_mut().enumerate() {
+                        fields.extend(result.fields_mut().into_iter().map(|(name, text)| (format!("results[{}].{}", i, name), text)));
+                    }
+                }
+            },
+            ResponseType::Pause => {}
+        }
+        fields
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -1,4 +1,46 @@
 use super::*;
+use crate::config::ServiceConfig;
+use crate::utils::mock::{MockReply, MockTransport};
+use crate::utils::RetryPolicy;
+use serde_json::json;
+use std::time::Duration;
+
+const ENDPOINT: &str = "https://wa.mock";
+const ASSISTANT_ID: &str = "assistant";
+const VERSION: &str = "2020-04-01";
+
+/// Upstream that deliver every request to `mock` and retry quickly.
+fn mock_upstream(mock: &MockTransport) -> Upstream {
+    Upstream::new("apikey".into())
+        .transport(mock.clone())
+        .retry(RetryPolicy {
+            max_retry: 2,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(5)
+        })
+}
+
+fn config() -> WaConfig {
+    WaConfig {
+        service: ServiceConfig::new(ENDPOINT, VERSION, "key"),
+        assistant_id: ASSISTANT_ID.to_owned()
+    }
+}
+
+/// Minimal WA v2 message response with single text response.
+fn text_response(text: &str) -> serde_json::Value {
+    json!({
+        "output": {
+            "generic": [{"response_type": "text", "text": text}],
+            "intents": [{"intent": "greeting", "confidence": 0.9}],
+            "entities": []
+        }
+    })
+}
+
+fn new_session(mock: &MockTransport) -> Result<WASession, GatewayErr> {
+    futures::executor::block_on(WASession::new(&config(), mock_upstream(mock)))
+}
 
 #[test]
 fn test_create_entity_builder() {
@@ -35,68 +77,235 @@ fn test_build_user_input() {
 }
 
 #[test]
-fn test_create_close_session() -> Result<(), CurlErr> {
-    dotenv::dotenv().unwrap();
-    let endpoint = std::env::var("WA_ENDPOINT").expect("Fail to find WA_ENDPOINT from environment variable");
-    let id = std::env::var("WA_ID").expect("Fail to find WA_ID from environment variable");
-    let api_key = std::env::var("WA_APIKEY").expect("Fail to find WA_APIKEY from environment variable");
-    let version = std::env::var("WA_VERSION").expect("Fail to find WA_VERSION from environment variable");
+fn test_create_close_session() -> Result<(), GatewayErr> {
+    let mock = MockTransport::new()
+                    .on("POST", "/sessions?", MockReply::json(200, json!({"session_id": "s1"})))
+                    .on("DELETE", "/sessions/s1?", MockReply::json(200, json!({})));
     
     futures::executor::block_on(async {
-        let session = WASession::new(endpoint, api_key, id, version).await?;
+        let session = WASession::new(&config(), mock_upstream(&mock)).await?;
+        assert_eq!(session.session_id, "s1");
         session.close().await?;
-        Ok(())
-    })
+        Ok::<(), GatewayErr>(())
+    })?;
+
+    let requests = mock.requests();
+    assert_eq!(requests[0].url, "https://wa.mock/v2/assistants/assistant/sessions?version=2020-04-01");
+    assert_eq!(requests[1].url, "https://wa.mock/v2/assistants/assistant/sessions/s1?version=2020-04-01");
+    Ok(())
 }
 
 #[test]
-fn test_create_send_session() -> Result<(), CurlErr> {
-    dotenv::dotenv().unwrap();
-    let endpoint = std::env::var("WA_ENDPOINT").expect("Fail to find WA_ENDPOINT from environment variable");
-    let id = std::env::var("WA_ID").expect("Fail to find WA_ID from environment variable");
-    let api_key = std::env::var("WA_APIKEY").expect("Fail to find WA_APIKEY from environment variable");
-    let version = std::env::var("WA_VERSION").expect("Fail to find WA_VERSION from environment variable");
+fn test_create_send_session() -> Result<(), GatewayErr> {
+    let mock = MockTransport::new()
+                    .on("POST", "/sessions?", MockReply::json(200, json!({"session_id": "s1"})))
+                    .on("POST", "/sessions/s1/message", MockReply::json(200, text_response("Hello")));
     let msg = UserInputBuilder::builder()
                                     .text("greeting !")
                                     .build();
     futures::executor::block_on(async {
-        let session = WASession::new(endpoint, api_key, id, version).await?;
+        let session = WASession::new(&config(), mock_upstream(&mock)).await?;
         let result: WAResponse = session.send(&msg).await?;
-        println!("{:?}", result);
-        Ok(())
-    })
+        assert_eq!(result.output.generic[0].text.as_deref(), Some("Hello"));
+        Ok::<(), GatewayErr>(())
+    })?;
+
+    let sent: serde_json::Value = serde_json::from_slice(&mock.requests()[1].body).unwrap();
+    assert_eq!(sent["input"]["text"], "greeting !");
+    Ok(())
 }
+
 #[test]
-fn test_create_send_txt_session() -> Result<(), CurlErr> {
-    dotenv::dotenv().unwrap();
-    let endpoint = std::env::var("WA_ENDPOINT").expect("Fail to find WA_ENDPOINT from environment variable");
-    let id = std::env::var("WA_ID").expect("Fail to find WA_ID from environment variable");
-    let api_key = std::env::var("WA_APIKEY").expect("Fail to find WA_APIKEY from environment variable");
-    let version = std::env::var("WA_VERSION").expect("Fail to find WA_VERSION from environment variable");
+fn test_create_send_txt_session() -> Result<(), GatewayErr> {
+    let mock = MockTransport::new()
+                    .on("POST", "/sessions?", MockReply::json(200, json!({"session_id": "s1"})))
+                    .on("POST", "/sessions/s1/message", MockReply::json(200, text_response("Hi")));
     
     futures::executor::block_on(async {
-        let session = WASession::new(endpoint, api_key, id, version).await?;
+        let session = WASession::new(&config(), mock_upstream(&mock)).await?;
         let result: WAResponse = session.send_txt("hey there").await?;
-        println!("{:?}", result);
+        assert_eq!(result.output.generic[0].text.as_deref(), Some("Hi"));
         Ok(())
     })
 }
 
 #[test]
-fn test_create_reattach_session() -> Result<(), CurlErr> {
-    dotenv::dotenv().unwrap();
-    let endpoint = std::env::var("WA_ENDPOINT").expect("Fail to find WA_ENDPOINT from environment variable");
-    let id = std::env::var("WA_ID").expect("Fail to find WA_ID from environment variable");
-    let api_key = std::env::var("WA_APIKEY").expect("Fail to find WA_APIKEY from environment variable");
-    let version = std::env::var("WA_VERSION").expect("Fail to find WA_VERSION from environment variable");
+fn test_create_reattach_session() -> Result<(), GatewayErr> {
+    let mock = MockTransport::new()
+                    .on("POST", "/sessions?", MockReply::json(200, json!({"session_id": "s1"})))
+                    .on("POST", "/sessions/s1/message", MockReply::json(200, text_response("Hello again")));
     let msg = UserInputBuilder::builder()
                                     .text("greeting !")
                                     .build();
     futures::executor::block_on(async {
-        let session = WASession::new(endpoint.to_owned(), api_key.to_owned(), id.to_owned(), version.to_owned()).await?;
-        let another_session = WASession::re_attach(endpoint, api_key, id, version, session.session_id);
+        let session = WASession::new(&config(), mock_upstream(&mock)).await?;
+        let another_session = WASession::re_attach(&config(), mock_upstream(&mock), session.session_id);
         let result : WAResponse = another_session.send(&msg).await?;
-        println!("{:?}", result);
-        Ok(())
-    })
-}
\ No newline at end of file
+        assert_eq!(result.output.generic[0].text.as_deref(), Some("Hello again"));
+        Ok::<(), GatewayErr>(())
+    })?;
+
+    assert_eq!(mock.count("/sessions?"), 1);
+    Ok(())
+}
+
+#[test]
+fn test_send_retry_transient_failure() -> Result<(), GatewayErr> {
+    let mock = MockTransport::new()
+                    .on("POST", "/sessions?", MockReply::request_fail())
+                    .on("POST", "/sessions?", MockReply::json(200, json!({"session_id": "s1"})))
+                    .on("POST", "/message", MockReply::status(503))
+                    .on("POST", "/message", MockReply::status(429).header("Retry-After", "0"))
+                    .on("POST", "/message", MockReply::json(200, text_response("Hi")));
+
+    let session = new_session(&mock)?;
+    futures::executor::block_on(session.send_txt("hey"))?;
+    assert_eq!(mock.count("/sessions?"), 2);
+    assert_eq!(mock.count("/message"), 3);
+    Ok(())
+}
+
+#[test]
+fn test_send_not_retry_bad_request() -> Result<(), GatewayErr> {
+    let mock = MockTransport::new()
+                    .on("POST", "/sessions?", MockReply::json(200, json!({"session_id": "s1"})))
+                    .on("POST", "/message", MockReply::status(400));
+
+    let session = new_session(&mock)?;
+    let result = futures::executor::block_on(session.send_txt("hey"));
+    assert_eq!(result.unwrap_err().http().map(|e| e.status), Some(400));
+    assert_eq!(mock.count("/message"), 1);
+    Ok(())
+}
+
+#[test]
+fn test_renew_expired_session() -> Result<(), GatewayErr> {
+    let mock = MockTransport::new()
+                    .on("POST", "/sessions?", MockReply::json(200, json!({"session_id": "s1"})))
+                    .on("POST", "/sessions?", MockReply::json(200, json!({"session_id": "s2"})))
+                    .on("POST", "/sessions/s1/message", MockReply::json(404, json!({"error": "Invalid Session", "code": 404})))
+                    .on("POST", "/sessions/s2/message", MockReply::json(200, text_response("Hi")));
+
+    let mut session = new_session(&mock)?;
+    match futures::executor::block_on(session.send_txt("hey")) {
+        Err(e) => assert!(e.is_session_expired()),
+        Ok(r) => panic!("Expect expired session but got {:?}", r)
+    }
+    futures::executor::block_on(session.renew())?;
+    assert_eq!(session.session_id, "s2");
+    futures::executor::block_on(session.send_txt("hey"))?;
+    Ok(())
+}
+
+#[test]
+fn test_send_timeout() -> Result<(), GatewayErr> {
+    let mock = MockTransport::new()
+                    .on("POST", "/sessions?", MockReply::json(200, json!({"session_id": "s1"})))
+                    .on("POST", "/message", MockReply::json(200, text_response("late")).latency(Duration::from_secs(5)));
+    let upstream = mock_upstream(&mock).retry(RetryPolicy::no_retry()).timeout(crate::utils::Timeout {
+        connect: Duration::from_millis(10),
+        total: Duration::from_millis(20)
+    });
+
+    let session = WASession::re_attach(&config(), upstream, "s1".to_owned());
+    let result = futures::executor::block_on(session.send_txt("hey"));
+    assert_eq!(result.unwrap_err().kind(), ErrKind::Timeout);
+    Ok(())
+}
+
+#[test]
+fn test_malformed_response() -> Result<(), GatewayErr> {
+    let mock = MockTransport::new()
+                    .on("POST", "/message", MockReply::raw(200, b"{\"output\": ".to_vec()));
+
+    let session = WASession::re_attach(&config(), mock_upstream(&mock), "s1".to_owned());
+    let result = futures::executor::block_on(session.send_txt("hey"));
+    assert_eq!(result.unwrap_err().kind(), ErrKind::Decode);
+    Ok(())
+}
+
+#[test]
+fn test_missing_session_id() {
+    let mock = MockTransport::new().on("POST", "/sessions?", MockReply::json(200, json!({})));
+    let err = new_session(&mock).unwrap_err();
+    assert_eq!(err.kind(), ErrKind::Decode);
+}
+
+#[test]
+fn test_error_context() {
+    let mock = MockTransport::new().on("POST", "/message", MockReply::status(400).header("X-Global-Transaction-Id", "tx-1"));
+    let session = WASession::re_attach(&config(), mock_upstream(&mock), "s1".to_owned());
+    let err = futures::executor::block_on(session.send_txt("hey")).unwrap_err();
+    assert_eq!(err.kind(), ErrKind::WatsonApi);
+    assert_eq!(err.report(), "Fail to send message to WA session s1: Server reject the request: HTTP 400 Mock error 400 (transaction id tx-1)");
+}
+
+#[test]
+fn test_response_texts() {
+    let mut response: ResponseGeneric = serde_json::from_value(json!({
+        "response_type": "option",
+        "title": "Pick one",
+        "options": [{"label": "Symptom", "value": {"input": {"text": "symptom"}}}],
+        "user_defined": {"notranslate": true}
+    })).unwrap();
+    assert!(response.no_translate());
+    let fields: Vec<(String, String)> = response.fields_mut().into_iter().map(|(name, text)| (name, text.to_owned())).collect();
+    assert_eq!(fields, vec![("title".to_owned(), "Pick one".to_owned()), ("options[0].label".to_owned(), "Symptom".to_owned())]);
+    response.user_defined = Some(json!({"notranslate": false}));
+    assert!(!response.no_translate());
+}
+
+/// Name and text of every field of `response` given as JSON.
+fn fields(response: serde_json::Value) -> Vec<(String, String)> {
+    let mut response: ResponseGeneric = serde_json::from_value(response).unwrap();
+    response.fields_mut().into_iter().map(|(name, text)| (name, text.to_owned())).collect()
+}
+
+fn owned(fields: &[(&str, &str)]) -> Vec<(String, String)> {
+    fields.iter().map(|(name, text)| (name.to_string(), text.to_string())).collect()
+}
+
+#[test]
+fn test_fields_of_every_response_type() {
+    assert_eq!(fields(json!({"response_type": "image", "source": "https://ddc.moph.go.th/mask.png", "title": "Wear mask", "description": "Cover nose and mouth"})),
+               owned(&[("title", "Wear mask"), ("description", "Cover nose and mouth")]));
+    assert_eq!(fields(json!({"response_type": "connect_to_agent", "message_to_human_agent": "User ask about test", "topic": "covid"})),
+               owned(&[("message_to_human_agent", "User ask about test")]));
+    assert_eq!(fields(json!({
+                    "response_type": "suggestion",
+                    "title": "Did you mean",
+                    "suggestions": [{"label": "Symptom", "value": {"input": {"text": "symptom"}}}]
+               })),
+               owned(&[("title", "Did you mean"), ("suggestions[0].label", "Symptom")]));
+    assert_eq!(fields(json!({"response_type": "option", "title": "Pick one", "description": "Or type", "options": []})),
+               owned(&[("title", "Pick one"), ("description", "Or type")]));
+    assert!(fields(json!({"response_type": "pause", "time": 500, "typing": true})).is_empty());
+}
+
+#[test]
+fn test_fields_of_search() {
+    let search = json!({
+        "response_type": "search",
+        "header": "I found this",
+        "results": [
+            {
+                "id": "1",
+                "result_metadata": {"confidence": 0.9, "score": 1.5},
+                "title": "Quarantine",
+                "body": "Stay home for 14 days",
+                "url": "https://ddc.moph.go.th/quarantine",
+                "highlight": {"title": ["<em>Quarantine</em>"], "body": ["Stay <em>home</em>", "14 days"]}
+            },
+            {"id": "2", "result_metadata": {"confidence": 0.5, "score": 0.7}, "body": "Wash your hands"}
+        ]
+    });
+    assert_eq!(fields(search), owned(&[
+        ("header", "I found this"),
+        ("results[0].title", "Quarantine"),
+        ("results[0].body", "Stay home for 14 days"),
+        ("results[0].highlight.body[0]", "Stay <em>home</em>"),
+        ("results[0].highlight.body[1]", "14 days"),
+        ("results[0].highlight.title[0]", "<em>Quarantine</em>"),
+        ("results[1].body", "Wash your hands")
+    ]));
+}
@@ -0,0 +1,228 @@
+//! Cache of translation keyed by model id and source text.
+//!
+//! Most WA responses are the same fixed text every turn so there's no need to pay WLT for them again.
+//! [TranslationCache](struct.TranslationCache.html) has two layers. The first one is in memory and keep only
+//! the most recently used translations. The second one is read from file, the pre-warm file and the persistent file,
+//! and [save](struct.TranslationCache.html#method.save) write it back to persistent file so it survive between invocations.
+//! The second layer is bounded too, the least recently used translations are dropped so the file doesn't grow forever.
+//! Only WA response is cached, user message may have personal data so it is never kept, see
+//! [translate_pair](../fn.translate_pair.html).
+//!
+//! Both files are JSON object of model id to source text to translation, the same as `translations` of mock server:
+//! ```json
+//! {"en-th": {"Hi, how can I help?": "สวัสดี มีอะไรให้ช่วยไหม"}}
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use crate::config::{CacheConfig, DEFAULT_CACHE_FILE_SIZE};
+use crate::error::GatewayErr;
+use crate::logger::Record;
+
+/// Model id to source text to translation
+pub type Table = BTreeMap<String, BTreeMap<String, String>>;
+
+/// Number of text found and not found in the cache.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize
+}
+
+/// Translations kept in memory. The least recently used one is evicted when it is full.
+#[derive(Debug)]
+struct Lru {
+    capacity: usize,
+    tick: u64,
+    entries: HashMap<(String, String), (String, u64)>
+}
+
+impl Lru {
+    fn get(&mut self, model: &str, text: &str) -> Option<String> {
+        self.tick += 1;
+        let tick = self.tick;
+        // tuple of String cannot be borrowed as tuple of &str so the key is built
+        self.entries.get_mut(&(model.to_owned(), text.to_owned())).map(|(translation, used)| {
+            *used = tick;
+            translation.to_owned()
+        })
+    }
+
+    fn put(&mut self, model: &str, text: &str, translation: &str) {
+        if self.capacity == 0 {
+            return;
+        }
+        let key = (model.to_owned(), text.to_owned());
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&key) {
+            let oldest = self.entries.iter().min_by_key(|(_, (_, used))| *used).map(|(k, _)| k.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.tick += 1;
+        self.entries.insert(key, (translation.to_owned(), self.tick));
+    }
+}
+
+impl Lru {
+    fn new(capacity: usize) -> Lru {
+        Lru {
+            capacity,
+            tick: 0,
+            entries: HashMap::new()
+        }
+    }
+
+    fn table(&self) -> Table {
+        let mut table = Table::new();
+        for ((model, text), (translation, _)) in &self.entries {
+            table.entry(model.to_owned()).or_default().insert(text.to_owned(), translation.to_owned());
+        }
+        table
+    }
+}
+
+/// Translations read from file, and the new ones to be saved.
+#[derive(Debug)]
+struct Store {
+    entries: Lru,
+    dirty: bool
+}
+
+#[derive(Debug)]
+pub struct TranslationCache {
+    memory: Mutex<Lru>,
+    store: Mutex<Store>,
+    path: Option<PathBuf>,
+    hits: AtomicUsize,
+    misses: AtomicUsize
+}
+
+/// Read table from JSON file at `path`.
+fn read_table(path: &Path) -> Result<Table, GatewayErr> {
+    let content = std::fs::read_to_string(path).map_err(|e| GatewayErr::config(format!("Cannot read translation cache {}: {}", path.display(), e)))?;
+    serde_json::from_str(&content).map_err(|e| GatewayErr::config(format!("Translation cache {} is malformed: {}", path.display(), e)))
+}
+
+impl TranslationCache {
+    /// Cache that keep at most `capacity` translations in memory and nothing in file.
+    pub fn new(capacity: usize) -> TranslationCache {
+        TranslationCache {
+            memory: Mutex::new(Lru::new(capacity)),
+            store: Mutex::new(Store {
+                entries: Lru::new(DEFAULT_CACHE_FILE_SIZE),
+                dirty: false
+            }),
+            path: None,
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0)
+        }
+    }
+
+    /// Cache as configured. It fail with `ErrKind::Config` if pre-warm file cannot be read.
+    /// Persistent file that doesn't exist yet is created on save. Unreadable one is logged and started over.
+    pub fn from_config(config: &CacheConfig) -> Result<TranslationCache, GatewayErr> {
+        let mut cache = TranslationCache::new(config.capacity).file_capacity(config.file_capacity);
+        if let Some(warm) = &config.warm {
+            cache = cache.warm(read_table(warm)?);
+        }
+        if let Some(path) = &config.path {
+            cache = cache.persist(path);
+        }
+        Ok(cache)
+    }
+
+    /// Keep at most `capacity` translations in file layer. Set it before [warm](#method.warm).
+    pub fn file_capacity(self, capacity: usize) -> TranslationCache {
+        self.store.lock().unwrap_or_else(|e| e.into_inner()).entries = Lru::new(capacity);
+        self
+    }
+
+    /// Put every translation of `table` in file layer. If there are more than it can keep, some are dropped.
+    pub fn warm(self, table: Table) -> TranslationCache {
+        {
+            let mut store = self.store.lock().unwrap_or_else(|e| e.into_inner());
+            for (model, translations) in table {
+                for (text, translation) in translations {
+                    store.entries.put(&model, &text, &translation);
+                }
+            }
+        }
+        self
+    }
+
+    /// Read file layer from `path` and save it back there.
+    pub fn persist<P: Into<PathBuf>>(mut self, path: P) -> TranslationCache {
+        let path = path.into();
+        if path.exists() {
+            match read_table(&path) {
+                Ok(table) => self = self.warm(table),
+                Err(e) => Record::warn(format!("Start translation cache over: {}", e.report())).emit()
+            }
+        }
+        self.path = Some(path);
+        self
+    }
+
+    /// Translation of `text` by the first of `models` that has one. It is counted as hit or miss.
+    pub fn get(&self, models: &[&str], text: &str) -> Option<String> {
+        let found = models.iter().find_map(|model| {
+            let mut memory = self.memory.lock().unwrap_or_else(|e| e.into_inner());
+            let mut store = self.store.lock().unwrap_or_else(|e| e.into_inner());
+            // looked up in file layer too so it know what is used recently
+            let stored = store.entries.get(model, text);
+            if let Some(translation) = memory.get(model, text) {
+                return Some(translation);
+            }
+            let translation = stored?;
+            memory.put(model, text, &translation);
+            Some(translation)
+        });
+        let counter = if found.is_some() { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        found
+    }
+
+    /// Keep `translation` of `text` by `model`.
+    pub fn put(&self, model: &str, text: &str, translation: &str) {
+        self.memory.lock().unwrap_or_else(|e| e.into_inner()).put(model, text, translation);
+        let mut store = self.store.lock().unwrap_or_else(|e| e.into_inner());
+        if store.entries.get(model, text).as_deref() != Some(translation) {
+            store.entries.put(model, text, translation);
+            store.dirty = true;
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed)
+        }
+    }
+
+    /// Write file layer to persistent file if there's new translation. The file is replaced at once so
+    /// concurrent invocation never read half written file.
+    pub fn save(&self) -> Result<(), GatewayErr> {
+        let path = match &self.path {
+            Some(p) => p,
+            None => return Ok(())
+        };
+        let mut store = self.store.lock().unwrap_or_else(|e| e.into_inner());
+        if !store.dirty {
+            return Ok(());
+        }
+        let fail = |e: std::io::Error| GatewayErr::config(format!("Cannot write translation cache {}: {}", path.display(), e));
+        let temp = PathBuf::from(format!("{}.{}.tmp", path.display(), fastrand::u64(..)));
+        let content = serde_json::to_string(&store.entries.table()).unwrap_or_default();
+        std::fs::write(&temp, content).map_err(fail)?;
+        std::fs::rename(&temp, path).map_err(|e| {
+            std::fs::remove_file(&temp).ok();
+            fail(e)
+        })?;
+        store.dirty = false;
+        Ok(())
+    }
+}
@@ -0,0 +1,159 @@
+//! Offline language detection.
+//!
+//! Language is guessed from Unicode script of the letters, i.e. Thai, Lao, Khmer, Myanmar, Latin and CJK.
+//! Latin text is told apart by small stopword lists. It doesn't need any request so it is used
+//! to skip WLT `/v3/identify` when the script alone is enough, and as fallback when identify fail.
+//!
+//! [can_skip_translation](fn.can_skip_translation.html) tell when text is already in the language
+//! it would be translated into, e.g. plain English typed by user who pick Thai, or `555` which has no letter at all.
+
+use super::IdentifiedLanguage;
+
+/// Detection at least this confident doesn't need to be confirmed by WLT.
+pub const CONFIDENT: f64 = 0.9;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Script {
+    Thai,
+    Lao,
+    Khmer,
+    Myanmar,
+    Latin,
+    /// Chinese character, also used in Japanese
+    Han,
+    /// Japanese Hiragana and Katakana
+    Kana,
+    /// Korean
+    Hangul
+}
+
+const STOPWORDS: [(&str, &[&str]); 6] = [
+    ("en", &["the", "a", "an", "is", "are", "was", "i", "you", "we", "they", "it", "what", "how", "where", "when", "can", "do",
+             "does", "to", "of", "in", "on", "for", "and", "my", "your", "have", "has", "not", "please", "thank", "thanks", "hello", "hi",
+             "with", "this", "that", "there"]),
+    ("id", &["yang", "dan", "di", "ke", "dari", "ini", "itu", "saya", "anda", "apa", "bagaimana", "tidak", "ada", "dengan", "untuk",
+             "bisa", "terima", "kasih"]),
+    ("fr", &["le", "la", "les", "un", "une", "est", "et", "je", "vous", "nous", "que", "qui", "pas", "pour", "dans", "avec", "bonjour",
+             "merci", "comment"]),
+    ("es", &["el", "los", "las", "una", "es", "y", "yo", "que", "como", "para", "con", "por", "hola", "gracias", "donde", "tengo"]),
+    ("de", &["der", "die", "das", "und", "ist", "ich", "sie", "wir", "nicht", "mit", "für", "wie", "was", "hallo", "danke", "ein", "eine"]),
+    ("vi", &["tôi", "bạn", "không", "là", "có", "của", "và", "được", "xin", "chào", "cảm", "ơn"])
+];
+
+/// Languages written in Latin script that WLT translate.
+const LATIN_LANGUAGES: [&str; 14] = ["en", "fr", "de", "es", "it", "pt", "nl", "id", "ms", "vi", "tl", "fil", "pl", "tr"];
+
+/// Script of letter `c`. Digit, punctuation, space and emoji has no script.
+pub fn script(c: char) -> Option<Script> {
+    if !c.is_alphabetic() {
+        return None;
+    }
+    match c as u32 {
+        0x0E00..=0x0E7F => Some(Script::Thai),
+        0x0E80..=0x0EFF => Some(Script::Lao),
+        0x1780..=0x17FF | 0x19E0..=0x19FF => Some(Script::Khmer),
+        0x1000..=0x109F | 0xAA60..=0xAA7F => Some(Script::Myanmar),
+        0x0041..=0x024F | 0x1E00..=0x1EFF => Some(Script::Latin),
+        0x3400..=0x4DBF | 0x4E00..=0x9FFF => Some(Script::Han),
+        0x3040..=0x30FF => Some(Script::Kana),
+        0x1100..=0x11FF | 0xAC00..=0xD7AF => Some(Script::Hangul),
+        _ => None
+    }
+}
+
+/// Script that `language` is written in, if it is known.
+pub fn language_script(language: &str) -> Option<Script> {
+    let base = language.split('-').next().unwrap_or_default();
+    match base {
+        "th" => Some(Script::Thai),
+        "lo" => Some(Script::Lao),
+        "km" => Some(Script::Khmer),
+        "my" => Some(Script::Myanmar),
+        "zh" => Some(Script::Han),
+        "ja" => Some(Script::Kana),
+        "ko" => Some(Script::Hangul),
+        l if LATIN_LANGUAGES.contains(&l) => Some(Script::Latin),
+        _ => None
+    }
+}
+
+/// Number of letters of each script in `text`.
+fn count_scripts(text: &str) -> Vec<(Script, usize)> {
+    let mut counts: Vec<(Script, usize)> = Vec::new();
+    for s in text.chars().filter_map(script) {
+        match counts.iter_mut().find(|(c, _)| *c == s) {
+            Some((_, n)) => *n += 1,
+            None => counts.push((s, 1))
+        }
+    }
+    counts.sort_by_key(|(_, n)| std::cmp::Reverse(*n));
+    counts
+}
+
+/// Whether `text` has any letter.
+pub fn has_letter(text: &str) -> bool {
+    text.chars().any(|c| script(c).is_some())
+}
+
+/// Guess language of Latin text from its stopwords. Text without any stopword is guessed as English
+/// with half confidence.
+fn detect_latin(text: &str) -> (&'static str, f64) {
+    let lowercase = text.to_lowercase();
+    let words: Vec<&str> = lowercase.split(|c: char| !c.is_alphabetic()).filter(|w| !w.is_empty()).collect();
+    let (language, hits) = STOPWORDS.iter()
+                                    .map(|(language, stopwords)| (*language, words.iter().filter(|w| stopwords.contains(w)).count()))
+                                    .fold(("en", 0), |best, current| if current.1 > best.1 { current } else { best });
+    if hits == 0 {
+        // letters that only Vietnamese use, e.g. ơ, ư and đ
+        if lowercase.chars().any(|c| "ăâđêôơưạảấầẩẫậắằẳẵặẹẻẽếềểễệỉịọỏốồổỗộớờởỡợụủứừửữựỳỵỷỹ".contains(c)) {
+            return ("vi", 0.9);
+        }
+        return ("en", 0.5);
+    }
+    (language, (0.5 + hits as f64 / words.len() as f64).min(1.0))
+}
+
+/// Guess language of `text`. It return `None` if text has no letter, e.g. `555` or emoji.
+/// Confidence is the share of letters in the dominant script, lowered for Latin text with few stopwords.
+pub fn detect(text: &str) -> Option<IdentifiedLanguage> {
+    let counts = count_scripts(text);
+    let total: usize = counts.iter().map(|(_, n)| n).sum();
+    let count = |s: Script| counts.iter().find(|(c, _)| *c == s).map_or(0, |(_, n)| *n);
+    let (dominant, n) = *counts.first()?;
+    let share = n as f64 / total as f64;
+    // Japanese is written in both Kana and Han
+    let japanese = (count(Script::Kana) + count(Script::Han)) as f64 / total as f64;
+    let (language, confidence) = match dominant {
+        Script::Thai => ("th", share),
+        Script::Lao => ("lo", share),
+        Script::Khmer => ("km", share),
+        Script::Myanmar => ("my", share),
+        Script::Kana => ("ja", japanese),
+        Script::Han if count(Script::Kana) > 0 => ("ja", japanese),
+        Script::Han => ("zh", share),
+        Script::Hangul => ("ko", share),
+        Script::Latin => {
+            let (language, confidence) = detect_latin(text);
+            (language, share * confidence)
+        }
+    };
+    Some(IdentifiedLanguage {
+        language: language.to_owned(),
+        confidence
+    })
+}
+
+/// Whether translating `text` from `source` to `target` language can be skipped because it won't change it.
+/// It is when `text` has no letter, when every letter is in script of `target` that isn't script of `source`,
+/// e.g. plain English typed by user who pick Thai, or when `text` is confidently detected as `target`.
+pub fn can_skip_translation(text: &str, source: &str, target: &str) -> bool {
+    let counts = count_scripts(text);
+    if counts.is_empty() {
+        return true;
+    }
+    let target_script = language_script(target);
+    if counts.len() == 1 && target_script == Some(counts[0].0) && language_script(source) != target_script {
+        return true;
+    }
+    detect(text).is_some_and(|d| d.language == target && d.confidence >= CONFIDENT)
+}
@@ -0,0 +1,187 @@
+//! Forced term pairs, e.g. `wlt-dataset/th/glossary.csv`.
+//!
+//! Glossary is read from CSV whose header row is language codes, e.g. `en,th`, or from TMX whose `<tu>` has
+//! one `<tuv xml:lang="..">` per language. Before text is sent to WLT, each term of source language is swapped
+//! for [placeholder](../placeholder/index.html) that is swapped back to the approved term of target language, so
+//! domain term such as `โควิด` is translated the same way even by base model.
+//!
+//! The same term may be listed more than once, e.g. `Covid,โควิด` and `Covid,โควิท`. Every spelling is
+//! recognized in source text and the first one listed is the approved translation.
+
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::Path;
+use crate::error::GatewayErr;
+use super::detect::{script, Script};
+use super::placeholder::Masked;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Glossary {
+    /// Each entry is term of the same meaning keyed by language code
+    entries: Vec<HashMap<String, String>>
+}
+
+/// Split CSV `line` into fields. Field may be quoted, with `""` for quote inside.
+fn csv_fields(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            },
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c)
+        }
+    }
+    fields.push(field);
+    fields.into_iter().map(|f| f.trim().to_owned()).collect()
+}
+
+/// Replace XML entities.
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&apos;", "'").replace("&amp;", "&")
+}
+
+/// Content of every `<tag ...>...</tag>` in `xml`, with its attributes.
+fn elements<'a>(xml: &'a str, tag: &str) -> Vec<(&'a str, &'a str)> {
+    let open = format!("<{}", tag);
+    let close = format!("</{}>", tag);
+    let mut found = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        let after = &rest[start + open.len()..];
+        // `<tu` shall not match `<tuv`
+        if !after.starts_with(|c: char| c == '>' || c.is_whitespace()) {
+            rest = after;
+            continue;
+        }
+        let (attributes, body) = match (after.find('>'), after.find(&close)) {
+            (Some(gt), Some(end)) if gt < end => (&after[..gt], &after[gt + 1..end]),
+            _ => break
+        };
+        found.push((attributes, body));
+        rest = &after[attributes.len() + 1 + body.len() + close.len()..];
+    }
+    found
+}
+
+/// Value of `name="value"` in element attributes.
+fn attribute<'a>(attributes: &'a str, name: &str) -> Option<&'a str> {
+    let start = attributes.find(&format!("{}=\"", name))? + name.len() + 2;
+    let len = attributes[start..].find('"')?;
+    Some(&attributes[start..start + len])
+}
+
+/// Whether `c` is part of Latin word, so term shall not match in the middle of it.
+fn is_word(c: char) -> bool {
+    c.is_ascii_digit() || script(c) == Some(Script::Latin)
+}
+
+/// End of `term` if `text` has it at `start`, ignoring case.
+fn match_at(text: &str, start: usize, term: &str) -> Option<usize> {
+    let mut chars = text[start..].char_indices();
+    for t in term.chars() {
+        let (_, c) = chars.next()?;
+        if !c.to_lowercase().eq(t.to_lowercase()) {
+            return None;
+        }
+    }
+    Some(chars.next().map_or(text.len(), |(i, _)| start + i))
+}
+
+impl Glossary {
+    /// Read CSV or TMX `content`. It fail with description of the problem.
+    pub fn parse(content: &str, tmx: bool) -> Result<Glossary, String> {
+        let entries = if tmx {
+            elements(content, "tu").into_iter().map(|(_, tu)| {
+                elements(tu, "tuv").into_iter().filter_map(|(attributes, tuv)| {
+                    let lang = attribute(attributes, "xml:lang").or_else(|| attribute(attributes, "lang"))?;
+                    let seg = elements(tuv, "seg").into_iter().next()?.1;
+                    Some((lang.to_owned(), unescape(seg.trim())))
+                }).collect::<HashMap<String, String>>()
+            }).collect()
+        } else {
+            let mut lines = content.lines().filter(|l| !l.trim().is_empty());
+            let header = csv_fields(lines.next().ok_or("Glossary is empty")?.trim_start_matches('\u{feff}'));
+            if header.len() < 2 || header.iter().any(|l| !crate::config::is_language_code(l)) {
+                return Err(format!("Header shall be language codes, found {}", header.join(",")));
+            }
+            lines.map(|line| header.iter().cloned().zip(csv_fields(line)).filter(|(_, term)| !term.is_empty()).collect()).collect()
+        };
+        Ok(Glossary { entries })
+    }
+
+    /// Read every file in `paths`. File ending with `.tmx` is read as TMX, others as CSV.
+    /// It fail with `ErrKind::Config` if any file cannot be read.
+    pub fn load<P: AsRef<Path>>(paths: &[P]) -> Result<Glossary, GatewayErr> {
+        let mut glossary = Glossary::default();
+        for path in paths {
+            let path = path.as_ref();
+            let tmx = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("tmx"));
+            let content = std::fs::read_to_string(path).map_err(|e| GatewayErr::config(format!("Cannot read glossary {}: {}", path.display(), e)))?;
+            let parsed = Glossary::parse(&content, tmx).map_err(|e| GatewayErr::config(format!("Glossary {} is malformed: {}", path.display(), e)))?;
+            glossary.entries.extend(parsed.entries);
+        }
+        Ok(glossary)
+    }
+
+    /// Term of `source` language and its approved translation in `target` language, the longest term first.
+    pub fn terms(&self, source: &str, target: &str) -> Vec<(&str, &str)> {
+        let mut terms: Vec<(&str, &str)> = Vec::new();
+        for entry in &self.entries {
+            if let (Some(s), Some(t)) = (entry.get(source), entry.get(target)) {
+                if !s.is_empty() && !terms.iter().any(|(known, _)| known.to_lowercase() == s.to_lowercase()) {
+                    terms.push((s, t));
+                }
+            }
+        }
+        terms.sort_by_key(|(s, _)| std::cmp::Reverse(s.chars().count()));
+        terms
+    }
+
+    /// Swap every term of `source` language in `text` for placeholder of its `target` language translation.
+    pub fn mask(&self, text: &str, source: &str, target: &str) -> Masked {
+        Masked::new(text, self.find(text, source, target))
+    }
+
+    /// Every term of `source` language in `text` with its `target` language translation.
+    /// Term is matched ignoring case, and Latin term only match whole word.
+    pub fn find(&self, text: &str, source: &str, target: &str) -> Vec<(Range<usize>, String)> {
+        let terms = self.terms(source, target);
+        let mut protected: Vec<(Range<usize>, String)> = Vec::new();
+        let mut start = 0;
+        let mut previous: Option<char> = None;
+        while start < text.len() {
+            let found = terms.iter().find_map(|(term, translation)| {
+                let end = match_at(text, start, term)?;
+                let first = term.chars().next()?;
+                let last = term.chars().last()?;
+                let inside_word = (is_word(first) && previous.is_some_and(is_word))
+                                  || (is_word(last) && text[end..].chars().next().is_some_and(is_word));
+                if inside_word {
+                    None
+                } else {
+                    Some((end, translation))
+                }
+            });
+            match found {
+                Some((end, translation)) => {
+                    protected.push((start..end, translation.to_string()));
+                    previous = text[..end].chars().last();
+                    start = end;
+                },
+                None => {
+                    let c = text[start..].chars().next().unwrap_or_default();
+                    previous = Some(c);
+                    start += c.len_utf8();
+                }
+            }
+        }
+        protected
+    }
+}
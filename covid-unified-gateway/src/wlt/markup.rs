@@ -0,0 +1,233 @@
+//! Translate only human readable part of text that has inline markup.
+//!
+//! WA response may have HTML tag, e.g. `<a href="https://ddc.moph.go.th">DDC</a>`, bare URL, markdown and emoji.
+//! WLT mangle them, e.g. it translate URL or drop `**`. [Markup](struct.Markup.html) split text into segments
+//! so only the readable ones are sent to WLT, then put the markup back around the translation.
+//! Link text is translated but link target is kept as is so link still work.
+//!
+//! Recognized markup are
+//! - HTML tag and entity, e.g. `<a href="...">`, `</a>`, `<br/>` and `&nbsp;`
+//! - URL starting with `http://`, `https://` or `www.`
+//! - markdown emphasis `*`, `_`, `~~`, inline code, link target `](...)`, and heading, list and quote at line start
+//! - emoji
+//! - `<notranslate>...</notranslate>` that dialog author put around wording that shall be kept exactly as written,
+//!   e.g. ministry name or legal disclaimer. Use [strip_no_translate](fn.strip_no_translate.html) to remove the markers
+//!   before text is returned.
+//!
+//! Space around segment and segment without any letter, e.g. `1422`, are kept as is.
+
+use std::ops::Range;
+use super::detect::has_letter;
+
+const NO_TRANSLATE_OPEN: &str = "<notranslate>";
+const NO_TRANSLATE_CLOSE: &str = "</notranslate>";
+
+/// Part of text that is either translated or kept as is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Segment<'a> {
+    Text(&'a str),
+    Markup(&'a str)
+}
+
+/// Text split into readable text and markup.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Markup<'a> {
+    pub segments: Vec<Segment<'a>>
+}
+
+fn is_emoji(c: char) -> bool {
+    matches!(c as u32, 0x1F000..=0x1FAFF | 0x2600..=0x27BF | 0x2B00..=0x2BFF | 0xFE0F | 0x200D | 0x20E3 | 0xE0020..=0xE007F)
+}
+
+/// Length of URL at start of `rest`, without trailing punctuation.
+fn url_len(rest: &str) -> Option<usize> {
+    let lowercase = rest.get(..8).unwrap_or(rest).to_ascii_lowercase();
+    if !["http://", "https://", "www."].iter().any(|p| lowercase.starts_with(p)) {
+        return None;
+    }
+    let end = rest.find(|c: char| c.is_whitespace() || c == '<' || c == '>' || c == '"').unwrap_or(rest.len());
+    let mut url = &rest[..end];
+    loop {
+        let trimmed = url.trim_end_matches(['.', ',', ';', ':', '!', '?', '\'']);
+        // closing bracket belong to URL only if it is opened in URL
+        let trimmed = match trimmed.strip_suffix(')') {
+            Some(t) if t.matches('(').count() <= t.matches(')').count() => t,
+            _ => trimmed
+        };
+        if trimmed.len() == url.len() {
+            break;
+        }
+        url = trimmed;
+    }
+    Some(url.len())
+}
+
+/// Length of markdown link target `](...)` at start of `rest`. Bracket inside target shall be balanced.
+fn link_target_len(rest: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in rest.char_indices().skip(1) {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 1 => return Some(i + 1),
+            ')' => depth -= 1,
+            c if c.is_whitespace() => return None,
+            _ => ()
+        }
+    }
+    None
+}
+
+/// Whether `rest` start with `marker`, ignoring case.
+fn starts_with_marker(rest: &str, marker: &str) -> bool {
+    rest.get(..marker.len()).is_some_and(|r| r.eq_ignore_ascii_case(marker))
+}
+
+/// Length of `<notranslate>...</notranslate>` at start of `rest`, ignoring case.
+/// Marker that is never closed cover the rest of text.
+fn no_translate_len(rest: &str) -> Option<usize> {
+    if !starts_with_marker(rest, NO_TRANSLATE_OPEN) {
+        return None;
+    }
+    let end = rest.to_ascii_lowercase().find(NO_TRANSLATE_CLOSE).map_or(rest.len(), |end| end + NO_TRANSLATE_CLOSE.len());
+    Some(end)
+}
+
+/// Remove every `<notranslate>` and `</notranslate>` marker, ignoring case, and keep what's inside as is.
+pub fn strip_no_translate(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut i = 0;
+    while i < text.len() {
+        let rest = &text[i..];
+        match [NO_TRANSLATE_OPEN, NO_TRANSLATE_CLOSE].iter().find(|m| starts_with_marker(rest, m)) {
+            Some(marker) => i += marker.len(),
+            None => {
+                let c = rest.chars().next().unwrap_or_default();
+                stripped.push(c);
+                i += c.len_utf8();
+            }
+        }
+    }
+    stripped
+}
+
+/// Length of markup at `i`, if any.
+fn markup_len(text: &str, i: usize, line_start: bool) -> Option<usize> {
+    let rest = &text[i..];
+    let c = rest.chars().next()?;
+    let previous = text[..i].chars().last();
+    if line_start {
+        let marker = rest.find(|c: char| !matches!(c, '#' | '-' | '*' | '+' | '>' | '0'..='9' | '.' | ' ' | '\t')).unwrap_or(rest.len());
+        let bullet = rest[..marker].trim_end();
+        let is_marker = bullet.chars().all(|c| c == '#')
+                        || matches!(bullet, "-" | "*" | "+" | ">")
+                        || (bullet.ends_with('.') && bullet[..bullet.len() - 1].chars().all(|c| c.is_ascii_digit()));
+        if !bullet.is_empty() && is_marker && rest[bullet.len()..].starts_with([' ', '\t']) {
+            return Some(marker);
+        }
+    }
+    if previous.is_none_or(|p| !p.is_alphanumeric()) {
+        if let Some(len) = url_len(rest) {
+            return Some(len);
+        }
+    }
+    if let Some(len) = no_translate_len(rest) {
+        return Some(len);
+    }
+    match c {
+        '<' if rest[1..].starts_with(|n: char| n.is_ascii_alphabetic() || n == '/' || n == '!') => {
+            let end = rest.find('>')?;
+            if rest[1..end].contains('<') { None } else { Some(end + 1) }
+        },
+        '&' => {
+            let end = rest.find(';')?;
+            let name = &rest[1..end];
+            let is_entity = !name.is_empty() && name.len() <= 8 && name.trim_start_matches('#').chars().all(|c| c.is_ascii_alphanumeric());
+            if is_entity { Some(end + 1) } else { None }
+        },
+        '`' => rest[1..].find('`').map(|end| end + 2),
+        ']' if rest[1..].starts_with('(') => link_target_len(rest).or(Some(1)),
+        '[' | ']' => Some(1),
+        '*' | '~' => Some(rest.len() - rest.trim_start_matches(c).len()),
+        // underscore inside word, e.g. snake_case, isn't emphasis
+        '_' if previous.is_none_or(|p| !p.is_alphanumeric()) || !rest[1..].starts_with(char::is_alphanumeric) => Some(rest.len() - rest.trim_start_matches('_').len()),
+        c if is_emoji(c) => Some(c.len_utf8()),
+        _ => None
+    }
+}
+
+impl<'a> Markup<'a> {
+    pub fn parse(text: &'a str) -> Markup<'a> {
+        // range and whether it is markup
+        let mut ranges: Vec<(Range<usize>, bool)> = Vec::new();
+        let mut push = |range: Range<usize>, markup: bool| {
+            match ranges.last_mut() {
+                Some((last, m)) if *m == markup && last.end == range.start => last.end = range.end,
+                _ => ranges.push((range, markup))
+            }
+        };
+        let mut i = 0;
+        while i < text.len() {
+            let line_start = i == 0 || text[..i].ends_with('\n');
+            match markup_len(text, i, line_start) {
+                Some(len) if len > 0 => {
+                    push(i..i + len, true);
+                    i += len;
+                },
+                _ => {
+                    let len = text[i..].chars().next().map_or(1, char::len_utf8);
+                    push(i..i + len, false);
+                    i += len;
+                }
+            }
+        }
+
+        // text is split into markup and trimmed readable text, then adjacent markups are merged
+        let mut parts: Vec<(Range<usize>, bool)> = Vec::new();
+        for (range, markup) in ranges {
+            let part = &text[range.clone()];
+            if markup || !has_letter(part) {
+                parts.push((range, true));
+                continue;
+            }
+            let start = range.start + part.len() - part.trim_start().len();
+            let end = range.start + part.trim_end().len();
+            parts.push((range.start..start, true));
+            parts.push((start..end, false));
+            parts.push((end..range.end, true));
+        }
+        let mut segments: Vec<Segment> = Vec::new();
+        let mut markup_start = None;
+        for (range, markup) in parts.into_iter().filter(|(r, _)| !r.is_empty()) {
+            if markup {
+                markup_start.get_or_insert(range.start);
+                continue;
+            }
+            if let Some(start) = markup_start.take() {
+                segments.push(Segment::Markup(&text[start..range.start]));
+            }
+            segments.push(Segment::Text(&text[range]));
+        }
+        if let Some(start) = markup_start {
+            segments.push(Segment::Markup(&text[start..]));
+        }
+        Markup { segments }
+    }
+
+    /// Readable text to be translated, in the order they appear.
+    pub fn to_translate(&self) -> Vec<&'a str> {
+        self.segments.iter().filter_map(|s| match s {
+            Segment::Text(t) => Some(*t),
+            Segment::Markup(_) => None
+        }).collect()
+    }
+
+    /// Put markup back around `translations` of [to_translate](#method.to_translate).
+    /// Text without translation, if `translations` is too short, is kept as is.
+    pub fn join<S: AsRef<str>>(&self, translations: &[S]) -> String {
+        let mut translations = translations.iter();
+        self.segments.iter().map(|s| match s {
+            Segment::Text(t) => translations.next().map_or(*t, |translated| translated.as_ref()),
+            Segment::Markup(m) => *m
+        }).collect()
+    }
+}
@@ -1,24 +1,73 @@
 //! The module to send translation request to WLT
 //! Construct [WLTTranslationRequest](struct.WLTTranslationRequest.html)
 //! then call async [send method](struct.WLTTranslationRequest.html#method.send)
-//! to get future result.
+//! to get future result. Batch larger than [ChunkConfig](../config/struct.ChunkConfig.html) is split into [chunks](fn.chunks.html)
+//! that are sent concurrently, and the response is put back together in the original order.
+//! [translate](fn.translate.html) follow [ModelRoute](../config/struct.ModelRoute.html) instead of single model.
+//! It fallback to base model when WLT cannot use the custom model, e.g. it is still training.
+//! [translate_pair](fn.translate_pair.html) also translate through pivot language when WLT has no
+//! model for the pair. Available pairs and [cached translations](cache/index.html) come from [Catalog](struct.Catalog.html).
+//! [identify](fn.identify.html) tell which language a text is written in. [detect](detect/index.html)
+//! guess it offline from Unicode script. [glossary](glossary/index.html) force translation of domain terms.
+//! [markup](markup/index.html) keep HTML, URL, markdown and emoji out of translation.
+//! [placeholder](placeholder/index.html) keep number, date and WA variable verbatim. [span](span/index.html) split code-switched text into runs of the same script.
+//! It fail with [GatewayErr](../error/struct.GatewayErr.html).
+//! WLT reply without any translation is `ErrKind::TranslationMismatch`.
 
-use dotenv::dotenv;
 use serde::{ Deserialize, Serialize };
+use std::collections::HashSet;
 use std::fmt::{ Debug };
-use std::env;
-use super::utils::{ post_json, CurlErr };
+use std::sync::Mutex;
+use super::config::{ChunkConfig, Direction, ModelRoute, WltConfig};
+use super::error::{ErrKind, GatewayErr};
+use super::logger::Record;
+use super::utils::{ get_json, post_json, post_text, Upstream };
+use cache::TranslationCache;
 
-#[derive(Serialize)]
+pub mod cache;
+pub mod detect;
+pub mod glossary;
+pub mod markup;
+pub mod placeholder;
+pub mod span;
+
+/// Request to translate `text` with one model. It is sent in chunks, see [send](#method.send).
 pub struct WLTTranslationRequest<'a> {
-    #[serde(skip)]
     pub endpoint: String,
-    #[serde(skip)]
-    pub api_key: &'a str,
+    pub upstream: Upstream,
+    pub chunk: ChunkConfig,
     pub model_id: String,
     pub text: &'a[&'a str]
 }
 
+/// Body of request of one chunk.
+#[derive(Serialize)]
+struct ChunkBody<'a> {
+    model_id: &'a str,
+    text: &'a [&'a str]
+}
+
+/// Split `text` into consecutive chunks that fit `limit`. Text larger than the limit by itself is sent alone.
+pub fn chunks<'a>(text: &'a [&'a str], limit: &ChunkConfig) -> Vec<&'a [&'a str]> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    let mut bytes = 0;
+    for (i, t) in text.iter().enumerate() {
+        // JSON encoded text and the comma after it
+        let size = serde_json::to_string(t).map_or(t.len(), |encoded| encoded.len()) + 1;
+        if i > start && (i - start >= limit.max_texts || bytes + size > limit.max_bytes) {
+            chunks.push(&text[start..i]);
+            start = i;
+            bytes = 0;
+        }
+        bytes += size;
+    }
+    if start < text.len() {
+        chunks.push(&text[start..]);
+    }
+    chunks
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Translation {
     pub translation: String
@@ -31,60 +80,262 @@ pub struct WLTTranslationResponse {
     pub translations: Vec<Translation>
 }
 
-#[derive(Debug)]
-pub enum WLTErr {
-    BuildRequestErr,
-    SendRequestErr,
-    DecodeResultErr,
-    NoTranslationErr
+/// Model as listed by `/v3/models`.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct WLTModel {
+    pub model_id: String,
+    pub source: String,
+    pub target: String,
+    /// e.g. `available` or `training`
+    #[serde(default)]
+    pub status: Option<String>
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct WLTModels {
+    models: Vec<WLTModel>
 }
 
 impl<'a> WLTTranslationRequest<'a> {
-    pub fn new(endpoint: &'a str, api_key: &'a str, text: &'a [&'a str], source: &'a str, target: &'a str, version: &'a str) -> WLTTranslationRequest<'a> {
-        dotenv().ok();
-        let model_id = env::var(format!("{}_{}", source, target)).unwrap_or(format!("{}-{}", source, target));
-        
+    /// Translate `text` with model `model_id`, e.g. `th-en` or id of custom model.
+    pub fn new<U: Into<Upstream>>(config: &WltConfig, upstream: U, text: &'a [&'a str], model_id: &str) -> WLTTranslationRequest<'a> {
         WLTTranslationRequest {
-            endpoint: format!("{}/v3/translate?version={}", endpoint, version),
-            api_key,
-            model_id,
+            endpoint: format!("{}/v3/translate?version={}", config.service.endpoint, config.service.version),
+            upstream: upstream.into(),
+            chunk: config.chunk,
+            model_id: model_id.to_owned(),
             text
         }
     }
 
-    pub async fn send(&self) -> Result<WLTTranslationResponse, WLTErr> {
-        match post_json(&self.endpoint, &self.api_key, Some(self)) as Result<WLTTranslationResponse, CurlErr> {
-            Ok(result) => {
-                if result.translations.len() > 0 {
-                    Ok(result)
-                } else {
-                    Err(WLTErr::NoTranslationErr)
-                }
+    /// Send every text, in chunks if needed. Word and character count are the sum of every chunk.
+    /// It fail with the first failure of any chunk. When there are many chunks, chunk whose translations
+    /// don't match its text fail with `ErrKind::TranslationMismatch`, otherwise translations would be misplaced.
+    pub async fn send(&self) -> Result<WLTTranslationResponse, GatewayErr> {
+        let chunks = chunks(self.text, &self.chunk);
+        let split = chunks.len() > 1;
+        if split {
+            log_debug!("Split {} text into {} chunks", self.text.len(), chunks.len());
+        }
+        let results = futures::future::join_all(chunks.iter().map(|chunk| self.send_chunk(chunk))).await;
+        let mut response = WLTTranslationResponse {
+            word_count: 0,
+            character_count: 0,
+            translations: Vec::with_capacity(self.text.len())
+        };
+        for (chunk, result) in chunks.iter().zip(results) {
+            let result = result?;
+            if split && result.translations.len() != chunk.len() {
+                return Err(GatewayErr::translation_mismatch(chunk.len(), result.translations.len())
+                           .context(format!("Fail to translate chunk with model {}", self.model_id)));
+            }
+            response.word_count += result.word_count;
+            response.character_count += result.character_count;
+            response.translations.extend(result.translations);
+        }
+        Ok(response)
+    }
+
+    async fn send_chunk(&self, text: &[&str]) -> Result<WLTTranslationResponse, GatewayErr> {
+        let body = ChunkBody {
+            model_id: &self.model_id,
+            text
+        };
+        let result: WLTTranslationResponse = post_json(&self.endpoint, &self.upstream, Some(&body)).await
+                                                .map_err(|e| GatewayErr::from(e).context(format!("Fail to translate with model {}", self.model_id)))?;
+        if result.translations.is_empty() {
+            return Err(GatewayErr::translation_mismatch(text.len(), 0).context(format!("Fail to translate with model {}", self.model_id)));
+        }
+        Ok(result)
+    }
+}
+
+/// Translate `text` with models of `route`, in order. Next model is tried only if WLT cannot use
+/// the previous one. Other failure is returned as is.
+/// Text found in `cache` isn't sent to WLT, so word and character count are of the text WLT actually translate.
+/// Without `cache`, every text is sent and nothing is kept.
+pub async fn translate(config: &WltConfig, upstream: &Upstream, cache: Option<&TranslationCache>, text: &[&str], route: &ModelRoute) -> Result<WLTTranslationResponse, GatewayErr> {
+    let models = route.models();
+    let cached: Vec<Option<String>> = text.iter().map(|t| cache.and_then(|c| c.get(&models, t))).collect();
+    let missing: Vec<&str> = text.iter().zip(&cached).filter(|(_, c)| c.is_none()).map(|(t, _)| *t).collect();
+    if missing.is_empty() {
+        return Ok(WLTTranslationResponse {
+            word_count: 0,
+            character_count: 0,
+            translations: cached.into_iter().flatten().map(|translation| Translation { translation }).collect()
+        });
+    }
+
+    let mut model = models[0];
+    let mut result = WLTTranslationRequest::new(config, upstream.clone(), &missing, model).send().await;
+    for next in &models[1..] {
+        match &result {
+            Err(e) if e.is_model_unavailable() => {
+                Record::warn(format!("Model {} is unavailable, fallback to {}: {}", model, next, e.report())).field("model", model).emit();
+                model = next;
+                result = WLTTranslationRequest::new(config, upstream.clone(), &missing, model).send().await;
             },
-            Err(e) => {
-                match e {
-                    CurlErr::InvalidUrl => {
-                        println!("The endpoing url may be invalid");
-                        Err(WLTErr::BuildRequestErr)
-                    },
-                    CurlErr::InvalidInputData => {
-                        println!("The input data may be invalid");
-                        Err(WLTErr::BuildRequestErr)
-                    },
-                    CurlErr::UnexpectedOutputData => {
-                        println!("The Curl engine doesn't support output data");
-                        Err(WLTErr::DecodeResultErr)
-                    },
-                    CurlErr::RequestFail => {
-                        println!("Fail to deliver translation request to server");
-                        Err(WLTErr::SendRequestErr)
-                    },
-                    CurlErr::IncompatibleResultData => {
-                        println!("The return data cannot be parsed into given struct");
-                        Err(WLTErr::DecodeResultErr)
+            _ => break
+        }
+    }
+    let mut response = result?;
+    if response.translations.len() != missing.len() {
+        if missing.len() == text.len() {
+            // caller decide what to do with mismatch
+            return Ok(response);
+        }
+        return Err(GatewayErr::translation_mismatch(text.len(), text.len() - missing.len() + response.translations.len()));
+    }
+    if let Some(cache) = cache {
+        for (t, translated) in missing.iter().zip(&response.translations) {
+            cache.put(model, t, &translated.translation);
+        }
+    }
+    let mut translated = response.translations.into_iter();
+    response.translations = cached.into_iter()
+                                  .map(|c| c.map_or_else(|| translated.next(), |translation| Some(Translation { translation })))
+                                  .collect::<Option<Vec<Translation>>>()
+                                  .unwrap_or_default();
+    Ok(response)
+}
+
+/// Language and how confident WLT is that text is written in it, from 0 to 1.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct IdentifiedLanguage {
+    pub language: String,
+    pub confidence: f64
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct WLTIdentifyResponse {
+    languages: Vec<IdentifiedLanguage>
+}
+
+/// Identify language of `text`. It return languages ranked by confidence, the most likely one first.
+/// It fail with `ErrKind::Decode` if WLT doesn't return any language.
+pub async fn identify(config: &WltConfig, upstream: &Upstream, text: &str) -> Result<Vec<IdentifiedLanguage>, GatewayErr> {
+    let url = format!("{}/v3/identify?version={}", config.service.endpoint, config.service.version);
+    let result: WLTIdentifyResponse = post_text(&url, upstream, text).await.map_err(|e| GatewayErr::from(e).context("Fail to identify language"))?;
+    let mut languages = result.languages;
+    if languages.is_empty() {
+        return Err(GatewayErr::new(ErrKind::Decode, "WLT return no language").context("Fail to identify language"));
+    }
+    languages.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+    Ok(languages)
+}
+
+/// List every model of WLT instance in `config`.
+pub async fn list_models(config: &WltConfig, upstream: &Upstream) -> Result<Vec<WLTModel>, GatewayErr> {
+    let url = format!("{}/v3/models?version={}", config.service.endpoint, config.service.version);
+    let result: WLTModels = get_json(&url, upstream).await.map_err(|e| GatewayErr::from(e).context("Fail to list WLT models"))?;
+    Ok(result.models)
+}
+
+/// What is known about WLT without asking it again, i.e. language pairs that WLT has model for and
+/// translations it already made. Pairs are either listed in config or listed from WLT the first time they are needed.
+#[derive(Debug)]
+pub struct Catalog {
+    pairs: Mutex<Option<HashSet<String>>>,
+    pub cache: TranslationCache
+}
+
+impl Catalog {
+    /// Catalog with translation cache in memory only. Use [with_cache](#method.with_cache) for cache as configured.
+    pub fn new(config: &WltConfig) -> Catalog {
+        Catalog {
+            pairs: Mutex::new(config.pairs.clone()),
+            cache: TranslationCache::new(config.cache.capacity)
+        }
+    }
+
+    pub fn with_cache(mut self, cache: TranslationCache) -> Catalog {
+        self.cache = cache;
+        self
+    }
+
+    /// Whether there's model from `source` to `target`, if it is known without asking WLT.
+    pub fn known(&self, source: &str, target: &str) -> Option<bool> {
+        self.pairs.lock().unwrap_or_else(|e| e.into_inner()).as_ref().map(|p| p.contains(&format!("{}-{}", source, target)))
+    }
+
+    /// Whether there's model from `source` to `target`. Model that isn't available yet, e.g. still training,
+    /// doesn't count.
+    pub async fn contains(&self, config: &WltConfig, upstream: &Upstream, source: &str, target: &str) -> Result<bool, GatewayErr> {
+        if let Some(known) = self.known(source, target) {
+            return Ok(known);
+        }
+        let pairs: HashSet<String> = list_models(config, upstream).await?
+                                        .into_iter()
+                                        .filter(|m| m.status.as_deref().is_none_or(|s| s == "available"))
+                                        .map(|m| format!("{}-{}", m.source, m.target))
+                                        .collect();
+        let found = pairs.contains(&format!("{}-{}", source, target));
+        *self.pairs.lock().unwrap_or_else(|e| e.into_inner()) = Some(pairs);
+        Ok(found)
+    }
+}
+
+/// Translation result and the pivot language it went through, if any.
+#[derive(Debug)]
+pub struct Translated {
+    pub response: WLTTranslationResponse,
+    pub pivot: Option<String>
+}
+
+/// Translate `text` from `source` to `target` following the route of `direction`.
+/// If WLT has no model for the pair, text is translated into pivot language then into `target`.
+/// Direct translation isn't even tried if `catalog` already know there's no model for it.
+/// Only output is cached. User message may have health data, and cache may be saved to file, so it's never cached.
+pub async fn translate_pair(config: &WltConfig, upstream: &Upstream, catalog: &Catalog, text: &[&str], direction: Direction, source: &str, target: &str) -> Result<Translated, GatewayErr> {
+    let cache = match direction {
+        Direction::Output => Some(&catalog.cache),
+        Direction::Input => None
+    };
+    let route = config.route(direction, source, target);
+    let pivot = config.pivot_of(&route).to_owned();
+    let can_pivot = pivot != source && pivot != target;
+    let skip_direct = can_pivot && route.custom.is_none() && catalog.known(source, target) == Some(false)
+                      && catalog.known(source, &pivot) == Some(true) && catalog.known(&pivot, target) == Some(true);
+    if !skip_direct {
+        match translate(config, upstream, cache, text, &route).await {
+            Err(e) if can_pivot && e.is_model_unavailable() => {
+                let legs = async {
+                    Ok::<bool, GatewayErr>(catalog.contains(config, upstream, source, &pivot).await? && catalog.contains(config, upstream, &pivot, target).await?)
+                };
+                match legs.await {
+                    Ok(true) => Record::info(format!("No model from {} to {}, translate through {}", source, target, pivot)).emit(),
+                    Ok(false) => return Err(e),
+                    Err(l) => {
+                        Record::warn(format!("Cannot find pivot model: {}", l.report())).emit();
+                        return Err(e);
                     }
                 }
+            },
+            result => {
+                return result.map(|response| Translated {
+                    response,
+                    pivot: None
+                });
             }
         }
     }
-}
\ No newline at end of file
+
+    let context = || format!("Fail to translate from {} to {} through {}", source, target, pivot);
+    let first = translate(config, upstream, cache, text, &config.route(direction, source, &pivot)).await.map_err(|e| e.context(context()))?;
+    if first.translations.len() != text.len() {
+        return Err(GatewayErr::translation_mismatch(text.len(), first.translations.len()).context(context()));
+    }
+    let intermediate: Vec<&str> = first.translations.iter().map(|t| t.translation.as_str()).collect();
+    let second = translate(config, upstream, cache, &intermediate, &config.route(direction, &pivot, target)).await.map_err(|e| e.context(context()))?;
+    Ok(Translated {
+        response: WLTTranslationResponse {
+            word_count: first.word_count + second.word_count,
+            character_count: first.character_count + second.character_count,
+            translations: second.translations
+        },
+        pivot: Some(pivot)
+    })
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,216 @@
+//! Protect part of text from being translated.
+//!
+//! Protected part is swapped for placeholder `[[0]]`, `[[1]]`, etc. before text is sent to WLT,
+//! then the placeholder in translation is swapped for the value it stand for, e.g. approved term of
+//! [glossary](../glossary/index.html). WLT may add space inside placeholder, e.g. `[[ 0 ]]`, so it is accepted too.
+//!
+//! [protected](fn.protected.html) find what WLT tend to change and shall be kept verbatim, i.e. number,
+//! hotline such as `1422`, date such as `12/04/2020`, time, WA variable such as `$name`, `<? ... ?>` expression
+//! and name. Name is capitalised Latin words in text of other script, e.g. `Somchai Jaidee` in Thai text.
+//! In Latin text, capital letter may just start a sentence, so names there are protected only through glossary.
+//! Text that already has something like placeholder, e.g. `[[0]]`, keep it verbatim too so it isn't mistaken for one.
+//! Placeholder that WLT dropped or duplicated is counted in [Restored](struct.Restored.html).
+
+use std::ops::Range;
+use super::detect::{script, Script};
+
+/// Placeholder of `index`-th protected part.
+pub fn token(index: usize) -> String {
+    format!("[[{}]]", index)
+}
+
+/// Whether `c` is part of Latin word, so number shall not start right after it, e.g. `abc123`.
+fn is_word(c: char) -> bool {
+    c.is_ascii_digit() || script(c) == Some(Script::Latin)
+}
+
+/// Length of number, date or time at start of `rest`, e.g. `1,234.5`, `12/04/2020`, `2020-04-12` or `10:30`.
+fn number_len(rest: &str) -> usize {
+    let mut len = 0;
+    let mut chars = rest.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_numeric() {
+            len += c.len_utf8();
+        } else if matches!(c, '.' | ',' | ':' | '/' | '-') && len > 0 && chars.peek().is_some_and(|n| n.is_numeric()) {
+            len += 1;
+        } else {
+            break;
+        }
+    }
+    len
+}
+
+/// Length of WA variable at start of `rest`, e.g. `$name` or `$user.first_name`.
+fn variable_len(rest: &str) -> usize {
+    if !rest.starts_with('$') || !rest[1..].starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        return 0;
+    }
+    let end = rest[1..].find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.')).map_or(rest.len(), |e| e + 1);
+    rest[..end].trim_end_matches('.').len()
+}
+
+/// Length of name at start of `rest`, i.e. one or more capitalised Latin words separated by space, e.g. `Somchai Jaidee`.
+fn name_len(rest: &str) -> usize {
+    let mut len = 0;
+    loop {
+        let word = &rest[len..];
+        if !word.starts_with(|c: char| c.is_ascii_uppercase()) {
+            break;
+        }
+        len += word.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(word.len());
+        if !rest[len..].starts_with(' ') || !rest[len + 1..].starts_with(|c: char| c.is_ascii_uppercase()) {
+            break;
+        }
+        len += 1;
+    }
+    len
+}
+
+/// Length of text that look like placeholder at start of `rest`, e.g. `[[0]]` or `[[ 12 ]]`.
+fn token_len(rest: &str) -> usize {
+    let inner = match rest.strip_prefix("[[") {
+        Some(inner) => inner,
+        None => return 0
+    };
+    match inner.find("]]") {
+        Some(end) if !inner[..end].trim().is_empty() && inner[..end].trim().chars().all(|c| c.is_ascii_digit()) => end + 4,
+        _ => 0
+    }
+}
+
+/// Parts of `text` that shall be kept verbatim, with the value to restore, which is the part itself.
+pub fn protected(text: &str) -> Vec<(Range<usize>, String)> {
+    // capitalised word in Latin text may just start a sentence so it isn't taken as name
+    let other_script = text.chars().any(|c| c.is_alphabetic() && script(c) != Some(Script::Latin));
+    let mut found = Vec::new();
+    let mut previous: Option<char> = None;
+    let mut i = 0;
+    while i < text.len() {
+        let rest = &text[i..];
+        let len = if rest.starts_with("<?") {
+            rest.find("?>").map_or(0, |end| end + 2)
+        } else if rest.starts_with('$') {
+            variable_len(rest)
+        } else if previous.is_none_or(|p| !is_word(p)) {
+            match number_len(rest) {
+                0 if other_script => name_len(rest),
+                len => len
+            }
+        } else {
+            0
+        };
+        if len > 0 {
+            found.push((i..i + len, text[i..i + len].to_owned()));
+            previous = text[..i + len].chars().last();
+            i += len;
+        } else {
+            let c = rest.chars().next().unwrap_or_default();
+            previous = Some(c);
+            i += c.len_utf8();
+        }
+    }
+    found
+}
+
+/// Text with protected parts swapped for placeholders.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Masked {
+    /// Text to be translated
+    pub text: String,
+    /// Value of each placeholder, in order
+    pub values: Vec<String>
+}
+
+/// Translation with placeholders swapped back.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Restored {
+    pub text: String,
+    /// Number of placeholders missing from translation. Their values are appended to the text so nothing is lost.
+    pub dropped: usize,
+    /// Number of extra copies of placeholders
+    pub duplicated: usize
+}
+
+impl Masked {
+    /// Swap each range of `text` for placeholder. Value is what placeholder is swapped back to.
+    /// When ranges overlap, the one that start first, or the longer one if they start together, is kept.
+    /// Text that already look like placeholder is swapped too, so restore put it back as is.
+    pub fn new(text: &str, mut protected: Vec<(Range<usize>, String)>) -> Masked {
+        let mut i = 0;
+        while let Some(start) = text[i..].find("[[").map(|s| s + i) {
+            let len = token_len(&text[start..]);
+            if len > 0 {
+                protected.push((start..start + len, text[start..start + len].to_owned()));
+            }
+            i = start + len.max(2);
+        }
+        protected.sort_by_key(|(range, _)| (range.start, std::cmp::Reverse(range.end)));
+        let mut masked = String::with_capacity(text.len());
+        let mut values = Vec::with_capacity(protected.len());
+        let mut last = 0;
+        for (range, value) in protected.into_iter().filter(|(range, _)| !range.is_empty()) {
+            if range.start < last {
+                continue;
+            }
+            masked.push_str(&text[last..range.start]);
+            masked.push_str(&token(values.len()));
+            values.push(value);
+            last = range.end;
+        }
+        masked.push_str(&text[last..]);
+        Masked {
+            text: masked,
+            values
+        }
+    }
+
+    /// Swap placeholders in `translated` back to their values. Unknown placeholder is kept as is.
+    pub fn unmask(&self, translated: &str) -> String {
+        self.restore(translated).text
+    }
+
+    /// Swap placeholders in `translated` back to their values and count the ones WLT dropped or duplicated.
+    pub fn restore(&self, translated: &str) -> Restored {
+        if self.values.is_empty() {
+            return Restored {
+                text: translated.to_owned(),
+                dropped: 0,
+                duplicated: 0
+            };
+        }
+        let mut seen = vec![0usize; self.values.len()];
+        let mut result = String::with_capacity(translated.len());
+        let mut rest = translated;
+        while let Some(start) = rest.find("[[") {
+            result.push_str(&rest[..start]);
+            let after = &rest[start + 2..];
+            let found = after.find("]]").and_then(|end| {
+                let index: usize = after[..end].trim().parse().ok()?;
+                Some((index, self.values.get(index)?, end))
+            });
+            match found {
+                Some((index, value, end)) => {
+                    seen[index] += 1;
+                    result.push_str(value);
+                    rest = &after[end + 2..];
+                },
+                None => {
+                    result.push_str("[[");
+                    rest = after;
+                }
+            }
+        }
+        result.push_str(rest);
+        let mut dropped = 0;
+        for (value, _) in self.values.iter().zip(&seen).filter(|(_, n)| **n == 0) {
+            result.push(' ');
+            result.push_str(value);
+            dropped += 1;
+        }
+        Restored {
+            text: result,
+            dropped,
+            duplicated: seen.iter().map(|n| n.saturating_sub(1)).sum()
+        }
+    }
+}
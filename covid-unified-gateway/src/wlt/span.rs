@@ -0,0 +1,90 @@
+//! Split code-switched text, e.g. `ยอด covid วันนี้`, into runs of the same script.
+//!
+//! Translating whole code-switched message sometimes garble the term that is already in target language.
+//! [CodeSwitched](struct.CodeSwitched.html) translate only the runs in source language script and keep the others as is.
+
+use super::detect::{language_script, script, Script};
+
+/// Run of text in the same script. Digit, punctuation and space belong to the run before them,
+/// or to the first run when they lead the text.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Span<'a> {
+    pub text: &'a str,
+    /// `None` if the text has no letter at all
+    pub script: Option<Script>
+}
+
+/// Split `text` into runs of the same script. Text without any letter is a single run.
+pub fn script_runs(text: &str) -> Vec<Span<'_>> {
+    let mut spans: Vec<Span> = Vec::new();
+    let mut start = 0;
+    let mut current: Option<Script> = None;
+    for (i, c) in text.char_indices() {
+        match script(c) {
+            Some(s) if current.is_none() => current = Some(s),
+            Some(s) if current != Some(s) => {
+                spans.push(Span { text: &text[start..i], script: current });
+                start = i;
+                current = Some(s);
+            },
+            _ => ()
+        }
+    }
+    if start < text.len() {
+        spans.push(Span { text: &text[start..], script: current });
+    }
+    spans
+}
+
+/// Text that mix script of source language with script of target language.
+#[derive(Debug)]
+pub struct CodeSwitched<'a> {
+    spans: Vec<Span<'a>>,
+    source: Script
+}
+
+impl<'a> CodeSwitched<'a> {
+    /// Split `text` to be translated from `source` to `target` language. It return `None` unless
+    /// `text` has both a run in `source` language script and a run in `target` language script,
+    /// in which case the whole text shall be translated as usual.
+    pub fn split(text: &'a str, source: &str, target: &str) -> Option<CodeSwitched<'a>> {
+        let source = language_script(source)?;
+        let target = language_script(target)?;
+        if source == target {
+            return None;
+        }
+        let spans = script_runs(text);
+        let has = |s: Script| spans.iter().any(|span| span.script == Some(s));
+        if has(source) && has(target) {
+            Some(CodeSwitched { spans, source })
+        } else {
+            None
+        }
+    }
+
+    /// Runs to be translated, without surrounding space, in the order they appear.
+    pub fn to_translate(&self) -> Vec<&'a str> {
+        self.spans.iter().filter(|s| s.script == Some(self.source)).map(|s| s.text.trim()).collect()
+    }
+
+    /// Put text back together with `translations` in place of the runs from [to_translate](#method.to_translate).
+    /// Runs without translation, if `translations` is too short, are kept as is.
+    /// Space is added between runs that touch each other, e.g. `ยอดcovid`, so translated word isn't glued to the next one.
+    pub fn join<S: AsRef<str>>(&self, translations: &[S]) -> String {
+        let mut translations = translations.iter();
+        let mut joined = String::new();
+        for span in &self.spans {
+            let translated = if span.script == Some(self.source) { translations.next() } else { None };
+            let text = match translated {
+                Some(translated) => span.text.replacen(span.text.trim(), translated.as_ref(), 1),
+                None => span.text.to_owned()
+            };
+            let touching = joined.ends_with(char::is_alphanumeric) && text.starts_with(char::is_alphanumeric);
+            if touching {
+                joined.push(' ');
+            }
+            joined.push_str(&text);
+        }
+        joined
+    }
+}
@@ -0,0 +1,588 @@
+use super::*;
+use crate::config::{ChunkConfig, Direction, ModelRoute, ServiceConfig};
+use crate::error::ErrKind;
+use crate::utils::mock::{MockReply, MockTransport};
+use crate::utils::RetryPolicy;
+use cache::{CacheStats, Table, TranslationCache};
+use serde_json::json;
+
+fn mock_upstream(mock: &MockTransport) -> Upstream {
+    Upstream::new("apikey".into()).transport(mock.clone()).retry(RetryPolicy::no_retry())
+}
+
+fn config() -> WltConfig {
+    WltConfig::new(ServiceConfig::new("https://wlt.mock", "2018-05-01", "key"))
+}
+
+#[test]
+fn test_send_translation() {
+    let mock = MockTransport::new()
+                    .on("POST", "/v3/translate", MockReply::json(200, json!({
+                        "word_count": 3,
+                        "character_count": 14,
+                        "translations": [{"translation": "สวัสดี"}, {"translation": "ลาก่อน"}]
+                    })));
+    let text = ["hello", "goodbye"];
+    let request = WLTTranslationRequest::new(&config(), mock_upstream(&mock), &text, "en-th");
+
+    let result = futures::executor::block_on(request.send()).unwrap();
+    assert_eq!(result.translations.len(), 2);
+    assert_eq!(result.translations[1].translation, "ลาก่อน");
+
+    let requests = mock.requests();
+    assert_eq!(requests[0].url, "https://wlt.mock/v3/translate?version=2018-05-01");
+    let sent: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert_eq!(sent["text"], json!(["hello", "goodbye"]));
+    assert_eq!(sent["model_id"], "en-th");
+    assert!(sent.get("endpoint").is_none());
+}
+
+#[test]
+fn test_send_in_chunks() {
+    let mock = MockTransport::new()
+                    .on("POST", "/v3/translate", translation_reply(&["one", "two"]))
+                    .on("POST", "/v3/translate", translation_reply(&["three", "four"]))
+                    .on("POST", "/v3/translate", translation_reply(&["five"]));
+    let mut config = config();
+    config.chunk = ChunkConfig { max_texts: 2, max_bytes: 1024 };
+    let text = ["หนึ่ง", "สอง", "สาม", "สี่", "ห้า"];
+    let result = futures::executor::block_on(WLTTranslationRequest::new(&config, mock_upstream(&mock), &text, "th-en").send()).unwrap();
+    let translated: Vec<&str> = result.translations.iter().map(|t| t.translation.as_str()).collect();
+    assert_eq!(translated, vec!["one", "two", "three", "four", "five"]);
+    let sent: Vec<serde_json::Value> = mock.requests().iter().map(|r| serde_json::from_slice::<serde_json::Value>(&r.body).unwrap()["text"].clone()).collect();
+    assert_eq!(sent, vec![json!(["หนึ่ง", "สอง"]), json!(["สาม", "สี่"]), json!(["ห้า"])]);
+
+    // chunk with missing translation isn't merged, the rest would be shifted
+    let mock = MockTransport::new()
+                    .on("POST", "/v3/translate", translation_reply(&["one"]))
+                    .on("POST", "/v3/translate", translation_reply(&["three", "four"]))
+                    .on("POST", "/v3/translate", translation_reply(&["five"]));
+    let err = futures::executor::block_on(WLTTranslationRequest::new(&config, mock_upstream(&mock), &text, "th-en").send()).unwrap_err();
+    assert_eq!(err.kind(), ErrKind::TranslationMismatch);
+}
+
+#[test]
+fn test_chunks() {
+    let text = ["hello", "goodbye", "สวัสดี", "a \"quoted\" text", "x"];
+    let sizes = |limit: ChunkConfig| chunks(&text, &limit).iter().map(|c| c.len()).collect::<Vec<usize>>();
+    assert_eq!(sizes(ChunkConfig::default()), vec![5]);
+    assert_eq!(sizes(ChunkConfig { max_texts: 2, max_bytes: 1024 }), vec![2, 2, 1]);
+    // "hello" and "goodbye" take 8 and 10 bytes with quote and comma, Thai letter take 3 bytes each
+    assert_eq!(sizes(ChunkConfig { max_texts: 50, max_bytes: 18 }), vec![2, 1, 1, 1]);
+    // text larger than the limit is sent alone
+    assert_eq!(sizes(ChunkConfig { max_texts: 50, max_bytes: 1 }), vec![1, 1, 1, 1, 1]);
+    assert!(chunks(&[], &ChunkConfig::default()).is_empty());
+    assert_eq!(chunks(&text, &ChunkConfig { max_texts: 2, max_bytes: 1024 })[2], &["x"]);
+}
+
+#[test]
+fn test_route_custom_model() {
+    let mock = MockTransport::new()
+                    .on("POST", "/v3/translate", MockReply::json(200, json!({"word_count": 1, "character_count": 5, "translations": [{"translation": "hello"}]})));
+    let mut route = ModelRoute::base("th", "en");
+    route.custom = Some("custom-th-en".to_owned());
+    let text = ["สวัสดี"];
+    futures::executor::block_on(translate(&config(), &mock_upstream(&mock), Some(&TranslationCache::new(10)), &text, &route)).unwrap();
+
+    let requests = mock.requests();
+    assert_eq!(requests.len(), 1);
+    let sent: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert_eq!(sent["model_id"], "custom-th-en");
+}
+
+#[test]
+fn test_route_fallback_to_base_model() {
+    let mock = MockTransport::new()
+                    .on("POST", "/v3/translate", MockReply::json(400, json!({"code": 400, "error": "Model custom-th-en is not available, status is training"})))
+                    .on("POST", "/v3/translate", MockReply::json(200, json!({"word_count": 1, "character_count": 5, "translations": [{"translation": "hello"}]})));
+    let mut route = ModelRoute::base("th", "en");
+    route.custom = Some("custom-th-en".to_owned());
+    let text = ["สวัสดี"];
+    let result = futures::executor::block_on(translate(&config(), &mock_upstream(&mock), Some(&TranslationCache::new(10)), &text, &route)).unwrap();
+    assert_eq!(result.translations[0].translation, "hello");
+
+    let models: Vec<serde_json::Value> = mock.requests().iter().map(|r| serde_json::from_slice::<serde_json::Value>(&r.body).unwrap()["model_id"].clone()).collect();
+    assert_eq!(models, vec![json!("custom-th-en"), json!("th-en")]);
+}
+
+#[test]
+fn test_route_no_fallback_on_other_failure() {
+    let mock = MockTransport::new()
+                    .on("POST", "/v3/translate", MockReply::json(500, json!({"code": 500, "error": "Internal error"})));
+    let mut route = ModelRoute::base("th", "en");
+    route.custom = Some("custom-th-en".to_owned());
+    let text = ["สวัสดี"];
+    let err = futures::executor::block_on(translate(&config(), &mock_upstream(&mock), Some(&TranslationCache::new(10)), &text, &route)).unwrap_err();
+    assert_eq!(err.http().map(|e| e.status), Some(500));
+    assert_eq!(mock.count("/v3/translate"), 1);
+}
+
+#[test]
+fn test_no_translation() {
+    let mock = MockTransport::new()
+                    .on("POST", "/v3/translate", MockReply::json(200, json!({"word_count": 0, "character_count": 0, "translations": []})));
+    let text = ["hello"];
+    let request = WLTTranslationRequest::new(&config(), mock_upstream(&mock), &text, "en-th");
+
+    assert_eq!(futures::executor::block_on(request.send()).unwrap_err().kind(), ErrKind::TranslationMismatch);
+}
+
+#[test]
+fn test_model_not_found() {
+    let mock = MockTransport::new()
+                    .on("POST", "/v3/translate", MockReply::json(404, json!({"code": 404, "error": "Model not found."})));
+    let text = ["hello"];
+    let request = WLTTranslationRequest::new(&config(), mock_upstream(&mock), &text, "en-xx");
+
+    let err = futures::executor::block_on(request.send()).unwrap_err();
+    assert_eq!(err.kind(), ErrKind::WatsonApi);
+    assert_eq!(err.http().and_then(|e| e.message()), Some("Model not found."));
+}
+
+#[test]
+fn test_malformed_translation() {
+    let mock = MockTransport::new()
+                    .on("POST", "/v3/translate", MockReply::json(200, json!({"translations": "oops"})));
+    let text = ["hello"];
+    let request = WLTTranslationRequest::new(&config(), mock_upstream(&mock), &text, "en-th");
+
+    assert_eq!(futures::executor::block_on(request.send()).unwrap_err().kind(), ErrKind::Decode);
+}
+
+fn translation_reply(text: &[&str]) -> MockReply {
+    let translations: Vec<serde_json::Value> = text.iter().map(|t| json!({"translation": t})).collect();
+    MockReply::json(200, json!({"word_count": text.len(), "character_count": 10, "translations": translations}))
+}
+
+fn sent_models(mock: &MockTransport) -> Vec<String> {
+    mock.requests().iter()
+        .filter(|r| r.url.contains("/v3/translate"))
+        .map(|r| serde_json::from_slice::<serde_json::Value>(&r.body).unwrap()["model_id"].as_str().unwrap().to_owned())
+        .collect()
+}
+
+#[test]
+fn test_list_models() {
+    let mock = MockTransport::new()
+                    .on("GET", "/v3/models", MockReply::json(200, json!({"models": [
+                        {"model_id": "en-th", "source": "en", "target": "th", "status": "available"},
+                        {"model_id": "custom-lo-en", "source": "lo", "target": "en", "status": "training"}
+                    ]})));
+    let catalog = Catalog::new(&config());
+    assert_eq!(catalog.known("en", "th"), None);
+    let found = futures::executor::block_on(catalog.contains(&config(), &mock_upstream(&mock), "en", "th")).unwrap();
+    assert!(found);
+    // training model doesn't count and the list is fetched only once
+    assert_eq!(catalog.known("lo", "en"), Some(false));
+    assert_eq!(mock.requests()[0].url, "https://wlt.mock/v3/models?version=2018-05-01");
+    assert_eq!(mock.count("/v3/models"), 1);
+}
+
+#[test]
+fn test_pivot_when_no_direct_model() {
+    let mock = MockTransport::new()
+                    .on("POST", "/v3/translate", MockReply::json(404, json!({"code": 404, "error": "Model not found."})))
+                    .on("GET", "/v3/models", MockReply::json(200, json!({"models": [
+                        {"model_id": "lo-en", "source": "lo", "target": "en", "status": "available"},
+                        {"model_id": "en-th", "source": "en", "target": "th", "status": "available"}
+                    ]})))
+                    .on("POST", "/v3/translate", translation_reply(&["hello"]))
+                    .on("POST", "/v3/translate", translation_reply(&["สวัสดี"]));
+    let text = ["ສະບາຍດີ"];
+    let result = futures::executor::block_on(translate_pair(&config(), &mock_upstream(&mock), &Catalog::new(&config()), &text, Direction::Input, "lo", "th")).unwrap();
+    assert_eq!(result.pivot.as_deref(), Some("en"));
+    assert_eq!(result.response.translations[0].translation, "สวัสดี");
+    assert_eq!(result.response.word_count, 2);
+    assert_eq!(sent_models(&mock), vec!["lo-th", "lo-en", "en-th"]);
+}
+
+#[test]
+fn test_pivot_from_configured_pairs() {
+    let mock = MockTransport::new()
+                    .on("POST", "/v3/translate", translation_reply(&["hello"]))
+                    .on("POST", "/v3/translate", translation_reply(&["สวัสดี"]));
+    let mut config = config();
+    config.pivot = "en".to_owned();
+    config.pairs = Some(["lo-en", "en-th"].iter().map(|p| p.to_string()).collect());
+    let text = ["ສະບາຍດີ"];
+    let result = futures::executor::block_on(translate_pair(&config, &mock_upstream(&mock), &Catalog::new(&config), &text, Direction::Output, "lo", "th")).unwrap();
+    assert_eq!(result.pivot.as_deref(), Some("en"));
+    // direct model is known to be missing so it isn't tried
+    assert_eq!(sent_models(&mock), vec!["lo-en", "en-th"]);
+    assert_eq!(mock.count("/v3/models"), 0);
+}
+
+#[test]
+fn test_no_pivot_model() {
+    let mock = MockTransport::new()
+                    .on("POST", "/v3/translate", MockReply::json(404, json!({"code": 404, "error": "Model not found."})))
+                    .on("GET", "/v3/models", MockReply::json(200, json!({"models": [
+                        {"model_id": "en-th", "source": "en", "target": "th", "status": "available"}
+                    ]})));
+    let text = ["ສະບາຍດີ"];
+    let err = futures::executor::block_on(translate_pair(&config(), &mock_upstream(&mock), &Catalog::new(&config()), &text, Direction::Input, "lo", "th")).unwrap_err();
+    assert!(err.is_model_unavailable());
+    assert_eq!(sent_models(&mock), vec!["lo-th"]);
+}
+
+#[test]
+fn test_direct_model_is_preferred() {
+    let mock = MockTransport::new().on("POST", "/v3/translate", translation_reply(&["hello"]));
+    let text = ["สวัสดี"];
+    let result = futures::executor::block_on(translate_pair(&config(), &mock_upstream(&mock), &Catalog::new(&config()), &text, Direction::Input, "th", "en")).unwrap();
+    assert!(result.pivot.is_none());
+    assert_eq!(sent_models(&mock), vec!["th-en"]);
+    assert_eq!(mock.count("/v3/models"), 0);
+}
+
+#[test]
+fn test_identify() {
+    let mock = MockTransport::new()
+                    .on("POST", "/v3/identify", MockReply::json(200, json!({"languages": [
+                        {"language": "lo", "confidence": 0.2},
+                        {"language": "th", "confidence": 0.7},
+                        {"language": "en", "confidence": 0.1}
+                    ]})));
+    let languages = futures::executor::block_on(identify(&config(), &mock_upstream(&mock), "สวัสดีครับ")).unwrap();
+    let ranked: Vec<&str> = languages.iter().map(|l| l.language.as_str()).collect();
+    assert_eq!(ranked, vec!["th", "lo", "en"]);
+    assert_eq!(languages[0].confidence, 0.7);
+
+    let request = &mock.requests()[0];
+    assert_eq!(request.url, "https://wlt.mock/v3/identify?version=2018-05-01");
+    assert_eq!(request.body, "สวัสดีครับ".as_bytes());
+}
+
+#[test]
+fn test_identify_no_language() {
+    let mock = MockTransport::new().on("POST", "/v3/identify", MockReply::json(200, json!({"languages": []})));
+    let err = futures::executor::block_on(identify(&config(), &mock_upstream(&mock), "?")).unwrap_err();
+    assert_eq!(err.kind(), ErrKind::Decode);
+    assert_eq!(err.report(), "Fail to identify language: WLT return no language");
+}
+
+#[test]
+fn test_script() {
+    assert_eq!(detect::script('ก'), Some(detect::Script::Thai));
+    assert_eq!(detect::script('ສ'), Some(detect::Script::Lao));
+    assert_eq!(detect::script('ក'), Some(detect::Script::Khmer));
+    assert_eq!(detect::script('မ'), Some(detect::Script::Myanmar));
+    assert_eq!(detect::script('é'), Some(detect::Script::Latin));
+    assert_eq!(detect::script('中'), Some(detect::Script::Han));
+    assert_eq!(detect::script('の'), Some(detect::Script::Kana));
+    assert_eq!(detect::script('한'), Some(detect::Script::Hangul));
+    for c in ['5', '๕', ' ', '?', '😷'] {
+        assert_eq!(detect::script(c), None, "{}", c);
+    }
+}
+
+#[test]
+fn test_detect_offline() {
+    let detected = |text: &str| detect::detect(text).map(|d| (d.language, d.confidence >= detect::CONFIDENT));
+    assert_eq!(detected("สวัสดีครับ"), Some(("th".to_owned(), true)));
+    assert_eq!(detected("ສະບາຍດີ"), Some(("lo".to_owned(), true)));
+    assert_eq!(detected("សួស្តី"), Some(("km".to_owned(), true)));
+    assert_eq!(detected("မင်္ဂလာပါ"), Some(("my".to_owned(), true)));
+    assert_eq!(detected("你好"), Some(("zh".to_owned(), true)));
+    assert_eq!(detected("こんにちは世界"), Some(("ja".to_owned(), true)));
+    assert_eq!(detected("안녕하세요"), Some(("ko".to_owned(), true)));
+    assert_eq!(detected("where is the hospital"), Some(("en".to_owned(), true)));
+    assert_eq!(detected("où est l'hôpital, merci"), Some(("fr".to_owned(), true)));
+    assert_eq!(detected("covid vaccine"), Some(("en".to_owned(), false)));
+    assert_eq!(detected("bệnh viện ở đâu"), Some(("vi".to_owned(), true)));
+    // short Latin text could be anything
+    assert_eq!(detected("ok"), Some(("en".to_owned(), false)));
+    assert_eq!(detected("555"), None);
+    assert_eq!(detected("😷 !!"), None);
+}
+
+#[test]
+fn test_can_skip_translation() {
+    assert!(detect::can_skip_translation("555", "th", "en"));
+    assert!(detect::can_skip_translation("👍", "th", "en"));
+    // Thai user typing plain English
+    assert!(detect::can_skip_translation("ok", "th", "en"));
+    assert!(detect::can_skip_translation("covid test", "th", "en"));
+    assert!(detect::can_skip_translation("สวัสดี", "en", "th"));
+    assert!(detect::can_skip_translation("where is the hospital", "fr", "en"));
+    assert!(!detect::can_skip_translation("สวัสดี", "th", "en"));
+    assert!(!detect::can_skip_translation("covid ที่ไหน", "th", "en"));
+    assert!(!detect::can_skip_translation("ok", "en", "th"));
+    assert!(!detect::can_skip_translation("bonjour", "fr", "en"));
+}
+
+#[test]
+fn test_script_runs() {
+    let runs: Vec<(&str, Option<detect::Script>)> = span::script_runs("1) ยอด covid-19 วันนี้?").into_iter().map(|s| (s.text, s.script)).collect();
+    assert_eq!(runs, vec![
+        ("1) ยอด ", Some(detect::Script::Thai)),
+        ("covid-19 ", Some(detect::Script::Latin)),
+        ("วันนี้?", Some(detect::Script::Thai))
+    ]);
+    assert_eq!(span::script_runs("555 :)"), vec![span::Span { text: "555 :)", script: None }]);
+    assert!(span::script_runs("").is_empty());
+}
+
+#[test]
+fn test_code_switched() {
+    let switched = span::CodeSwitched::split("ยอด covid วันนี้", "th", "en").unwrap();
+    assert_eq!(switched.to_translate(), vec!["ยอด", "วันนี้"]);
+    assert_eq!(switched.join(&["Total", "today"]), "Total covid today");
+
+    let switched = span::CodeSwitched::split("ยอดcovidวันนี้", "th", "en").unwrap();
+    assert_eq!(switched.join(&["Total", "today"]), "Total covid today");
+    // missing translation keep the run as is
+    assert_eq!(switched.join(&["Total"]), "Total covid วันนี้");
+
+    // single script or unknown script is translated as a whole
+    assert!(span::CodeSwitched::split("ยอดผู้ติดเชื้อวันนี้", "th", "en").is_none());
+    assert!(span::CodeSwitched::split("covid today", "th", "en").is_none());
+    assert!(span::CodeSwitched::split("ยอด covid", "th", "xx").is_none());
+    assert!(span::CodeSwitched::split("hello bonjour", "en", "fr").is_none());
+}
+
+#[test]
+fn test_cache_lru() {
+    let cache = TranslationCache::new(2);
+    cache.put("th-en", "สวัสดี", "hello");
+    cache.put("th-en", "ลาก่อน", "goodbye");
+    assert_eq!(cache.get(&["th-en"], "สวัสดี").as_deref(), Some("hello"));
+    // the least recently used one is evicted from memory but it is still in file layer
+    cache.put("th-en", "ขอบคุณ", "thank you");
+    assert_eq!(cache.get(&["custom-th-en", "th-en"], "ลาก่อน").as_deref(), Some("goodbye"));
+    assert_eq!(cache.get(&["th-en"], "ไม่รู้"), None);
+    assert_eq!(cache.get(&["en-th"], "สวัสดี"), None);
+    assert_eq!(cache.stats(), CacheStats { hits: 2, misses: 2 });
+}
+
+#[test]
+fn test_cached_translation() {
+    let mock = MockTransport::new()
+                    .on("POST", "/v3/translate", MockReply::json(400, json!({"code": 404, "error": "Model not found"})))
+                    .on("POST", "/v3/translate", MockReply::json(200, json!({"word_count": 1, "character_count": 6, "translations": [{"translation": "goodbye"}]})));
+    let mut route = ModelRoute::base("th", "en");
+    route.custom = Some("custom-th-en".to_owned());
+    let cache = TranslationCache::new(10);
+    cache.put("th-en", "สวัสดี", "hello");
+    let text = ["สวัสดี", "ลาก่อน"];
+    let result = futures::executor::block_on(translate(&config(), &mock_upstream(&mock), Some(&cache), &text, &route)).unwrap();
+    let translations: Vec<&str> = result.translations.iter().map(|t| t.translation.as_str()).collect();
+    assert_eq!(translations, vec!["hello", "goodbye"]);
+    assert_eq!(result.character_count, 6);
+    let sent: serde_json::Value = serde_json::from_slice(&mock.requests()[1].body).unwrap();
+    assert_eq!(sent["text"], json!(["ลาก่อน"]));
+
+    // translation is cached under the model that made it
+    let result = futures::executor::block_on(translate(&config(), &mock_upstream(&mock), Some(&cache), &text, &route)).unwrap();
+    assert_eq!(result.translations[1].translation, "goodbye");
+    assert_eq!(result.word_count, 0);
+    assert_eq!(mock.requests().len(), 2);
+    assert_eq!(cache.get(&["th-en"], "ลาก่อน").as_deref(), Some("goodbye"));
+}
+
+#[test]
+fn test_cache_file() {
+    let path = std::env::temp_dir().join(format!("translation-cache-{}.json", fastrand::u64(..)));
+    let mut warm = Table::new();
+    warm.entry("en-th".to_owned()).or_default().insert("Hi".to_owned(), "สวัสดี".to_owned());
+    let cache = TranslationCache::new(10).warm(warm).persist(&path);
+    assert_eq!(cache.get(&["en-th"], "Hi").as_deref(), Some("สวัสดี"));
+    cache.put("th-en", "ลาก่อน", "goodbye");
+    cache.save().unwrap();
+
+    let saved: Table = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(saved["th-en"]["ลาก่อน"], "goodbye");
+    let cache = TranslationCache::new(10).persist(&path);
+    assert_eq!(cache.get(&["th-en"], "ลาก่อน").as_deref(), Some("goodbye"));
+    assert_eq!(cache.get(&["en-th"], "Hi").as_deref(), Some("สวัสดี"));
+
+    // malformed file is started over
+    std::fs::write(&path, "not json").unwrap();
+    let cache = TranslationCache::new(10).persist(&path);
+    assert_eq!(cache.get(&["en-th"], "Hi"), None);
+    std::fs::remove_file(&path).ok();
+}
+
+#[test]
+fn test_cache_file_capacity() {
+    let path = std::env::temp_dir().join(format!("translation-cache-{}.json", fastrand::u64(..)));
+    let cache = TranslationCache::new(10).file_capacity(2).persist(&path);
+    cache.put("th-en", "สวัสดี", "hello");
+    cache.put("th-en", "ลาก่อน", "goodbye");
+    assert_eq!(cache.get(&["th-en"], "สวัสดี").as_deref(), Some("hello"));
+    cache.put("th-en", "ขอบคุณ", "thank you");
+    cache.save().unwrap();
+
+    // the least recently used one is dropped from file
+    let saved: Table = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(saved["th-en"].len(), 2);
+    assert!(!saved["th-en"].contains_key("ลาก่อน"));
+    // file larger than capacity, e.g. written with larger one, is cut on read
+    let cache = TranslationCache::new(10).file_capacity(1).persist(&path);
+    let found = ["สวัสดี", "ขอบคุณ"].iter().filter(|t| cache.get(&["th-en"], t).is_some()).count();
+    assert_eq!(found, 1);
+    std::fs::remove_file(&path).ok();
+}
+
+#[test]
+fn test_only_output_is_cached() {
+    let mock = MockTransport::new()
+                    .on("POST", "/v3/translate", translation_reply(&["hello"]))
+                    .on("POST", "/v3/translate", translation_reply(&["hello"]))
+                    .on("POST", "/v3/translate", translation_reply(&["hello"]));
+    let catalog = Catalog::new(&config());
+    let text = ["สวัสดี"];
+    for _ in 0..2 {
+        futures::executor::block_on(translate_pair(&config(), &mock_upstream(&mock), &catalog, &text, Direction::Input, "th", "en")).unwrap();
+    }
+    // user message is neither looked up nor kept
+    assert_eq!(mock.count("/v3/translate"), 2);
+    assert_eq!(catalog.cache.stats(), CacheStats::default());
+
+    for _ in 0..2 {
+        futures::executor::block_on(translate_pair(&config(), &mock_upstream(&mock), &catalog, &text, Direction::Output, "th", "en")).unwrap();
+    }
+    assert_eq!(mock.count("/v3/translate"), 3);
+    assert_eq!(catalog.cache.stats(), CacheStats { hits: 1, misses: 1 });
+}
+
+#[test]
+fn test_placeholder() {
+    let text = "ยอด covid วันนี้";
+    let masked = placeholder::Masked::new(text, vec![(10..15, "Covid".to_owned())]);
+    assert_eq!(masked.text, "ยอด [[0]] วันนี้");
+    assert_eq!(masked.unmask("total [[0]] today"), "total Covid today");
+    // WLT may put space inside placeholder
+    assert_eq!(masked.unmask("total [[ 0 ]] today"), "total Covid today");
+    // dropped placeholder is appended so nothing is lost
+    assert_eq!(masked.restore("[[1]] and [[ today"), placeholder::Restored {
+        text: "[[1]] and [[ today Covid".to_owned(),
+        dropped: 1,
+        duplicated: 0
+    });
+    assert_eq!(masked.restore("[[0]] and [[0]]"), placeholder::Restored {
+        text: "Covid and Covid".to_owned(),
+        dropped: 0,
+        duplicated: 1
+    });
+    assert_eq!(placeholder::Masked::new(text, Vec::new()).unmask("[[0]]"), "[[0]]");
+    // overlapping range is skipped
+    let masked = placeholder::Masked::new("covid-19", vec![(6..8, "19".to_owned()), (0..8, "COVID-19".to_owned())]);
+    assert_eq!(masked.text, "[[0]]");
+    assert_eq!(masked.values, vec!["COVID-19"]);
+}
+
+#[test]
+fn test_protected() {
+    let values = |text: &str| placeholder::protected(text).into_iter().map(|(_, v)| v).collect::<Vec<String>>();
+    assert_eq!(values("Call 1422 before 10:30 on 12/04/2020 or 2020-04-12, 1,234.5 cases."), vec!["1422", "10:30", "12/04/2020", "2020-04-12", "1,234.5"]);
+    assert_eq!(values("โทร1422 ยอด ๑๒๓ ราย"), vec!["1422", "๑๒๓"]);
+    assert_eq!(values("Hello $name, your $user.first_name. <? $count + 1 ?> left"), vec!["$name", "$user.first_name", "<? $count + 1 ?>"]);
+    assert_eq!(values("abc123 and $5 and <? open"), vec!["5"]);
+    let masked = placeholder::Masked::new("Call 1422, $name", placeholder::protected("Call 1422, $name"));
+    assert_eq!(masked.text, "Call [[0]], [[1]]");
+}
+
+#[test]
+fn test_protected_name() {
+    let values = |text: &str| placeholder::protected(text).into_iter().map(|(_, v)| v).collect::<Vec<String>>();
+    assert_eq!(values("ติดต่อ Somchai Jaidee ที่ 1422 หรือ WHO"), vec!["Somchai Jaidee", "1422", "WHO"]);
+    assert_eq!(values("ยอด covid ที่ Bangkok, Chiang Mai"), vec!["Bangkok", "Chiang Mai"]);
+    // capital letter in Latin text may only start a sentence
+    assert_eq!(values("Call John at 1422"), vec!["1422"]);
+    let text = "ผมชื่อ John";
+    let masked = placeholder::Masked::new(text, placeholder::protected(text));
+    assert_eq!(masked.text, "ผมชื่อ [[0]]");
+    assert_eq!(masked.unmask("My name is [[0]]"), "My name is John");
+}
+
+#[test]
+fn test_literal_placeholder() {
+    let text = "พิมพ์ [[0]] หรือ [[ 1 ]] แทน 5 คน [[x]]";
+    let masked = placeholder::Masked::new(text, placeholder::protected(text));
+    assert_eq!(masked.text, "พิมพ์ [[0]] หรือ [[1]] แทน [[2]] คน [[x]]");
+    assert_eq!(masked.values, vec!["[[0]]", "[[ 1 ]]", "5"]);
+    // literal one come back verbatim and isn't reported as dropped or duplicated
+    assert_eq!(masked.restore("type [[0]] or [[1]] for [[2]] people [[x]]"), placeholder::Restored {
+        text: "type [[0]] or [[ 1 ]] for 5 people [[x]]".to_owned(),
+        dropped: 0,
+        duplicated: 0
+    });
+}
+
+fn dataset(file: &str) -> glossary::Glossary {
+    glossary::Glossary::load(&[format!("{}/../wlt-dataset/th/{}", env!("CARGO_MANIFEST_DIR"), file)]).unwrap()
+}
+
+#[test]
+fn test_glossary_csv_and_tmx() {
+    let csv = dataset("glossary.csv");
+    let tmx = dataset("glossary.tmx");
+    for glossary in [&csv, &tmx] {
+        let terms = glossary.terms("en", "th");
+        assert_eq!(terms[0], ("status report for today", "สถานะวันนี้"));
+        // the first translation listed is the approved one
+        assert!(terms.contains(&("Covid", "โควิด")));
+        assert!(!terms.contains(&("Covid", "โควิท")));
+        let terms = glossary.terms("th", "en");
+        assert!(terms.contains(&("โควิท", "Covid")));
+        assert!(glossary.terms("th", "zh").is_empty());
+    }
+
+    let quoted = glossary::Glossary::parse("\u{feff}en,th\n\"covid, the disease\",\"โควิด\"\n\nmask,\n", false).unwrap();
+    assert_eq!(quoted.terms("en", "th"), vec![("covid, the disease", "โควิด")]);
+    assert!(glossary::Glossary::parse("english,thai\ncovid,โควิด", false).is_err());
+    assert!(glossary::Glossary::parse("", false).is_err());
+    let tmx = glossary::Glossary::parse(r#"<tmx><body><tu><tuv xml:lang="en"><seg>R&amp;D</seg></tuv><tuv xml:lang="th"><seg>วิจัย</seg></tuv></tu></body></tmx>"#, true).unwrap();
+    assert_eq!(tmx.terms("th", "en"), vec![("วิจัย", "R&D")]);
+}
+
+#[test]
+fn test_glossary_mask() {
+    let glossary = dataset("glossary.csv");
+    let masked = glossary.mask("ยอดโควิดวันนี้ กับ โคโรน่า", "th", "en");
+    assert_eq!(masked.text, "[[0]]วันนี้ กับ [[1]]");
+    assert_eq!(masked.values, vec!["Covid's case count", "Corona"]);
+    assert_eq!(masked.unmask("[[0]] today with [[1]]"), "Covid's case count today with Corona");
+
+    let masked = glossary.mask("COVID and Covidiot, patient count of covid.", "en", "th");
+    assert_eq!(masked.text, "[[0]] and Covidiot, [[1]] of [[2]].");
+    assert_eq!(masked.values, vec!["โควิด", "ยอดผู้ป่วย", "โควิด"]);
+    assert!(glossary.mask("hello", "en", "th").values.is_empty());
+}
+
+#[test]
+fn test_markup() {
+    let parsed = markup::Markup::parse(r#"Call <a href="tel:1422">hotline 1422</a> or visit https://ddc.moph.go.th/news. 😷"#);
+    assert_eq!(parsed.segments, vec![
+        markup::Segment::Text("Call"),
+        markup::Segment::Markup(r#" <a href="tel:1422">"#),
+        markup::Segment::Text("hotline 1422"),
+        markup::Segment::Markup("</a> "),
+        markup::Segment::Text("or visit"),
+        markup::Segment::Markup(" https://ddc.moph.go.th/news. 😷")
+    ]);
+    assert_eq!(parsed.join(&["โทร", "สายด่วน 1422", "หรือเข้า"]), r#"โทร <a href="tel:1422">สายด่วน 1422</a> หรือเข้า https://ddc.moph.go.th/news. 😷"#);
+    // text without translation is kept
+    assert_eq!(parsed.join(&["โทร"]), r#"โทร <a href="tel:1422">hotline 1422</a> or visit https://ddc.moph.go.th/news. 😷"#);
+}
+
+#[test]
+fn test_markdown() {
+    let parsed = markup::Markup::parse("## Stay safe\n- Wash **your hands** often\n2. See [the guide](https://www.who.int/guide_(th)) and `covid_status`\nsnake_case &amp; _more_ ~~less~~");
+    assert_eq!(parsed.to_translate(), vec!["Stay safe", "Wash", "your hands", "often", "See", "the guide", "and", "snake_case", "more", "less"]);
+    assert!(parsed.segments.contains(&markup::Segment::Markup("](https://www.who.int/guide_(th)) ")));
+    let parsed = markup::Markup::parse("Visit www.moph.go.th (Thai only), 3.5 million < 4 million");
+    assert_eq!(parsed.to_translate(), vec!["Visit", "(Thai only), 3.5 million < 4 million"]);
+    assert!(markup::Markup::parse("https://ddc.moph.go.th").to_translate().is_empty());
+    assert!(markup::Markup::parse("👍🏻 1422").to_translate().is_empty());
+}
+
+#[test]
+fn test_no_translate_marker() {
+    let parsed = markup::Markup::parse("Call <notranslate>DDC hotline</notranslate> or <NOTRANSLATE>1422");
+    assert_eq!(parsed.to_translate(), vec!["Call", "or"]);
+    assert_eq!(parsed.join(&["โทร", "หรือ"]), "โทร <notranslate>DDC hotline</notranslate> หรือ <NOTRANSLATE>1422");
+    assert_eq!(markup::strip_no_translate("โทร <notranslate>DDC hotline</notranslate> หรือ <NOTRANSLATE>1422"), "โทร DDC hotline หรือ 1422");
+    assert_eq!(markup::strip_no_translate("<notranslate"), "<notranslate");
+}
@@ -0,0 +1,467 @@
+//! Run the gateway binary end to end against local mock Watson server.
+
+use covid_unified_gateway::mock_server::{Endpoint, Fault, MockConfig, MockServer};
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// Empty working directory, so local `.env` or `gateway.toml` doesn't leak into the test.
+fn work_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("gateway-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).expect("Fail to create working directory");
+    dir
+}
+
+fn command(server: &MockServer) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_covid-unified-gateway"));
+    command.env_clear();
+    if let Some(path) = std::env::var_os("PATH") {
+        command.env("PATH", path);
+    }
+    command
+                        .current_dir(work_dir())
+                        .env("WLT_APIKEY", "dummy")
+                        .env("WLT_ENDPOINT", server.url())
+                        .env("WLT_VERSION", "2018-05-01")
+                        .env("WA_APIKEY", "dummy")
+                        .env("WA_ID", "assistant")
+                        .env("WA_ENDPOINT", server.url())
+                        .env("WA_VERSION", "2020-04-01")
+                        .env("WA_RETRY", "0")
+                        .env("WLT_RETRY", "0");
+    command
+}
+
+fn gateway(server: &MockServer, params: Value) -> Output {
+    command(server).arg(params.to_string()).output().expect("Fail to run gateway binary")
+}
+
+fn parse_output(output: Output) -> Value {
+    assert!(output.status.success(), "Gateway exit with {:?}", output.status);
+    let stdout = String::from_utf8(output.stdout).expect("Output shall be UTF-8");
+    // logs go to stderr so stdout has only the result
+    assert_eq!(stdout.lines().count(), 1, "Unexpected output {}", stdout);
+    serde_json::from_str(&stdout).unwrap_or_else(|e| panic!("Output is not JSON: {} ({})", stdout, e))
+}
+
+fn run_turn(server: &MockServer, params: Value) -> Value {
+    parse_output(gateway(server, params))
+}
+
+fn config() -> MockConfig {
+    MockConfig::default()
+        .reply("hello", "Hi, how can I help?")
+        .translation("th-en", "สวัสดี", "hello")
+        .translation("en-th", "Hi, how can I help?", "สวัสดี มีอะไรให้ช่วยไหม")
+}
+
+#[test]
+fn test_translated_turn() {
+    let server = MockServer::start(config()).unwrap();
+    let result = run_turn(&server, json!({"message": "สวัสดี", "sourceLang": "th", "targetLang": "en"}));
+    assert_eq!(result["status"], 200);
+    assert_eq!(result["result"]["output"]["generic"][0]["text"], "สวัสดี มีอะไรให้ช่วยไหม");
+    assert_eq!(server.requests(Endpoint::Translate).len(), 2);
+}
+
+#[test]
+fn test_unavailable_custom_model_fallback_to_base() {
+    let server = MockServer::start(config()).unwrap();
+    let output = command(&server).env("WLT_MODEL_th_en", "training-th-en")
+                                 .arg(json!({"message": "สวัสดี", "sourceLang": "th", "targetLang": "en"}).to_string())
+                                 .output()
+                                 .expect("Fail to run gateway binary");
+    let result = parse_output(output);
+    assert_eq!(result["status"], 200);
+    assert!(result.get("warnings").is_none());
+    assert_eq!(result["result"]["output"]["generic"][0]["text"], "สวัสดี มีอะไรให้ช่วยไหม");
+    let models: Vec<String> = server.requests(Endpoint::Translate).iter()
+                                    .map(|r| serde_json::from_str::<Value>(&r.body).unwrap()["model_id"].as_str().unwrap().to_owned())
+                                    .collect();
+    assert_eq!(models, vec!["training-th-en", "th-en", "en-th"]);
+}
+
+#[test]
+fn test_pivot_through_english() {
+    let config = config().translation("lo-en", "ສະບາຍດີ", "hello")
+                         .translation("en-lo", "Hi, how can I help?", "ສະບາຍດີ, ມີຫຍັງໃຫ້ຊ່ວຍບໍ?");
+    let server = MockServer::start(config).unwrap();
+    let result = run_turn(&server, json!({"message": "ສະບາຍດີ", "sourceLang": "lo", "targetLang": "th"}));
+    assert_eq!(result["status"], 200);
+    assert!(result.get("warnings").is_none(), "{}", result);
+    assert_eq!(result["result"]["output"]["generic"][0]["text"], "ສະບາຍດີ, ມີຫຍັງໃຫ້ຊ່ວຍບໍ?");
+    assert_eq!(result["pivots"], json!([
+        {"stage": "input_translation", "source": "lo", "pivot": "en", "target": "th"},
+        {"stage": "output_translation", "source": "th", "pivot": "en", "target": "lo"}
+    ]));
+    assert_eq!(server.requests(Endpoint::ListModels).len(), 1);
+}
+
+#[test]
+fn test_identify_source_language() {
+    let server = MockServer::start(config().language("สวัสดี", "th")).unwrap();
+    for params in [json!({"message": "สวัสดี", "targetLang": "en"}), json!({"message": "สวัสดี", "sourceLang": "auto", "targetLang": "en"})] {
+        let result = run_turn(&server, params);
+        assert_eq!(result["status"], 200);
+        assert_eq!(result["detectedLang"], "th");
+        assert_eq!(result["result"]["output"]["generic"][0]["text"], "สวัสดี มีอะไรให้ช่วยไหม");
+    }
+    // Thai script is enough, WLT isn't asked
+    assert!(server.requests(Endpoint::Identify).is_empty());
+}
+
+#[test]
+fn test_identify_latin_source_language() {
+    let server = MockServer::start(config().language("bom dia", "pt")
+                                           .translation("pt-en", "bom dia", "hello")
+                                           .translation("en-pt", "Hi, how can I help?", "Olá, como posso ajudar?")).unwrap();
+    let result = run_turn(&server, json!({"message": "bom dia", "targetLang": "en"}));
+    assert_eq!(result["status"], 200);
+    assert_eq!(result["detectedLang"], "pt");
+    assert_eq!(result["result"]["output"]["generic"][0]["text"], "Olá, como posso ajudar?");
+    assert_eq!(server.requests(Endpoint::Identify).len(), 1);
+}
+
+#[test]
+fn test_identify_failure() {
+    let server = MockServer::start(config()).unwrap();
+    server.inject(Endpoint::Identify, Fault::Status { status: 500 }, None);
+    let result = run_turn(&server, json!({"message": "covid", "targetLang": "en"}));
+    assert_eq!(result["status"], 200);
+    // offline detection is used instead
+    assert_eq!(result["detectedLang"], "en");
+    assert_eq!(result["warnings"][0]["stage"], "language_identification");
+    assert_eq!(server.requests(Endpoint::Identify).len(), 1);
+    assert!(server.requests(Endpoint::Translate).is_empty());
+}
+
+#[test]
+fn test_message_without_letter() {
+    let server = MockServer::start(config()).unwrap();
+    let result = run_turn(&server, json!({"message": "555", "sourceLang": "auto", "targetLang": "en"}));
+    assert_eq!(result["status"], 200);
+    assert!(result.get("detectedLang").is_none());
+    assert!(server.requests(Endpoint::Identify).is_empty());
+    assert!(server.requests(Endpoint::Translate).is_empty());
+}
+
+#[test]
+fn test_input_already_in_target_language() {
+    let server = MockServer::start(config()).unwrap();
+    let result = run_turn(&server, json!({"message": "hello", "sourceLang": "th", "targetLang": "en"}));
+    assert_eq!(result["status"], 200);
+    assert_eq!(result["result"]["output"]["generic"][0]["text"], "สวัสดี มีอะไรให้ช่วยไหม");
+    // only WA response is translated
+    let translations = server.requests(Endpoint::Translate);
+    assert_eq!(translations.len(), 1);
+    assert_eq!(serde_json::from_str::<Value>(&translations[0].body).unwrap()["model_id"], "en-th");
+}
+
+#[test]
+fn test_code_switched_input() {
+    let config = config().reply("covid today", "There are 10 new cases")
+                         .translation("th-en", "ยอด", "total")
+                         .translation("th-en", "วันนี้", "today");
+    let server = MockServer::start(config).unwrap();
+    let result = run_turn(&server, json!({"message": "ยอด covid วันนี้", "sourceLang": "th", "targetLang": "en"}));
+    assert_eq!(result["status"], 200);
+    assert_eq!(result["result"]["output"]["generic"][0]["text"], "There are 10 new cases");
+    let input: Value = serde_json::from_str(&server.requests(Endpoint::Translate)[0].body).unwrap();
+    assert_eq!(input["text"], json!(["ยอด", "วันนี้"]));
+    let message: Value = serde_json::from_str(&server.requests(Endpoint::Message)[0].body).unwrap();
+    assert_eq!(message["input"]["text"], "total covid today");
+}
+
+#[test]
+fn test_translation_cache_between_invocations() {
+    let server = MockServer::start(config()).unwrap();
+    let path = std::env::temp_dir().join(format!("gateway-cache-{}.json", std::process::id()));
+    let params = json!({"message": "สวัสดี", "sourceLang": "th", "targetLang": "en"}).to_string();
+    for _ in 0..2 {
+        let output = command(&server).env("WLT_CACHE", &path).arg(&params).output().expect("Fail to run gateway binary");
+        let result = parse_output(output);
+        assert_eq!(result["result"]["output"]["generic"][0]["text"], "สวัสดี มีอะไรให้ช่วยไหม");
+    }
+    let saved: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    std::fs::remove_file(&path).ok();
+    // WA response of second turn is served from cache, but user message is always translated and never saved
+    assert_eq!(server.requests(Endpoint::Translate).len(), 3);
+    assert!(saved.get("th-en").is_none(), "{}", saved);
+    assert!(saved.get("en-th").is_some(), "{}", saved);
+}
+
+#[test]
+fn test_glossary_in_both_directions() {
+    let config = config().reply("case count", "Covid cases today: 10")
+                         .translation("th-en", "[[0]]วันนี้", "[[0]] today")
+                         .translation("en-th", "[[0]] cases today: [[1]]", "ผู้ป่วย[[ 0 ]]วันนี้: [[1]]");
+    let server = MockServer::start(config).unwrap();
+    let output = command(&server).env("WLT_GLOSSARY", concat!(env!("CARGO_MANIFEST_DIR"), "/../wlt-dataset/th/glossary.csv"))
+                                 .arg(json!({"message": "ยอดโควิดวันนี้", "sourceLang": "th", "targetLang": "en"}).to_string())
+                                 .output()
+                                 .expect("Fail to run gateway binary");
+    let result = parse_output(output);
+    assert_eq!(result["status"], 200);
+    assert_eq!(result["result"]["output"]["generic"][0]["text"], "ผู้ป่วยโควิดวันนี้: 10");
+    let message: Value = serde_json::from_str(&server.requests(Endpoint::Message)[0].body).unwrap();
+    assert_eq!(message["input"]["text"], "Covid's case count today");
+}
+
+#[test]
+fn test_markup_is_kept_in_output() {
+    let config = config().reply("hotline", r#"Call <a href="tel:1422">hotline 1422</a> or visit https://ddc.moph.go.th 😷"#)
+                         .translation("th-en", "สายด่วน", "hotline")
+                         .translation("en-th", "Call", "โทร")
+                         .translation("en-th", "hotline [[0]]", "สายด่วน [[0]]")
+                         .translation("en-th", "or visit", "หรือเข้า");
+    let server = MockServer::start(config).unwrap();
+    let result = run_turn(&server, json!({"message": "สายด่วน", "sourceLang": "th", "targetLang": "en"}));
+    assert_eq!(result["status"], 200);
+    assert_eq!(result["result"]["output"]["generic"][0]["text"], r#"โทร <a href="tel:1422">สายด่วน 1422</a> หรือเข้า https://ddc.moph.go.th 😷"#);
+    let output: Value = serde_json::from_str(&server.requests(Endpoint::Translate)[1].body).unwrap();
+    assert_eq!(output["text"], json!(["Call", "hotline [[0]]", "or visit"]));
+}
+
+#[test]
+fn test_dropped_placeholder() {
+    let config = config().reply("hotline", "Call 1422 for $name")
+                         .translation("th-en", "สายด่วน", "hotline")
+                         .translation("en-th", "Call [[0]] for [[1]]", "โทรหา [[1]] [[1]]");
+    let server = MockServer::start(config).unwrap();
+    let result = run_turn(&server, json!({"message": "สายด่วน", "sourceLang": "th", "targetLang": "en"}));
+    assert_eq!(result["status"], 200);
+    // hotline is appended rather than lost
+    assert_eq!(result["result"]["output"]["generic"][0]["text"], "โทรหา $name $name 1422");
+    assert_eq!(result["warnings"][0]["code"], "translation_mismatch");
+    assert_eq!(result["warnings"][0]["stage"], "output_translation");
+    assert_eq!(result["warnings"][0]["message"], "WLT dropped 1 and duplicated 1 of 2 placeholders");
+}
+
+#[test]
+fn test_no_translate_markers() {
+    let config = config().dialog("hotline", vec![
+                             json!({"response_type": "text", "text": "Call <notranslate>DDC hotline</notranslate> now"}),
+                             json!({"response_type": "text", "text": "Department of Disease Control", "user_defined": {"notranslate": true}})
+                         ])
+                         .translation("th-en", "สายด่วน", "hotline")
+                         .translation("en-th", "Call", "โทร")
+                         .translation("en-th", "now", "เลย");
+    let server = MockServer::start(config).unwrap();
+    let result = run_turn(&server, json!({"message": "สายด่วน", "sourceLang": "th", "targetLang": "en"}));
+    assert_eq!(result["status"], 200);
+    assert_eq!(result["result"]["output"]["generic"][0]["text"], "โทร DDC hotline เลย");
+    assert_eq!(result["result"]["output"]["generic"][1]["text"], "Department of Disease Control");
+    let output: Value = serde_json::from_str(&server.requests(Endpoint::Translate)[1].body).unwrap();
+    assert_eq!(output["text"], json!(["Call", "now"]));
+}
+
+#[test]
+fn test_no_translate_markers_without_translation() {
+    let config = config().reply("ddc", "Hi from <NoTranslate>DDC</NoTranslate>");
+    let server = MockServer::start(config).unwrap();
+    let result = run_turn(&server, json!({"message": "ddc", "sourceLang": "en", "targetLang": "en"}));
+    assert_eq!(result["result"]["output"]["generic"][0]["text"], "Hi from DDC");
+}
+
+/// Dialog with text, option and flagged response, and translations of them.
+fn fallback_config() -> MockConfig {
+    config().dialog("symptom", vec![
+                json!({"response_type": "text", "text": "Stay home"}),
+                json!({"response_type": "option", "title": "Pick one", "options": [{"label": "Fever", "value": {"input": {"text": "fever"}}}]}),
+                json!({"response_type": "text", "text": "Department of Disease Control", "user_defined": {"notranslate": true}})
+            ])
+            .translation("en-th", "Stay home", "อยู่บ้าน")
+            .translation("en-th", "Pick one", "เลือก")
+            .translation("en-th", "Fever", "ไข้")
+}
+
+fn translation_statuses(result: &Value) -> Vec<(&str, &str)> {
+    result["translations"].as_array().unwrap().iter().map(|t| (t["field"].as_str().unwrap(), t["status"].as_str().unwrap())).collect()
+}
+
+#[test]
+fn test_output_translation_fallback() {
+    let server = MockServer::start(fallback_config()).unwrap();
+    // the batch lose a translation, each text is then translated on its own
+    server.inject(Endpoint::Translate, Fault::PartialTranslation { missing: 1 }, Some(1));
+    let result = run_turn(&server, json!({"message": "symptom", "sourceLang": "th", "targetLang": "en"}));
+    assert_eq!(result["status"], 200);
+    assert_eq!(translation_statuses(&result), vec![
+        ("output.generic[0].text", "fallback"),
+        ("output.generic[1].title", "fallback"),
+        ("output.generic[1].options[0].label", "fallback"),
+        ("output.generic[2].text", "original")
+    ]);
+    let generic = &result["result"]["output"]["generic"];
+    assert_eq!(generic[0]["text"], "อยู่บ้าน");
+    assert_eq!(generic[1]["title"], "เลือก");
+    assert_eq!(generic[1]["options"][0]["label"], "ไข้");
+    assert_eq!(generic[2]["text"], "Department of Disease Control");
+    assert_eq!(result["warnings"][0]["code"], "translation_mismatch");
+    assert_eq!(server.requests(Endpoint::Translate).len(), 4);
+}
+
+#[test]
+fn test_output_translation_status() {
+    let server = MockServer::start(fallback_config()).unwrap();
+    let result = run_turn(&server, json!({"message": "symptom", "sourceLang": "th", "targetLang": "en"}));
+    assert_eq!(translation_statuses(&result)[..2], [("output.generic[0].text", "translated"), ("output.generic[1].title", "translated")]);
+    assert!(result.get("warnings").is_none());
+
+    // text that cannot be translated even on its own is kept as WA return it
+    server.inject(Endpoint::Translate, Fault::PartialTranslation { missing: 1 }, None);
+    let result = run_turn(&server, json!({"message": "symptom", "sourceLang": "th", "targetLang": "en"}));
+    assert!(translation_statuses(&result).iter().all(|(_, status)| *status == "original"));
+    assert_eq!(result["result"]["output"]["generic"][0]["text"], "Stay home");
+    assert_eq!(result["warnings"].as_array().map(|w| w.len()), Some(1));
+}
+
+#[test]
+fn test_every_response_type_is_translated() {
+    let config = config().dialog("quarantine", vec![
+                             json!({"response_type": "image", "source": "https://ddc.moph.go.th/mask.png", "title": "Wear mask"}),
+                             json!({"response_type": "connect_to_agent", "message_to_human_agent": "Ask about test"}),
+                             json!({
+                                 "response_type": "search",
+                                 "header": "I found this",
+                                 "results": [{
+                                     "id": "1",
+                                     "result_metadata": {"confidence": 0.9, "score": 1.5},
+                                     "title": "Quarantine",
+                                     "body": "Stay home",
+                                     "highlight": {"body": ["Stay <em>home</em>"]}
+                                 }]
+                             })
+                         ])
+                         .translation("th-en", "กักตัว", "quarantine")
+                         .translation("en-th", "Wear mask", "สวมหน้ากาก")
+                         .translation("en-th", "Ask about test", "สอบถามเรื่องการตรวจ")
+                         .translation("en-th", "I found this", "พบข้อมูลนี้")
+                         .translation("en-th", "Quarantine", "กักตัว")
+                         .translation("en-th", "Stay home", "อยู่บ้าน")
+                         .translation("en-th", "Stay", "อยู่")
+                         .translation("en-th", "home", "บ้าน");
+    let server = MockServer::start(config).unwrap();
+    let result = run_turn(&server, json!({"message": "กักตัว", "sourceLang": "th", "targetLang": "en"}));
+    assert_eq!(result["status"], 200);
+    let generic = &result["result"]["output"]["generic"];
+    assert_eq!(generic[0]["title"], "สวมหน้ากาก");
+    assert_eq!(generic[0]["source"], "https://ddc.moph.go.th/mask.png");
+    assert_eq!(generic[1]["message_to_human_agent"], "สอบถามเรื่องการตรวจ");
+    assert_eq!(generic[2]["header"], "พบข้อมูลนี้");
+    assert_eq!(generic[2]["results"][0]["title"], "กักตัว");
+    assert_eq!(generic[2]["results"][0]["body"], "อยู่บ้าน");
+    assert_eq!(generic[2]["results"][0]["highlight"]["body"][0], "อยู่ <em>บ้าน</em>");
+    assert!(translation_statuses(&result).iter().all(|(_, status)| *status == "translated"));
+}
+
+#[test]
+fn test_expired_session_is_renewed() {
+    let server = MockServer::start(config()).unwrap();
+    let result = run_turn(&server, json!({"message": "hello", "sessionId": "gone", "sourceLang": "en", "targetLang": "en"}));
+    assert_eq!(result["status"], 200);
+    assert_eq!(result["sessionId"], "mock-session-1");
+    assert_eq!(result["result"]["output"]["generic"][0]["text"], "Hi, how can I help?");
+}
+
+#[test]
+fn test_input_translation_failure() {
+    let server = MockServer::start(config()).unwrap();
+    server.inject(Endpoint::Translate, Fault::Status { status: 500 }, Some(1));
+    let result = run_turn(&server, json!({"message": "สวัสดี", "sourceLang": "th", "targetLang": "en"}));
+    // untranslated input doesn't match any dialog rule
+    assert_eq!(result["status"], 200);
+    assert_eq!(result["result"]["output"]["generic"].as_array().map(|g| g.len()), Some(0));
+    assert_eq!(result["warnings"][0]["code"], "upstream_error");
+    assert_eq!(result["warnings"][0]["stage"], "input_translation");
+}
+
+#[test]
+fn test_session_failure_envelope() {
+    let server = MockServer::start(config()).unwrap();
+    server.inject(Endpoint::CreateSession, Fault::Status { status: 500 }, None);
+    let result = run_turn(&server, json!({"message": "hello", "sourceLang": "en", "targetLang": "en"}));
+    assert_eq!(result["status"], 502);
+    assert_eq!(result["code"], "upstream_error");
+    assert_eq!(result["stage"], "session");
+    assert!(result.get("sessionId").is_none());
+}
+
+#[test]
+fn test_message_failure_envelope() {
+    let server = MockServer::start(config()).unwrap();
+    server.inject(Endpoint::Message, Fault::Malformed, None);
+    let result = run_turn(&server, json!({"message": "hello", "sourceLang": "en", "targetLang": "en"}));
+    assert_eq!(result["status"], 502);
+    assert_eq!(result["code"], "upstream_malformed");
+    assert_eq!(result["stage"], "message");
+    assert_eq!(result["sessionId"], "mock-session-1");
+}
+
+#[test]
+fn test_output_translation_warning() {
+    let server = MockServer::start(config().reply("สวัสดี", "Hi, how can I help?")).unwrap();
+    server.inject(Endpoint::Translate, Fault::PartialTranslation { missing: 1 }, None);
+    let result = run_turn(&server, json!({"message": "สวัสดี", "sourceLang": "th", "targetLang": "en"}));
+    assert_eq!(result["status"], 200);
+    let stages: Vec<&str> = result["warnings"].as_array().unwrap().iter().map(|w| w["stage"].as_str().unwrap()).collect();
+    assert_eq!(stages, vec!["input_translation", "output_translation"]);
+}
+
+#[test]
+fn test_invalid_request_envelope() {
+    let server = MockServer::start(config()).unwrap();
+    let result = run_turn(&server, json!({"sourceLang": "th"}));
+    assert_eq!(result["status"], 400);
+    assert_eq!(result["code"], "invalid_request");
+    assert_eq!(result["stage"], "request");
+    assert!(server.requests(Endpoint::Identify).is_empty());
+}
+
+#[test]
+fn test_invalid_language_code() {
+    let server = MockServer::start(config()).unwrap();
+    let result = run_turn(&server, json!({"message": "hello", "sourceLang": "thai", "targetLang": "en"}));
+    assert_eq!(result["status"], 400);
+    assert_eq!(result["code"], "invalid_request");
+    assert!(result["message"].as_str().unwrap().contains("sourceLang"));
+    assert!(server.requests(Endpoint::Translate).is_empty());
+}
+
+#[test]
+fn test_every_config_problem_reported() {
+    let server = MockServer::start(config()).unwrap();
+    let output = command(&server).env_remove("WA_ID")
+                                 .env("WLT_ENDPOINT", "wlt.example")
+                                 .env("TURN_TIMEOUT", "soon")
+                                 .arg(json!({"message": "hello", "sourceLang": "en", "targetLang": "en"}).to_string())
+                                 .output()
+                                 .expect("Fail to run gateway binary");
+    let result = parse_output(output);
+    assert_eq!(result["status"], 500);
+    assert_eq!(result["code"], "config_error");
+    assert_eq!(result["stage"], "config");
+    let message = result["message"].as_str().unwrap();
+    for setting in ["WA_ID", "WLT_ENDPOINT", "TURN_TIMEOUT"] {
+        assert!(message.contains(setting), "{} not reported in {}", setting, message);
+    }
+    assert!(server.requests(Endpoint::CreateSession).is_empty());
+}
+
+#[test]
+fn test_structured_log() {
+    let server = MockServer::start(config()).unwrap();
+    let output = gateway(&server, json!({"message": "สวัสดี", "sourceLang": "th", "targetLang": "en"}));
+    let records: Vec<Value> = String::from_utf8(output.stderr).unwrap()
+                                    .lines()
+                                    .map(|l| serde_json::from_str(l).unwrap_or_else(|e| panic!("Log is not JSON: {} ({})", l, e)))
+                                    .collect();
+    let correlation_id = &records[0]["correlation_id"];
+    assert!(correlation_id.is_string());
+    assert!(records.iter().all(|r| &r["correlation_id"] == correlation_id));
+    let input = records.iter().find(|r| r["stage"] == "input_translation").expect("Missing input translation log");
+    assert!(input["latency_ms"].is_u64());
+    assert_eq!(input["text"], "<redacted 6 chars>");
+    // user text never appear in log unless debug is enabled
+    assert!(!records.iter().any(|r| r.to_string().contains("สวัสดี")));
+}
@@ -0,0 +1,64 @@
+//! Run the whole gateway flow against recorded Watson interactions.
+//!
+//! Each cassette in `tests/cassettes` is replayed by the binary through `HTTP_CASSETTE`
+//! so the test is deterministic and doesn't need any credential.
+//! The cassettes are hand-made rather than recorded from live Watson. Request body is written
+//! as the gateway send it, since replay match on body too.
+
+use serde_json::{json, Value};
+use std::process::Command;
+
+fn run_turn(cassette: &str, params: Value) -> Value {
+    let dir = std::env::temp_dir().join(format!("gateway-replay-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).expect("Fail to create working directory");
+    let mut command = Command::new(env!("CARGO_BIN_EXE_covid-unified-gateway"));
+    // only the settings below, so local `.env`, `gateway.toml` or environment doesn't change the replay
+    command.env_clear();
+    if let Some(path) = std::env::var_os("PATH") {
+        command.env("PATH", path);
+    }
+    let output = command
+                        .current_dir(dir)
+                        .env("HTTP_CASSETTE_MODE", "replay")
+                        .env("HTTP_CASSETTE", format!("{}/tests/cassettes/{}", env!("CARGO_MANIFEST_DIR"), cassette))
+                        .env("WLT_APIKEY", "dummy")
+                        .env("WLT_ENDPOINT", "https://wlt.cassette")
+                        .env("WLT_VERSION", "2018-05-01")
+                        .env("WA_APIKEY", "dummy")
+                        .env("WA_ID", "assistant")
+                        .env("WA_ENDPOINT", "https://wa.cassette")
+                        .env("WA_VERSION", "2020-04-01")
+                        .arg(params.to_string())
+                        .output()
+                        .expect("Fail to run gateway binary");
+    assert!(output.status.success(), "Gateway exit with {:?}", output.status);
+    let stdout = String::from_utf8(output.stdout).expect("Output shall be UTF-8");
+    // logs go to stderr so stdout has only the result
+    assert_eq!(stdout.lines().count(), 1, "Unexpected output {}", stdout);
+    serde_json::from_str(&stdout).unwrap_or_else(|e| panic!("Output is not JSON: {} ({})", stdout, e))
+}
+
+#[test]
+fn test_replay_translated_turn() {
+    let result = run_turn("th_en_turn.json", json!({
+        "message": "สวัสดี",
+        "sourceLang": "th",
+        "targetLang": "en"
+    }));
+    assert_eq!(result["status"], 200);
+    assert_eq!(result["sessionId"], "cassette-session");
+    assert_eq!(result["result"]["output"]["generic"][0]["text"], "สวัสดี มีอะไรให้ช่วยไหม");
+}
+
+#[test]
+fn test_replay_expired_session() {
+    let result = run_turn("expired_session.json", json!({
+        "message": "hello",
+        "sessionId": "old-session",
+        "sourceLang": "en",
+        "targetLang": "en"
+    }));
+    assert_eq!(result["status"], 200);
+    assert_eq!(result["sessionId"], "renewed-session");
+    assert_eq!(result["result"]["output"]["generic"][0]["text"], "Welcome back");
+}